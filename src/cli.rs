@@ -1,4 +1,13 @@
+use super::config::{parse_time_of_day, Preset, Session, SessionKind};
+use super::pomodoro_clock::check_duration_fmt;
+use super::pomodoro_clock::Adjustment;
+use super::socket::Protocol;
+
+use chrono::NaiveTime;
+
+use std::net::SocketAddr;
 use std::path::PathBuf;
+use std::time::Duration;
 
 
 #[derive(clap::Parser, Debug)]
@@ -9,31 +18,614 @@ pub struct Cli {
 
     #[arg(long = "config")]
     pub config_path: Option<PathBuf>,
+
+    /// Print nothing but errors and what was asked for, e.g. the output of `fetch`
+    #[arg(short, long, global = true)]
+    pub quiet: bool,
+
+    /// Log what's going on to stderr, `-v` for info, `-vv` for debug and `-vvv` for trace,
+    /// while `RUST_LOG` sets levels by module, e.g. `pomidoro::socket=trace`
+    #[arg(short, long, action = clap::ArgAction::Count, global = true)]
+    pub verbose: u8,
+
+    /// Color the output of `status` and `watch`, or the `{{kind_color}}` of templates,
+    /// `auto` unless `$NO_COLOR` is set or the output isn't a terminal
+    #[arg(long, value_name = "WHEN", default_value = "auto", global = true)]
+    pub color: ColorChoice,
 }
 
 #[derive(clap::Subcommand, Debug)]
 pub enum Command {
     Start {
-        #[arg(long = "id", default_value_t = 0)]
-        server_id: u32,
+        /// Number or name of the server, `$POMIDORO_ID` when it's set
+        #[arg(long = "id", default_value_t = default_server_id(), value_parser = parse_server_id)]
+        server_id: String,
+
+        /// Socket file of the server, in place of the one in `socket_dir` named after its id
+        #[arg(long = "socket", conflicts_with = "server_id")]
+        socket_path: Option<PathBuf>,
+
+        /// Start with the clock running instead of paused
+        #[arg(long)]
+        running: bool,
+
+        /// Replace the socket of a server already running with the same id
+        #[arg(long)]
+        force: bool,
+
+        /// Stop a server already running with the same id, and carry on from where it was
+        #[arg(long, conflicts_with = "force")]
+        takeover: bool,
+
+        /// Write the server's process id here instead of next to its socket,
+        /// the only place it's written for abstract sockets
+        #[arg(long)]
+        pid_file: Option<PathBuf>,
+
+        /// Run in the background, detached from the terminal, once the server is listening
+        #[arg(long)]
+        daemon: bool,
+
+        /// Where the output of the daemon goes, instead of next to its socket
+        #[arg(long)]
+        log_file: Option<PathBuf>,
+
+        /// Also accept requests over TCP at this address, e.g. `127.0.0.1:7878`,
+        /// unencrypted so best kept to loopback or a tunnel
+        #[arg(long)]
+        listen: Option<SocketAddr>,
+
+        /// Serve `GET /state`, `POST /toggle`, `/pause`, `/resume`, `/skip` and `/reset` over HTTP
+        /// at this address, e.g. `127.0.0.1:8899`, unencrypted like `--listen`
+        #[arg(long)]
+        http: Option<SocketAddr>,
+
+        /// Export the main timer as `org.pomidoro.Timer1` on the session bus,
+        /// with `Toggle`, `Pause`, `Resume`, `Skip`, `Reset` and `Fetch` methods and a `State` property
+        #[arg(long)]
+        dbus: bool,
+
+        /// Make time pass this many times faster, e.g. for testing integrations
+        #[arg(long, default_value_t = 1.0, value_parser = parse_speed)]
+        speed: f64,
+
+        /// Stop the clock after this many full cycles
+        #[arg(long)]
+        cycles: Option<u32>,
+
+        /// Run a single countdown instead of the session cycle, then stop the server
+        #[arg(long, value_parser = humantime::parse_duration, conflicts_with = "cycles")]
+        timer: Option<Duration>,
+
+        /// Run a single countdown until the given local time (`hh:mm[:ss]`),
+        /// then stop the server
+        #[arg(long, value_parser = parse_time_of_day, conflicts_with_all = ["cycles", "timer"])]
+        until: Option<NaiveTime>,
+
+        /// Run these sessions instead of the configured ones, as `name:duration[:kind][:time_format]`,
+        /// e.g. `work:25m,rest:5m:break,work:25m,long:15m:long-break`, sessions being work
+        /// unless they're given another kind, like in the config
+        #[arg(long, value_delimiter = ',', value_parser = parse_session, conflicts_with_all = ["timer", "until"])]
+        sessions: Vec<Session>,
+
+        /// Run built-in sessions instead of the configured ones
+        #[arg(long, value_enum, conflicts_with_all = ["timer", "until", "sessions"])]
+        preset: Option<Preset>,
     },
     Send {
-        #[arg(long = "id", default_value_t = 0)]
-        server_id: u32,
+        /// Number or name of the server, `$POMIDORO_ID` when it's set
+        #[arg(long = "id", default_value_t = default_server_id(), value_parser = parse_server_id)]
+        server_id: String,
+
+        /// Socket file of the server, in place of the one in `socket_dir` named after its id
+        #[arg(long = "socket", conflicts_with_all = ["server_id", "host"])]
+        socket_path: Option<PathBuf>,
+
+        /// Reach the server over TCP at this address instead of its Unix socket
+        #[arg(long, conflicts_with = "server_id")]
+        host: Option<String>,
+
+        /// Address one of the server's named timers instead of its main one,
+        /// started paused on first use and removed by `stop`
+        #[arg(long)]
+        timer: Option<String>,
+
+        /// Send the request to every running server, one after another
+        #[arg(long, conflicts_with_all = ["server_id", "host", "socket_path"])]
+        all: bool,
+
+        /// Encoding to talk to the server in, overrides the config
+        #[arg(long, value_enum)]
+        protocol: Option<Protocol>,
+
+        /// Print responses as JSON instead of rendering templates, states
+        /// on their own and anything else as the whole response
+        #[arg(long)]
+        json: bool,
+
+        /// Fetch the state once the request is done and print it with this template,
+        /// e.g. `send toggle --then-fetch '{{time}}'` for a click handler
+        #[arg(long, global = true, value_parser = mustache::compile_str)]
+        then_fetch: Option<mustache::Template>,
 
         #[command(subcommand)]
         request: Request,
     },
+    /// Print a summary of the state of a server's main timer
+    Status {
+        /// Number or name of the server, `$POMIDORO_ID` when it's set
+        #[arg(long = "id", default_value_t = default_server_id(), value_parser = parse_server_id)]
+        server_id: String,
+
+        /// Print a table of every server with a socket instead
+        #[arg(long, conflicts_with = "server_id")]
+        all: bool,
+
+        /// Print the state as JSON, or an object of the states by server id
+        /// with `--all`, `null` for the ones not responding
+        #[arg(long)]
+        json: bool,
+    },
+    /// Fetch the state of a server's main timer every second, redrawing it in place
+    Watch {
+        /// Number or name of the server, `$POMIDORO_ID` when it's set
+        #[arg(long = "id", default_value_t = default_server_id(), value_parser = parse_server_id)]
+        server_id: String,
+
+        /// Print a line every second instead of redrawing, e.g. for logs
+        #[arg(long)]
+        no_clear: bool,
+
+        /// Same as for `send fetch`, the summary printed by `status` by default
+        #[arg(value_parser = mustache::compile_str)]
+        template: Option<mustache::Template>,
+    },
+    /// Show a full-screen dashboard of servers, with keys to toggle, skip and reset their clocks
+    Tui {
+        /// Number or name of a server, once for each to show, `$POMIDORO_ID` when none is given
+        #[arg(long = "id", value_parser = parse_server_id)]
+        server_ids: Vec<String>,
+
+        /// Show every server with a socket instead
+        #[arg(long, conflicts_with = "server_ids")]
+        all: bool,
+    },
+    /// Show a server's session and time left in the system tray, with a menu to toggle,
+    /// skip and reset its clock
+    Tray {
+        /// Number or name of the server, `$POMIDORO_ID` when it's set
+        #[arg(long = "id", default_value_t = default_server_id(), value_parser = parse_server_id)]
+        server_id: String,
+
+        /// Same as for `send fetch`, for the tooltip, the summary printed by `status` by default
+        #[arg(value_parser = mustache::compile_str)]
+        template: Option<mustache::Template>,
+    },
+    /// Exit with status 0 if the server answers a ping, 1 otherwise, printing nothing
+    IsRunning {
+        /// Number or name of the server, `$POMIDORO_ID` when it's set
+        #[arg(long = "id", default_value_t = default_server_id(), value_parser = parse_server_id)]
+        server_id: String,
+    },
+    /// Print a server's version, PID, config file, socket, hash of its sessions and uptime
+    Info {
+        /// Number or name of the server, `$POMIDORO_ID` when it's set
+        #[arg(long = "id", default_value_t = default_server_id(), value_parser = parse_server_id)]
+        server_id: String,
+
+        /// Print the information as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Print the sessions a server runs, edits included, marking the one its cycle is at
+    Sessions {
+        /// Number or name of the server, `$POMIDORO_ID` when it's set
+        #[arg(long = "id", default_value_t = default_server_id(), value_parser = parse_server_id)]
+        server_id: String,
+
+        /// Print the sessions as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Write a commented config to `$XDG_CONFIG_HOME/pomidoro/config.toml`,
+    /// or to the path given with `--config`
+    Init {
+        /// Overwrite the config file if there's one already
+        #[arg(long)]
+        force: bool,
+    },
+    /// Ask for the lengths of sessions and a few preferences, then write them
+    /// to the config file, like `init` does
+    Setup {
+        /// Overwrite the config file if there's one already
+        #[arg(long)]
+        force: bool,
+    },
+    /// Report every problem with the config file, exiting with status 1 if there are any
+    Check,
+    /// Inspect the configuration
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+    /// Print the sessions of the next few cycles with their local start and end times,
+    /// as if the clock ran without pausing
+    Schedule {
+        /// Local time the first session starts at, `hh:mm[:ss]` or `now`
+        #[arg(long, default_value = "now", value_parser = parse_start_time)]
+        from: NaiveTime,
+
+        /// Number of cycles to print, fewer if the config runs fewer
+        #[arg(long, default_value_t = 2)]
+        cycles: u32,
+    },
+    /// Print every server with a socket, whether it responds, and its session
+    List,
+    /// Stop a server, or every server with a socket after asking
+    Stop {
+        /// Number or name of the server, `$POMIDORO_ID` when it's set
+        #[arg(long = "id", default_value_t = default_server_id(), value_parser = parse_server_id)]
+        server_id: String,
+
+        /// Stop every server with a socket, removing the files of the ones that are gone,
+        /// once the list of them is confirmed
+        #[arg(long, conflicts_with = "server_id")]
+        all: bool,
+
+        /// Don't ask before stopping every server
+        #[arg(long, short, requires = "all")]
+        yes: bool,
+    },
+    /// Same as `stop --all`, kept for the scripts that came before it
+    #[command(hide = true)]
+    StopAll {
+        /// Don't ask before stopping every server
+        #[arg(long, short)]
+        yes: bool,
+    },
+    #[command(flatten)]
+    Shortcut(Shortcut),
+}
+
+#[derive(clap::Subcommand, Debug)]
+pub enum ConfigCommand {
+    /// Print the configuration in effect as TOML, with the defaults filled in
+    Dump,
+    /// Print the path of the config file in use, if any
+    Path,
+}
+
+/// Requests sent to the main timer of server `$POMIDORO_ID`, or 0, without going
+/// through `send`, e.g. for keybindings
+#[derive(clap::Subcommand, Debug)]
+pub enum Shortcut {
+    /// Same as `send fetch`
+    Fetch(FetchArgs),
+    /// Same as `send toggle`
+    Toggle,
+    /// Same as `send skip`
+    Skip {
+        /// Fast-forward to the next session with this name
+        #[arg(long, conflicts_with = "count")]
+        to: Option<String>,
+
+        /// Number of sessions to skip
+        #[arg(short = 'n', default_value_t = 1)]
+        count: u32,
+    },
+    /// Same as `send reset`
+    Reset {
+        /// Don't ask first, even from a terminal
+        #[arg(long, visible_alias = "yes")]
+        force: bool,
+    },
+}
+
+impl From<Shortcut> for Request {
+    fn from(shortcut: Shortcut) -> Self {
+        match shortcut {
+            Shortcut::Fetch(args) => Self::Fetch(args),
+            Shortcut::Toggle => Self::Toggle,
+            Shortcut::Skip { to, count } => Self::Skip { to, count },
+            Shortcut::Reset { force } => Self::Reset { force },
+        }
+    }
+}
+
+/// Arguments of `send fetch` and its shortcut
+#[derive(clap::Args, Debug, Default)]
+pub struct FetchArgs {
+    /// Mustache template of the state, `default_template` from the config if left out
+    #[arg(value_parser = mustache::compile_str)]
+    pub template: Option<mustache::Template>,
+
+    /// Render the template of this name from the config's `templates`
+    #[arg(long, conflicts_with = "template")]
+    pub template_name: Option<String>,
+
+    /// Print these variables of the templates, tab-separated, instead of rendering one,
+    /// e.g. `time,percent,session`
+    #[arg(long, value_delimiter = ',', conflicts_with_all = ["template", "template_name"])]
+    pub fields: Option<Vec<String>>,
+
+    /// Keep printing the state every time the rendered template changes,
+    /// pushed by the server unless polled with `--interval`
+    #[arg(long)]
+    pub follow: bool,
+
+    /// Fetch the state this often instead of waiting for the server to push it
+    #[arg(long, value_parser = humantime::parse_duration, requires = "follow")]
+    pub interval: Option<Duration>,
+
+    /// Exit with status 10 if the clock is paused or finished, 11 if it's running
+    /// a break, 0 if it's running any other session
+    #[arg(long, conflicts_with = "follow")]
+    pub exit_status: bool,
 }
 
 #[derive(clap::Subcommand, Debug)]
 pub enum Request {
-    Fetch {
+    /// Print the state, rendered with a template
+    Fetch(FetchArgs),
+    /// Print the state every time the session or the clock changes
+    Subscribe {
+        /// Mustache template of the state, `default_template` from the config if left out
         #[arg(value_parser = mustache::compile_str)]
-        template: mustache::Template,
+        template: Option<mustache::Template>,
+
+        /// Render the template of this name from the config's `templates`
+        #[arg(long, conflicts_with = "template")]
+        template_name: Option<String>,
     },
     Toggle,
-    Skip,
-    Reset,
+    /// Pause the clock, doing nothing if it's paused already
+    Pause,
+    /// Resume the clock, doing nothing if it's running already
+    Resume,
+    Skip {
+        /// Fast-forward to the next session with this name
+        #[arg(long, conflicts_with = "count")]
+        to: Option<String>,
+
+        /// Number of sessions to skip
+        #[arg(short = 'n', default_value_t = 1)]
+        count: u32,
+    },
+    /// Rewind to the start of the previous session, or of the current one
+    /// if it's been running for more than a few seconds
+    Back,
+    /// Restart the current session without touching the rest of the cycle
+    RestartSession,
+    /// Extend (`+5m`) or shorten (`-2m`) the current session
+    Adjust {
+        #[arg(allow_hyphen_values = true)]
+        adjustment: Adjustment,
+    },
+    /// Postpone the transition to the next session
+    Snooze {
+        #[arg(value_parser = humantime::parse_duration)]
+        duration: Duration,
+    },
+    /// Run a one-off session right after the current one
+    Queue {
+        #[arg(long)]
+        name: String,
+
+        #[arg(long, value_parser = humantime::parse_duration)]
+        duration: Duration,
+    },
+    /// Suspend the cycle with an interrupt session, e.g. for a meeting
+    Interrupt {
+        #[arg(required_unless_present = "end")]
+        name: Option<String>,
+
+        /// Count down from this duration instead of counting up
+        #[arg(long, value_parser = humantime::parse_duration)]
+        duration: Option<Duration>,
+
+        /// Resume the cycle where it was interrupted
+        #[arg(long, conflicts_with_all = ["name", "duration"])]
+        end: bool,
+    },
+    /// Record an interruption against the current work session
+    #[command(group(clap::ArgGroup::new("source").required(true)))]
+    Interruption {
+        /// Distraction coming from within, e.g. checking the phone
+        #[arg(long, group = "source")]
+        internal: bool,
+
+        /// Distraction coming from others, e.g. a colleague's question
+        #[arg(long, group = "source")]
+        external: bool,
+    },
+    /// Set the time elapsed within the current session
+    Seek {
+        /// `[[hh:]mm:]ss` or a human readable duration, e.g. `12:30` or `12m 30s`
+        #[arg(value_parser = parse_elapsed)]
+        elapsed: Duration,
+
+        /// Seek within the whole cycle instead of the current session
+        #[arg(long = "cycle")]
+        within_cycle: bool,
+    },
+    /// Go back to the start of the cycle, asking first when run from a terminal,
+    /// and print the progress thrown away
+    Reset {
+        /// Don't ask first, even from a terminal
+        #[arg(long, visible_alias = "yes")]
+        force: bool,
+    },
+    /// Revert the most recent toggle, skip, adjustment, reset, etc.
+    Undo,
     Stop,
+    /// Check that the server is alive, exiting with status 3 if it isn't
+    Ping,
+    /// Automatically skip sessions marked as breaks
+    AutoSkip {
+        switch: Switch,
+    },
+    /// Change the sessions of the cycle until the server stops, staying in the current one
+    Sessions {
+        #[command(subcommand)]
+        edit: SessionsEdit,
+    },
+}
+
+#[derive(clap::Subcommand, Debug)]
+pub enum SessionsEdit {
+    /// Add a session at the end of the cycle, or after every session with a name
+    Add {
+        /// `name:duration[:kind][:time_format]`, written like sessions of `start --sessions`
+        #[arg(value_parser = parse_session)]
+        session: Session,
+
+        /// Add it after every session with this name instead
+        #[arg(long)]
+        after: Option<String>,
+    },
+    /// Remove every session with the name, unless the cycle is at one of them
+    Rm {
+        name: String,
+    },
+    /// Change every session with the name
+    #[command(group(clap::ArgGroup::new("change").required(true).multiple(true)))]
+    Set {
+        name: String,
+
+        #[arg(long, value_parser = humantime::parse_duration, group = "change")]
+        duration: Option<Duration>,
+
+        #[arg(long, value_enum, group = "change")]
+        kind: Option<SessionKind>,
+    },
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+pub enum ColorChoice {
+    Auto,
+    Always,
+    Never,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+pub enum Switch {
+    On,
+    Off,
+}
+
+impl From<Switch> for bool {
+    fn from(switch: Switch) -> Self {
+        matches!(switch, Switch::On)
+    }
+}
+
+fn parse_elapsed(text: &str) -> Result<Duration, String> {
+    if !text.contains(':') {
+        return humantime::parse_duration(text).map_err(|err| err.to_string());
+    }
+    let parts = text.split(':').collect::<Vec<_>>();
+    if parts.len() > 3 {
+        return Err(format!("Expected at most 3 colon separated parts, got {}", parts.len()));
+    }
+    parts
+        .into_iter()
+        .try_fold(0u64, |secs, part| {
+            let value = part.parse::<u64>().map_err(|err| err.to_string())?;
+            secs.checked_mul(60)
+                .and_then(|secs| secs.checked_add(value))
+                .ok_or_else(|| "too large".to_string())
+        })
+        .map(Duration::from_secs)
+        .map_err(|err| format!("Invalid time '{text}': {err}"))
+}
+
+fn parse_start_time(text: &str) -> Result<NaiveTime, chrono::ParseError> {
+    match text {
+        "now" => Ok(chrono::Local::now().time()),
+        text => parse_time_of_day(text),
+    }
+}
+
+fn parse_session(text: &str) -> Result<Session, String> {
+    let mut parts = text.splitn(3, ':');
+    let (Some(name), Some(duration)) = (parts.next().filter(|name| !name.is_empty()), parts.next()) else {
+        return Err(format!("Expected '<name>:<duration>[:<kind>][:<time format>]', got '{text}'"));
+    };
+    let duration = humantime::parse_duration(duration).map_err(|err| format!("Invalid duration '{duration}': {err}"))?;
+    // time formats have colons of their own, but no kind's name is one
+    let (kind, time_format) = match parts.next() {
+        None => (SessionKind::Work, None),
+        Some(rest) => {
+            let (first, after) = rest.split_once(':').map_or((rest, None), |(first, after)| (first, Some(after)));
+            match <SessionKind as clap::ValueEnum>::from_str(first, false) {
+                Ok(kind) => (kind, after),
+                Err(_) => (SessionKind::Work, Some(rest)),
+            }
+        },
+    };
+    let time_format = time_format.map(String::from);
+    if let Some(time_format) = &time_format {
+        check_duration_fmt(time_format).map_err(|err| format!("Invalid time format '{time_format}': {err}"))?;
+    }
+    Ok(Session {
+        name: name.into(),
+        duration: Some(duration),
+        end_at: None,
+        time_format,
+        repeat: 1,
+        kind,
+        pause_on_start: false,
+        checkpoint_every: None,
+    })
+}
+
+/// `$POMIDORO_ID`, or 0
+pub fn default_server_id() -> String {
+    std::env::var("POMIDORO_ID")
+        .ok()
+        .filter(|server_id| !server_id.is_empty())
+        .unwrap_or_else(|| "0".into())
+}
+
+fn parse_server_id(text: &str) -> Result<String, String> {
+    if text.is_empty() {
+        Err("Server id can't be empty".into())
+    } else {
+        Ok(text.into())
+    }
+}
+
+fn parse_speed(text: &str) -> Result<f64, String> {
+    let speed: f64 = text.parse().map_err(|err| format!("{err}"))?;
+    if speed.is_finite() && speed > 0.0 {
+        Ok(speed)
+    } else {
+        Err("Speed has to be a positive number".into())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn session_args() {
+        let session = parse_session("longread:50m").unwrap();
+        assert_eq!((session.kind, session.time_format), (SessionKind::Work, None));
+        let session = parse_session("nap:20m:long-break:%M:%S").unwrap();
+        assert_eq!((session.kind, session.time_format.as_deref()), (SessionKind::LongBreak, Some("%M:%S")));
+        let session = parse_session("rest:5m:%M:%S").unwrap();
+        assert_eq!((session.kind, session.time_format.as_deref()), (SessionKind::Work, Some("%M:%S")));
+        assert_eq!(parse_session("rest:5m:break").unwrap().kind, SessionKind::Break);
+        assert!(parse_session("rest:5m:break:%q").is_err());
+    }
+
+    #[test]
+    fn elapsed_args() {
+        assert_eq!(parse_elapsed("1:02:03"), Ok(Duration::from_secs(3723)));
+        assert_eq!(parse_elapsed("90s"), Ok(Duration::from_secs(90)));
+        assert!(parse_elapsed("999999999999999999:00:00").is_err());
+        assert!(parse_elapsed("1:2:3:4").is_err());
+    }
 }
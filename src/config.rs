@@ -1,22 +1,341 @@
-use serde::Deserialize;
+use super::pomodoro_clock::check_duration_fmt;
+use super::schedule::Schedule;
+use super::socket::{Protocol, SocketKind, SocketPermissions, SocketType};
+use super::time_source::{ClockSource, SuspendPolicy};
 
+use serde::{Serialize, Serializer, Deserialize, Deserializer};
+use serde::de::IntoDeserializer;
+use chrono::{NaiveDateTime, NaiveTime, Timelike};
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::os::unix::net::SocketAddr;
 use std::time::Duration;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
 
-#[derive(Debug)]
+/// Serializes in the shape of the config file, save for `long_rest`
+#[derive(Debug, Serialize)]
 pub struct Config {
     pub paused_state_text: String,
     pub running_state_text: String,
+    pub finished_state_text: String,
     pub time_format: String,
     pub socket_dir: PathBuf,
+    /// Socket file of the server given with `--socket`, in place of the one
+    /// in `socket_dir` named after its id
+    #[serde(skip)]
+    pub socket_path: Option<PathBuf>,
+    /// Color variables of templates expand to escape codes, as decided by `--color`
+    #[serde(skip)]
+    pub color: bool,
+    /// Only what was asked for gets printed, as decided by `--quiet`
+    #[serde(skip)]
+    pub quiet: bool,
+    pub socket_kind: SocketKind,
+    pub socket_type: SocketType,
+    /// Permission bits of the server's socket file
+    pub socket_mode: u32,
+    /// Name or id of the group owning the server's socket file
+    pub socket_group: Option<String>,
+    /// Shared secret required of requests over TCP, sent by clients with `--host`
+    #[serde(serialize_with = "serialize_secret")]
+    pub auth_token: Option<String>,
+    /// Encoding the client talks to servers in
+    pub protocol: Protocol,
+    /// Time the client waits for a response before sending the request again
+    #[serde(serialize_with = "serialize_duration")]
+    pub response_timeout: Duration,
+    /// Number of times the client sends a request again, for requests safe to repeat
+    pub retries: u32,
+    /// Rendered by `fetch` and `subscribe` when not given a template
+    pub default_template: Option<String>,
+    /// Templates picked by name with `--template-name`
+    pub templates: BTreeMap<String, String>,
     pub sessions: Vec<Session>,
+    pub long_rest: Option<LongRest>,
+    /// Cycle made of sessions referenced by name, replaces passes through `sessions`
+    pub schedule: Option<Schedule>,
+    /// Number of full cycles after which the clock finishes, runs forever if `None`
+    pub cycles: Option<u32>,
+    /// Skip sessions marked as breaks
+    pub auto_skip_breaks: bool,
+    /// Keep counting past the end of a session until it's skipped
+    pub overtime: bool,
+    /// Refuse pausing running work sessions
+    pub strict: bool,
+    /// Start the server with the clock running instead of paused
+    pub start_running: bool,
+    pub clock_source: ClockSource,
+    pub suspend_policy: SuspendPolicy,
+    /// Pause the clock outside of these hours
+    pub active_hours: Option<ActiveHours>,
+    /// Number of work sessions to complete every day
+    pub daily_goal: Option<u32>,
+    /// Time before a session following an automatic transition starts counting
+    #[serde(serialize_with = "serialize_duration")]
+    pub transition_grace: Duration,
+    /// Report percent and time with sub-second precision
+    pub precise: bool,
+}
+
+/// Long rest taking the place of the last session of every `every`-th pass
+/// through `Config::sessions`
+#[derive(Debug, Serialize)]
+pub struct LongRest {
+    pub session: Session,
+    pub every: u32,
 }
 
 impl Config {
-    pub fn server_path(&self, server_id: u32) -> PathBuf {
-        self.socket_dir.join(format!("server{server_id}.sock"))
+    pub fn server_addr(&self, server_id: &str) -> io::Result<SocketAddr> {
+        match &self.socket_path {
+            Some(socket_path) => SocketAddr::from_pathname(socket_path),
+            None => self.socket_addr(&server_file_name(server_id, "sock")),
+        }
+    }
+
+    /// Address of the socket called `name`, in `socket_dir` unless it's abstract,
+    /// or next to `socket_path`
+    pub fn socket_addr(&self, name: &str) -> io::Result<SocketAddr> {
+        match self.socket_path.as_deref().and_then(Path::parent) {
+            Some(dir) => SocketKind::File.addr(dir, name),
+            None => self.socket_kind.addr(&self.socket_dir, name),
+        }
+    }
+
+    pub fn pid_path(&self, server_id: &str) -> PathBuf {
+        match &self.socket_path {
+            Some(socket_path) => socket_path.with_extension("pid"),
+            None => self.socket_dir.join(server_file_name(server_id, "pid")),
+        }
+    }
+
+    pub fn log_path(&self, server_id: &str) -> PathBuf {
+        match &self.socket_path {
+            Some(socket_path) => socket_path.with_extension("log"),
+            None => self.socket_dir.join(server_file_name(server_id, "log")),
+        }
+    }
+
+    /// Ids of the servers with a socket, whether or not they're still running
+    pub fn server_ids(&self) -> io::Result<Vec<String>> {
+        let names: Vec<String> = match self.socket_kind {
+            SocketKind::File => match fs::read_dir(&self.socket_dir) {
+                Ok(entries) => entries
+                    .map(|entry| Ok(entry?.file_name().to_string_lossy().into_owned()))
+                    .collect::<io::Result<_>>()?,
+                Err(err) if err.kind() == io::ErrorKind::NotFound => Vec::new(),
+                Err(err) => return Err(err),
+            },
+            // abstract sockets only show up in the kernel's list
+            SocketKind::Abstract => fs::read_to_string("/proc/net/unix")?
+                .lines()
+                .filter_map(|line| line.split_whitespace().nth(7)?.strip_prefix("@pomidoro/"))
+                .map(String::from)
+                .collect(),
+        };
+        let mut server_ids: Vec<String> = names
+            .iter()
+            .filter_map(|name| name.strip_prefix("server")?.strip_suffix(".sock"))
+            .filter(|server_id| !server_id.is_empty())
+            .map(String::from)
+            .collect();
+        // numbers first, in order
+        server_ids.sort_by_key(|server_id| (server_id.parse().unwrap_or(u64::MAX), server_id.clone()));
+        server_ids.dedup();
+        Ok(server_ids)
+    }
+
+    /// Template named `name`, or the default one, `None` if there's no default
+    pub fn template(&self, name: Option<&str>) -> io::Result<Option<&str>> {
+        let Some(name) = name else {
+            return Ok(self.default_template.as_deref());
+        };
+        self.templates
+            .get(name)
+            .map(|template| Some(template.as_str()))
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("There's no template named '{name}'")))
     }
+
+    /// Looks up `socket_group` in `/etc/group` unless it's already a group id
+    pub fn socket_permissions(&self) -> io::Result<SocketPermissions> {
+        let group = match &self.socket_group {
+            Some(group) => match group.parse() {
+                Ok(gid) => Some(gid),
+                Err(_) => Some(group_id(group)?),
+            },
+            None => None,
+        };
+        Ok(SocketPermissions { mode: self.socket_mode, group })
+    }
+
+    /// Everything wrong with the config that would only show once the server runs,
+    /// each problem prefixed with the field it's about
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if let Err(err) = check_duration_fmt(&self.time_format) {
+            problems.push(format!("time_format: {err}"));
+        }
+        if let Some(Err(err)) = self.default_template.as_deref().map(mustache::compile_str) {
+            problems.push(format!("default_template: {err}"));
+        }
+        for (name, template) in &self.templates {
+            if let Err(err) = mustache::compile_str(template) {
+                problems.push(format!("templates.{name}: {err}"));
+            }
+        }
+
+        if self.sessions.is_empty() {
+            problems.push("sessions: there are no sessions".into());
+        }
+        let long_rest = self.long_rest.iter().map(|long_rest| ("long_rest".to_string(), &long_rest.session));
+        let sessions = self.sessions.iter().enumerate().map(|(i, session)| (format!("sessions[{i}]"), session));
+        for (field, session) in sessions.chain(long_rest) {
+            if session.duration.is_some_and(|duration| duration.is_zero()) {
+                problems.push(format!("{field}.duration: '{}' takes no time", session.name));
+            }
+            if session.duration.is_some() && session.end_at.is_some() {
+                problems.push(format!("{field}.duration: '{}' also has an `end_at`, which overrides it", session.name));
+            }
+            if session.checkpoint_every.is_some_and(|every| every.is_zero()) {
+                problems.push(format!("{field}.checkpoint_every: can't be zero"));
+            }
+            if let Some(Err(err)) = session.time_format.as_deref().map(check_duration_fmt) {
+                problems.push(format!("{field}.time_format: {err}"));
+            }
+        }
+        if self.long_rest.as_ref().is_some_and(|long_rest| long_rest.every == 0) {
+            problems.push("long_rest_every: can't be zero".into());
+        }
+        if let Some(schedule) = &self.schedule {
+            if schedule.names().is_empty() {
+                problems.push("schedule: there are no sessions in it".into());
+            }
+            let mut names = schedule.names();
+            names.dedup();
+            for name in names {
+                if !self.sessions.iter().any(|session| Schedule::refers_to(name, &session.name)) {
+                    problems.push(format!("schedule: there's no session named '{name}'"));
+                }
+            }
+        }
+        if self.active_hours.is_some_and(|hours| hours.start == hours.end) {
+            problems.push("active_hours: starts and ends at the same time".into());
+        }
+
+        if self.response_timeout.is_zero() {
+            problems.push("response_timeout: can't be zero".into());
+        }
+        // a missing directory is created by the server
+        let is_socket_dir_file = fs::metadata(&self.socket_dir).is_ok_and(|metadata| !metadata.is_dir());
+        if matches!(self.socket_kind, SocketKind::File) && is_socket_dir_file {
+            problems.push("socket_dir: not a directory".into());
+        }
+        if let Err(err) = self.server_addr("0") {
+            problems.push(format!("socket_dir: {err}"));
+        }
+        if let Err(err) = self.socket_permissions() {
+            problems.push(format!("socket_group: {err}"));
+        }
+        if self.auth_token.as_deref() == Some("") {
+            problems.push("auth_token: can't be empty".into());
+        }
+        problems
+    }
+
+    /// Sessions of one full cycle, with repeats expanded and long rests
+    /// already inserted, an error if the schedule names a missing session
+    /// or the cycle has none to run
+    pub fn cycle(&self) -> io::Result<Vec<&Session>> {
+        let cycle = self.expand_cycle()?;
+        if cycle.is_empty() {
+            return Err(io::Error::other("There are no sessions in the cycle"));
+        }
+        Ok(cycle)
+    }
+
+    /// `cycle`, possibly empty
+    fn expand_cycle(&self) -> io::Result<Vec<&Session>> {
+        if let Some(schedule) = &self.schedule {
+            return schedule.names()
+                .into_iter()
+                .map(|name| self.sessions
+                    .iter()
+                    .find(|session| Schedule::refers_to(name, &session.name))
+                    .ok_or_else(|| io::Error::other(format!("There's no session named '{name}' in the schedule"))))
+                .collect();
+        }
+        let pass: Vec<&Session> = self.sessions
+            .iter()
+            .flat_map(|session| std::iter::repeat_n(session, session.repeat as usize))
+            .collect();
+        let Some(LongRest { session: long_rest, every }) = &self.long_rest else {
+            return Ok(pass);
+        };
+        let cycle_len = pass.len() * (*every).max(1) as usize;
+        Ok(pass.into_iter()
+            .cycle()
+            .take(cycle_len)
+            .enumerate()
+            .map(|(i, session)| if i + 1 == cycle_len { long_rest } else { session })
+            .collect())
+    }
+
+    /// Sessions of the next `cycles` cycles from `start` along with their
+    /// start and end, skipping the ones the clock would skip
+    pub fn timeline(&self, start: NaiveDateTime, cycles: u32) -> io::Result<Vec<(NaiveDateTime, NaiveDateTime, &Session)>> {
+        let cycles = self.cycles.map_or(cycles, |max_cycles| cycles.min(max_cycles));
+        let cycle = self.cycle()?;
+        let mut timeline = Vec::new();
+        let mut end = start;
+        for session in (0..cycles).flat_map(|_| cycle.iter()) {
+            if self.auto_skip_breaks && session.kind.is_break() {
+                continue;
+            }
+            let start = match timeline.is_empty() {
+                true => end,
+                false => end + self.transition_grace,
+            };
+            let duration = match (session.end_at, session.duration) {
+                (Some(end_at), _) => {
+                    let end = start.date().and_time(end_at);
+                    let end = if end > start { end } else { end + chrono::Days::new(1) };
+                    (end - start).to_std().unwrap_or_default()
+                },
+                (None, Some(duration)) => duration,
+                (None, None) => Duration::ZERO,
+            };
+            if duration.is_zero() {
+                continue;
+            }
+            end = start + duration;
+            timeline.push((start, end, *session));
+        }
+        Ok(timeline)
+    }
+}
+
+/// Name of one of the server's files, with anything but letters, digits,
+/// `-` and `_` in its id replaced by `_`
+fn server_file_name(server_id: &str, extension: &str) -> String {
+    let name: String = server_id
+        .chars()
+        .map(|c| if c.is_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+        .collect();
+    format!("server{name}.{extension}")
+}
+
+fn group_id(name: &str) -> io::Result<u32> {
+    fs::read_to_string("/etc/group")?
+        .lines()
+        .map(|line| line.split(':').collect::<Vec<_>>())
+        .find(|fields| fields[0] == name)
+        .and_then(|fields| fields.get(2)?.parse().ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("There's no group named '{name}'")))
 }
 
 impl From<TomlConfig> for Config {
@@ -24,18 +343,99 @@ impl From<TomlConfig> for Config {
         let TomlConfig {
             paused_state_text,
             running_state_text,
+            finished_state_text,
             time_format,
             socket_dir,
+            socket_kind,
+            socket_type,
+            socket_mode,
+            socket_group,
+            auth_token,
+            protocol,
+            response_timeout,
+            retries,
+            default_template,
+            templates,
             sessions,
+            long_rest,
+            long_rest_every,
+            schedule,
+            cycles,
+            auto_skip_breaks,
+            overtime,
+            strict,
+            start_running,
+            clock_source,
+            suspend_policy,
+            active_hours,
+            daily_goal,
+            transition_grace,
+            precise,
         } = toml_config;
+        let long_rest = long_rest.unwrap_or_else(|| Session {
+            name: "long rest".into(),
+            duration: Some(Duration::from_secs(60 * 15)),
+            end_at: None,
+            time_format: None,
+            repeat: 1,
+            kind: SessionKind::LongBreak,
+            pause_on_start: false,
+            checkpoint_every: None,
+        });
+        let (sessions, long_rest) = if schedule.is_some() {
+            // the schedule places the long rest itself, wherever it's referenced
+            let mut sessions = sessions;
+            if !sessions.iter().any(|session| session.name == long_rest.name) {
+                sessions.push(long_rest);
+            }
+            (sessions, None)
+        } else {
+            let long_rest = long_rest_every.map(|every| LongRest { session: long_rest, every });
+            (sessions, long_rest)
+        };
         Self {
             paused_state_text: paused_state_text.unwrap_or("paused".into()),
             running_state_text: running_state_text.unwrap_or("running".into()),
+            finished_state_text: finished_state_text.unwrap_or("finished".into()),
             time_format: time_format.unwrap_or("%M:%S".into()),
-            socket_dir: socket_dir.unwrap_or_else(|| {
-                std::env::temp_dir().join("pomidoro")
-            }),
+            // the runtime directory is private to the user and cleared on logout
+            socket_dir: std::env::var_os("POMIDORO_SOCKET_DIR")
+                .filter(|dir| !dir.is_empty())
+                .map(PathBuf::from)
+                .or(socket_dir)
+                .unwrap_or_else(|| {
+                    std::env::var_os("XDG_RUNTIME_DIR")
+                        .filter(|dir| !dir.is_empty())
+                        .map_or_else(std::env::temp_dir, PathBuf::from)
+                        .join("pomidoro")
+                }),
+            socket_path: None,
+            color: false,
+            quiet: false,
+            socket_kind: socket_kind.unwrap_or_default(),
+            socket_type: socket_type.unwrap_or_default(),
+            socket_mode: socket_mode.unwrap_or(0o600),
+            socket_group,
+            auth_token,
+            protocol: protocol.unwrap_or_default(),
+            response_timeout: response_timeout.unwrap_or(Duration::from_secs(1)),
+            retries: retries.unwrap_or(2),
+            default_template,
+            templates,
             sessions,
+            long_rest,
+            schedule,
+            cycles,
+            auto_skip_breaks: auto_skip_breaks.unwrap_or(false),
+            overtime: overtime.unwrap_or(false),
+            strict: strict.unwrap_or(false),
+            start_running: start_running.unwrap_or(false),
+            clock_source: clock_source.unwrap_or_default(),
+            suspend_policy: suspend_policy.unwrap_or_default(),
+            active_hours,
+            daily_goal,
+            transition_grace: transition_grace.unwrap_or_default(),
+            precise: precise.unwrap_or(false),
         }
     }
 }
@@ -45,9 +445,49 @@ impl From<TomlConfig> for Config {
 pub struct TomlConfig {
     pub paused_state_text: Option<String>,
     pub running_state_text: Option<String>,
+    pub finished_state_text: Option<String>,
     pub time_format: Option<String>,
+    /// `$XDG_RUNTIME_DIR/pomidoro` by default, or `pomidoro` in the temporary directory,
+    /// overridden by `$POMIDORO_SOCKET_DIR`
     pub socket_dir: Option<PathBuf>,
+    /// "file" | "abstract"
+    pub socket_kind: Option<SocketKind>,
+    /// "datagram" | "stream", the same for servers and clients
+    pub socket_type: Option<SocketType>,
+    /// e.g. `0o660`, along with `socket_group` to share the server with a group
+    pub socket_mode: Option<u32>,
+    pub socket_group: Option<String>,
+    /// Needed by `start --listen` and `--http`
+    pub auth_token: Option<String>,
+    /// "bincode" | "json" | "msgpack" (with the `msgpack` feature)
+    pub protocol: Option<Protocol>,
+    #[serde(default, deserialize_with = "deserialize_optional_duration")]
+    pub response_timeout: Option<Duration>,
+    pub retries: Option<u32>,
+    pub default_template: Option<String>,
+    #[serde(default)]
+    pub templates: BTreeMap<String, String>,
     pub sessions: Vec<Session>,
+    pub long_rest: Option<Session>,
+    /// Number of passes through `sessions` after which `long_rest` is taken
+    pub long_rest_every: Option<u32>,
+    /// e.g. `"(work rest){3} work long_rest"`, ignores `repeat` and `long_rest_every`
+    pub schedule: Option<Schedule>,
+    pub cycles: Option<u32>,
+    pub auto_skip_breaks: Option<bool>,
+    pub overtime: Option<bool>,
+    pub strict: Option<bool>,
+    pub start_running: Option<bool>,
+    /// "monotonic" | "wall"
+    pub clock_source: Option<ClockSource>,
+    /// "continue" | "pause" | "ignore"
+    pub suspend_policy: Option<SuspendPolicy>,
+    /// e.g. `"09:00-17:00"`
+    pub active_hours: Option<ActiveHours>,
+    pub daily_goal: Option<u32>,
+    #[serde(default, deserialize_with = "deserialize_optional_duration")]
+    pub transition_grace: Option<Duration>,
+    pub precise: Option<bool>,
 }
 
 impl Default for TomlConfig {
@@ -55,27 +495,345 @@ impl Default for TomlConfig {
         Self {
             paused_state_text: None,
             running_state_text: None,
+            finished_state_text: None,
             time_format: None,
             socket_dir: None,
+            socket_kind: None,
+            socket_type: None,
+            socket_mode: None,
+            socket_group: None,
+            auth_token: None,
+            protocol: None,
+            response_timeout: None,
+            retries: None,
+            default_template: None,
+            templates: BTreeMap::new(),
+            long_rest: None,
+            long_rest_every: None,
+            schedule: None,
+            cycles: None,
+            auto_skip_breaks: None,
+            overtime: None,
+            strict: None,
+            start_running: None,
+            clock_source: None,
+            suspend_policy: None,
+            active_hours: None,
+            daily_goal: None,
+            transition_grace: None,
+            precise: None,
             sessions: vec![
                 Session {
                     name: "work".into(),
-                    duration: Duration::from_secs(60 * 25),
+                    duration: Some(Duration::from_secs(60 * 25)),
+                    end_at: None,
                     time_format: None,
+                    repeat: 1,
+                    kind: SessionKind::Work,
+                    pause_on_start: false,
+                    checkpoint_every: None,
                 },
                 Session {
                     name: "rest".into(),
-                    duration: Duration::from_secs(60 * 5),
+                    duration: Some(Duration::from_secs(60 * 5)),
+                    end_at: None,
                     time_format: None,
+                    repeat: 1,
+                    kind: SessionKind::Break,
+                    pause_on_start: false,
+                    checkpoint_every: None,
                 },
             ]
         }
     }
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Hash, Serialize, Deserialize)]
 pub struct Session {
     pub name: String,
-    pub duration: Duration,
+    /// Counts up until skipped if `None`
+    #[serde(
+        default,
+        deserialize_with = "deserialize_optional_duration",
+        serialize_with = "serialize_optional_duration",
+    )]
+    pub duration: Option<Duration>,
+    /// Local time of day at which the session ends, overrides `duration`
+    #[serde(default, deserialize_with = "deserialize_time_of_day", serialize_with = "serialize_time_of_day")]
+    pub end_at: Option<NaiveTime>,
     pub time_format: Option<String>,
+    /// Number of consecutive times the session is run within one pass
+    #[serde(default = "default_repeat", deserialize_with = "deserialize_repeat")]
+    pub repeat: u32,
+    /// Also read from `break = true`, written before there were kinds
+    #[serde(default, alias = "break", deserialize_with = "deserialize_kind")]
+    pub kind: SessionKind,
+    /// Pause the clock whenever the session starts
+    #[serde(default)]
+    pub pause_on_start: bool,
+    /// Interval between checkpoints within the session
+    #[serde(
+        default,
+        deserialize_with = "deserialize_optional_duration",
+        serialize_with = "serialize_optional_duration",
+    )]
+    pub checkpoint_every: Option<Duration>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum SessionKind {
+    #[default]
+    Work,
+    Break,
+    LongBreak,
+    /// Sessions suspending the cycle, not meant to be configured
+    #[serde(skip_deserializing)]
+    #[value(skip)]
+    Interrupt,
+}
+
+impl SessionKind {
+    /// Skipped when `auto_skip_breaks` is enabled
+    pub fn is_break(self) -> bool {
+        matches!(self, Self::Break | Self::LongBreak)
+    }
+}
+
+/// Hard-coded sessions, for running without a config
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+pub enum Preset {
+    /// 25 minutes of work and 5 of rest, with 15 minutes of rest after every 4th work session
+    Classic,
+    /// 52 minutes of work and 17 of rest
+    #[value(name = "52-17")]
+    Work52Rest17,
+    /// 90 minutes of work and 20 of rest
+    #[value(name = "90-20")]
+    Work90Rest20,
+}
+
+impl Preset {
+    pub fn sessions(self) -> (Vec<Session>, Option<LongRest>) {
+        let session = |name: &str, minutes: u64, kind| Session {
+            name: name.into(),
+            duration: Some(Duration::from_secs(60 * minutes)),
+            end_at: None,
+            time_format: None,
+            repeat: 1,
+            kind,
+            pause_on_start: false,
+            checkpoint_every: None,
+        };
+        let (work, rest) = match self {
+            Self::Classic => (25, 5),
+            Self::Work52Rest17 => (52, 17),
+            Self::Work90Rest20 => (90, 20),
+        };
+        let mut sessions = vec![session("work", work, SessionKind::Work), session("rest", rest, SessionKind::Break)];
+        if work >= 60 {
+            sessions[0].time_format = Some("%H:%M:%S".into());
+        }
+        let long_rest = matches!(self, Self::Classic).then(|| LongRest {
+            session: session("long rest", 15, SessionKind::LongBreak),
+            every: 4,
+        });
+        (sessions, long_rest)
+    }
+}
+
+/// Window of local time, wrapping around midnight if `end` is earlier than `start`
+#[derive(Debug, Clone, Copy)]
+pub struct ActiveHours {
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+}
+
+impl ActiveHours {
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        if self.start <= self.end {
+            self.start <= time && time < self.end
+        } else {
+            self.start <= time || time < self.end
+        }
+    }
+}
+
+impl FromStr for ActiveHours {
+    type Err = String;
+
+    /// Accepts `hh:mm[:ss]-hh:mm[:ss]`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (start, end) = s.split_once('-')
+            .ok_or_else(|| format!("Expected '<start>-<end>', got '{s}'"))?;
+        let parse = |text: &str| parse_time_of_day(text.trim())
+            .map_err(|err| format!("Invalid time '{text}': {err}"));
+        Ok(Self { start: parse(start)?, end: parse(end)? })
+    }
+}
+
+impl fmt::Display for ActiveHours {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}-{}", format_time_of_day(self.start), format_time_of_day(self.end))
+    }
+}
+
+impl Serialize for ActiveHours {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for ActiveHours {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+fn default_repeat() -> u32 {
+    1
+}
+
+/// Refuses 0, a session that never runs could leave the cycle empty
+fn deserialize_repeat<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u32, D::Error> {
+    match u32::deserialize(deserializer)? {
+        0 => Err(serde::de::Error::custom("a session repeated 0 times never runs")),
+        repeat => Ok(repeat),
+    }
+}
+
+/// Accepts either the name of a kind or whether the session is a break
+fn deserialize_kind<'de, D: Deserializer<'de>>(deserializer: D) -> Result<SessionKind, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum RawKind {
+        Name(String),
+        Break(bool),
+    }
+
+    match RawKind::deserialize(deserializer)? {
+        RawKind::Name(name) => SessionKind::deserialize(name.into_deserializer()),
+        RawKind::Break(true) => Ok(SessionKind::Break),
+        RawKind::Break(false) => Ok(SessionKind::Work),
+    }
+}
+
+/// Accepts either a human readable duration (e.g. `"25m"`, `"1h 30m"`)
+/// or serde's `{ secs, nanos }` representation
+pub fn deserialize_duration<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum RawDuration {
+        Human(String),
+        Std(Duration),
+    }
+
+    match RawDuration::deserialize(deserializer)? {
+        RawDuration::Human(text) => humantime::parse_duration(&text)
+            .map_err(serde::de::Error::custom),
+        RawDuration::Std(duration) => Ok(duration),
+    }
+}
+
+/// Accepts `hh:mm` or `hh:mm:ss`
+pub fn parse_time_of_day(text: &str) -> Result<NaiveTime, chrono::ParseError> {
+    NaiveTime::parse_from_str(text, "%H:%M")
+        .or_else(|_| NaiveTime::parse_from_str(text, "%H:%M:%S"))
+}
+
+/// `hh:mm`, or `hh:mm:ss` if there are seconds
+pub fn format_time_of_day(time: NaiveTime) -> String {
+    let format = if time.second() == 0 { "%H:%M" } else { "%H:%M:%S" };
+    time.format(format).to_string()
+}
+
+fn serialize_time_of_day<S: Serializer>(time: &Option<NaiveTime>, serializer: S) -> Result<S::Ok, S::Error> {
+    match time {
+        Some(time) => serializer.serialize_str(&format_time_of_day(*time)),
+        None => serializer.serialize_none(),
+    }
+}
+
+fn deserialize_time_of_day<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<NaiveTime>, D::Error> {
+    let text = String::deserialize(deserializer)?;
+    parse_time_of_day(&text)
+        .map(Some)
+        .map_err(serde::de::Error::custom)
+}
+
+fn deserialize_optional_duration<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Duration>, D::Error> {
+    deserialize_duration(deserializer).map(Some)
+}
+
+/// Human readable, as accepted by `deserialize_duration`
+fn serialize_duration<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(&humantime::format_duration(*duration))
+}
+
+fn serialize_optional_duration<S: Serializer>(duration: &Option<Duration>, serializer: S) -> Result<S::Ok, S::Error> {
+    match duration {
+        Some(duration) => serialize_duration(duration, serializer),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// Tells whether there's a secret without giving it away
+fn serialize_secret<S: Serializer>(secret: &Option<String>, serializer: S) -> Result<S::Ok, S::Error> {
+    match secret {
+        Some(_) => serializer.serialize_str("<hidden>"),
+        None => serializer.serialize_none(),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn config_cycle() {
+        let toml_config: TomlConfig = toml::from_str(r#"
+            long_rest_every = 2
+            long_rest = { name = "long rest", duration = "15m" }
+            sessions = [
+                { name = "work", duration = "25m", repeat = 2 },
+                { name = "rest", duration = "5m" },
+            ]
+        "#).unwrap();
+        let config = Config::from(toml_config);
+        let names: Vec<&str> = config.cycle().unwrap().iter().map(|session| session.name.as_str()).collect();
+        assert_eq!(names, ["work", "work", "rest", "work", "work", "long rest"]);
+
+        let never_runs = r#"sessions = [{ name = "work", duration = "25m", repeat = 0 }]"#;
+        assert!(toml::from_str::<TomlConfig>(never_runs).is_err());
+
+        let toml_config: TomlConfig = toml::from_str(r#"
+            schedule = "work nap"
+            sessions = [{ name = "work", duration = "25m" }]
+        "#).unwrap();
+        assert!(Config::from(toml_config).cycle().is_err());
+
+        let toml_config: TomlConfig = toml::from_str("sessions = []").unwrap();
+        assert!(Config::from(toml_config).cycle().is_err());
+    }
+
+    #[test]
+    fn session_kinds() {
+        let toml_config: TomlConfig = toml::from_str(r#"
+            sessions = [
+                { name = "work", duration = "25m" },
+                { name = "rest", duration = "5m", kind = "break" },
+                { name = "nap", duration = "20m", break = true },
+                { name = "read", duration = "10m", break = false },
+                { name = "walk", duration = "30m", kind = "long-break" },
+            ]
+        "#).unwrap();
+        let kinds: Vec<SessionKind> = toml_config.sessions.iter().map(|session| session.kind).collect();
+        let [work, rest, long_rest] = [SessionKind::Work, SessionKind::Break, SessionKind::LongBreak];
+        assert_eq!(kinds, [work, rest, rest, work, long_rest]);
+
+        assert!(toml::from_str::<TomlConfig>(r#"sessions = [{ name = "nap", duration = "5m", kind = "nap" }]"#).is_err());
+    }
 }
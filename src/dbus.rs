@@ -0,0 +1,588 @@
+use std::io::{self, ErrorKind, Read, Write};
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::net::{SocketAddr, UnixStream};
+#[cfg(target_os = "linux")]
+use std::os::linux::net::SocketAddrExt;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+
+pub const NAME: &str = "org.pomidoro.Timer1";
+pub const INTERFACE: &str = "org.pomidoro.Timer1";
+pub const PATH: &str = "/org/pomidoro/Timer1";
+pub const PROPERTIES_INTERFACE: &str = "org.freedesktop.DBus.Properties";
+pub const INTROSPECTABLE_INTERFACE: &str = "org.freedesktop.DBus.Introspectable";
+/// Methods of `INTERFACE`, each returning the response of the server as JSON
+pub const METHODS: [&str; 6] = ["Toggle", "Pause", "Resume", "Skip", "Reset", "Fetch"];
+
+const METHOD_CALL: u8 = 1;
+const METHOD_RETURN: u8 = 2;
+const ERROR: u8 = 3;
+const SIGNAL: u8 = 4;
+
+const BUS_NAME: &str = "org.freedesktop.DBus";
+const BUS_PATH: &str = "/org/freedesktop/DBus";
+
+/// Codes of the header fields
+const FIELD_PATH: u8 = 1;
+const FIELD_INTERFACE: u8 = 2;
+const FIELD_MEMBER: u8 = 3;
+const FIELD_ERROR_NAME: u8 = 4;
+const FIELD_REPLY_SERIAL: u8 = 5;
+const FIELD_DESTINATION: u8 = 6;
+const FIELD_SENDER: u8 = 7;
+const FIELD_SIGNATURE: u8 = 8;
+
+/// Largest message the bus lets through
+const MAX_MESSAGE_SIZE: usize = 128 * 1024 * 1024;
+
+
+/// Message received from the bus, of which only the header is read
+#[derive(Debug, Default)]
+pub struct Message {
+    kind: u8,
+    serial: u32,
+    reply_serial: Option<u32>,
+    pub path: Option<String>,
+    pub interface: Option<String>,
+    pub member: Option<String>,
+    sender: Option<String>,
+    body: Vec<u8>,
+}
+
+impl Message {
+    pub fn is_method_call(&self) -> bool {
+        self.kind == METHOD_CALL
+    }
+
+    pub fn is_signal(&self) -> bool {
+        self.kind == SIGNAL
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.kind == ERROR
+    }
+
+    /// Arguments of the message, to be read in the order of its signature
+    pub fn args(&self) -> Reader<'_> {
+        Reader { data: &self.body, pos: 0 }
+    }
+
+    pub fn read(stream: &mut impl Read) -> io::Result<Self> {
+        let invalid = |msg: &str| io::Error::new(ErrorKind::InvalidData, msg.to_string());
+
+        let mut fixed = [0u8; 16];
+        stream.read_exact(&mut fixed)?;
+        if fixed[0] != b'l' {
+            return Err(invalid("Only little-endian messages are supported"));
+        }
+        let u32_at = |i: usize| u32::from_le_bytes(fixed[i..i + 4].try_into().unwrap()) as usize;
+        let (body_len, fields_len) = (u32_at(4), u32_at(12));
+        if body_len + fields_len > MAX_MESSAGE_SIZE {
+            return Err(invalid("Message too large"));
+        }
+        let mut rest = vec![0u8; fields_len.next_multiple_of(8) + body_len];
+        stream.read_exact(&mut rest)?;
+        let mut header = fixed.to_vec();
+        header.extend(&rest[..fields_len]);
+
+        let mut message = Self {
+            kind: fixed[1],
+            serial: u32_at(8) as u32,
+            body: rest[fields_len.next_multiple_of(8)..].to_vec(),
+            ..Self::default()
+        };
+        let mut reader = Reader { data: &header, pos: 16 };
+        while reader.pos < header.len() {
+            reader.align(8);
+            let code = reader.byte()?;
+            let signature = reader.signature()?;
+            match signature.as_str() {
+                "s" | "o" => {
+                    let value = Some(reader.string()?);
+                    match code {
+                        FIELD_PATH => message.path = value,
+                        FIELD_INTERFACE => message.interface = value,
+                        FIELD_MEMBER => message.member = value,
+                        FIELD_SENDER => message.sender = value,
+                        _ => (),
+                    }
+                },
+                "g" => {
+                    reader.signature()?;
+                },
+                "u" | "h" => {
+                    let value = reader.u32()?;
+                    if code == FIELD_REPLY_SERIAL {
+                        message.reply_serial = Some(value);
+                    }
+                },
+                _ => return Err(invalid("Unexpected header field type")),
+            }
+        }
+        Ok(message)
+    }
+}
+
+pub struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn align(&mut self, align: usize) {
+        self.pos = self.pos.next_multiple_of(align);
+    }
+
+    /// Start of a struct, or of a dict entry
+    pub fn open_struct(&mut self) {
+        self.align(8);
+    }
+
+    fn take(&mut self, len: usize) -> io::Result<&[u8]> {
+        let bytes = self.data
+            .get(self.pos..self.pos + len)
+            .ok_or_else(|| io::Error::new(ErrorKind::UnexpectedEof, "Message cut short"))?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn u32(&mut self) -> io::Result<u32> {
+        self.align(4);
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub fn i32(&mut self) -> io::Result<i32> {
+        self.u32().map(|value| value as i32)
+    }
+
+    pub fn string(&mut self) -> io::Result<String> {
+        let len = self.u32()? as usize;
+        let string = String::from_utf8_lossy(self.take(len)?).into_owned();
+        self.take(1)?;
+        Ok(string)
+    }
+
+    pub fn signature(&mut self) -> io::Result<String> {
+        let len = self.byte()? as usize;
+        let signature = String::from_utf8_lossy(self.take(len)?).into_owned();
+        self.take(1)?;
+        Ok(signature)
+    }
+
+    /// Elements aligned to `align`, each read by `element`
+    pub fn array<T>(&mut self, align: usize, mut element: impl FnMut(&mut Self) -> io::Result<T>) -> io::Result<Vec<T>> {
+        let len = self.u32()? as usize;
+        self.align(align);
+        let end = self.pos + len;
+        let mut elements = Vec::new();
+        while self.pos < end {
+            elements.push(element(self)?);
+        }
+        Ok(elements)
+    }
+
+    /// Skips over a variant, which has to be of a basic type
+    pub fn skip_variant(&mut self) -> io::Result<()> {
+        match self.signature()?.as_str() {
+            "y" => self.byte().map(|_| ()),
+            "b" | "i" | "u" => self.u32().map(|_| ()),
+            "s" | "o" => self.string().map(|_| ()),
+            "g" => self.signature().map(|_| ()),
+            _ => Err(io::Error::new(ErrorKind::InvalidData, "Unexpected variant type")),
+        }
+    }
+}
+
+/// Value of a variant, written along with its signature
+pub enum Variant<'a> {
+    String(&'a str),
+    Object(&'a str),
+    U32(u32),
+    I32(i32),
+    Bool(bool),
+    /// Any array, empty
+    EmptyArray(&'a str),
+    /// `(sa(iiay)ss)` of a StatusNotifierItem tooltip, without an icon
+    ToolTip { icon_name: &'a str, title: &'a str, description: &'a str },
+}
+
+/// Marshals values in little-endian, aligned from the start of the buffer
+#[derive(Default)]
+pub struct Writer {
+    data: Vec<u8>,
+}
+
+impl Writer {
+    fn align(&mut self, align: usize) {
+        self.data.resize(self.data.len().next_multiple_of(align), 0);
+    }
+
+    pub fn byte(&mut self, value: u8) -> &mut Self {
+        self.data.push(value);
+        self
+    }
+
+    pub fn u32(&mut self, value: u32) -> &mut Self {
+        self.align(4);
+        self.data.extend(value.to_le_bytes());
+        self
+    }
+
+    pub fn i32(&mut self, value: i32) -> &mut Self {
+        self.u32(value as u32)
+    }
+
+    pub fn bool(&mut self, value: bool) -> &mut Self {
+        self.u32(value.into())
+    }
+
+    pub fn string(&mut self, value: &str) -> &mut Self {
+        self.u32(value.len() as u32);
+        self.data.extend(value.as_bytes());
+        self.byte(0)
+    }
+
+    pub fn object(&mut self, value: &str) -> &mut Self {
+        self.string(value)
+    }
+
+    pub fn signature(&mut self, value: &str) -> &mut Self {
+        self.byte(value.len() as u8);
+        self.data.extend(value.as_bytes());
+        self.byte(0)
+    }
+
+    /// Start of a struct, or of a dict entry
+    pub fn open_struct(&mut self) -> &mut Self {
+        self.align(8);
+        self
+    }
+
+    /// Array of elements aligned to `align`, written by `elements`
+    pub fn array(&mut self, align: usize, elements: impl FnOnce(&mut Self)) -> &mut Self {
+        self.u32(0);
+        let len_pos = self.data.len() - 4;
+        self.align(align);
+        let start = self.data.len();
+        elements(self);
+        let len = (self.data.len() - start) as u32;
+        self.data[len_pos..len_pos + 4].copy_from_slice(&len.to_le_bytes());
+        self
+    }
+
+    pub fn variant(&mut self, value: &Variant) -> &mut Self {
+        match value {
+            Variant::String(value) => self.signature("s").string(value),
+            Variant::Object(value) => self.signature("o").object(value),
+            Variant::U32(value) => self.signature("u").u32(*value),
+            Variant::I32(value) => self.signature("i").i32(*value),
+            Variant::Bool(value) => self.signature("b").bool(*value),
+            Variant::EmptyArray(signature) => {
+                let align = alignment(signature.as_bytes().get(1).copied().unwrap_or(b'y'));
+                self.signature(signature).array(align, |_| ())
+            },
+            Variant::ToolTip { icon_name, title, description } => self
+                .signature("(sa(iiay)ss)")
+                .open_struct()
+                .string(icon_name)
+                .array(8, |_| ())
+                .string(title)
+                .string(description),
+        }
+    }
+
+    /// What has been written so far, read from the start
+    #[cfg(test)]
+    pub fn reader(&self) -> Reader<'_> {
+        Reader { data: &self.data, pos: 0 }
+    }
+
+    /// `a{sv}`
+    pub fn dict(&mut self, entries: &[(&str, Variant)]) -> &mut Self {
+        self.array(8, |writer| for (key, value) in entries {
+            writer.open_struct().string(key).variant(value);
+        })
+    }
+
+    /// `a{sv}` with string values
+    fn string_dict(&mut self, entries: &[(&str, &str)]) -> &mut Self {
+        self.array(8, |writer| for (key, value) in entries {
+            writer.align(8);
+            writer.string(key).signature("s").string(value);
+        })
+    }
+}
+
+enum Field<'a> {
+    Object(&'a str),
+    String(&'a str),
+    Serial(u32),
+    Signature(&'a str),
+}
+
+/// Connection to the session bus, shared between the thread reading it
+/// and whoever replies or emits signals
+pub struct Bus {
+    stream: Mutex<UnixStream>,
+    next_serial: AtomicU32,
+    /// Value of the `State` property, as of the last signal
+    state: Mutex<String>,
+}
+
+impl Bus {
+    /// Connects to the session bus and claims `NAME`, returning the bus
+    /// along with its end to read method calls from
+    pub fn connect() -> io::Result<(Self, UnixStream)> {
+        let (bus, mut reader) = Self::open()?;
+        bus.request_name(NAME, &mut reader)?;
+        Ok((bus, reader))
+    }
+
+    /// Connects to the session bus without claiming a name
+    pub fn open() -> io::Result<(Self, UnixStream)> {
+        let address = std::env::var("DBUS_SESSION_BUS_ADDRESS")
+            .map_err(|_| io::Error::new(ErrorKind::NotFound, "There's no session bus"))?;
+        let mut stream = UnixStream::connect_addr(&parse_address(&address)?)?;
+
+        // the bus knows who's connecting from the socket, so the uid only has to match
+        let uid = std::fs::metadata("/proc/self")?.uid();
+        let hex_uid: String = uid.to_string().bytes().map(|byte| format!("{byte:02x}")).collect();
+        stream.write_all(format!("\0AUTH EXTERNAL {hex_uid}\r\n").as_bytes())?;
+        let mut line = Vec::new();
+        while !line.ends_with(b"\r\n") {
+            let mut byte = [0u8];
+            stream.read_exact(&mut byte)?;
+            line.push(byte[0]);
+        }
+        if !line.starts_with(b"OK ") {
+            return Err(io::Error::new(ErrorKind::PermissionDenied, "Session bus refused authentication"));
+        }
+        stream.write_all(b"BEGIN\r\n")?;
+
+        let mut reader = stream.try_clone()?;
+        let bus = Self {
+            stream: Mutex::new(stream),
+            next_serial: AtomicU32::new(1),
+            state: Mutex::new("{}".into()),
+        };
+        bus.call_bus("Hello", "", Writer::default(), &mut reader)?;
+        Ok((bus, reader))
+    }
+
+    /// Claims a well-known `name`, before anything else reads from `reader`
+    pub fn request_name(&self, name: &str, reader: &mut UnixStream) -> io::Result<()> {
+        let mut args = Writer::default();
+        // fails rather than waits in line if the name is taken
+        args.string(name).u32(4);
+        let reply = self.call_bus("RequestName", "su", args, reader)?;
+        if reply.args().u32()? != 1 {
+            return Err(io::Error::new(ErrorKind::AddrInUse, format!("{name} is taken on the session bus")));
+        }
+        Ok(())
+    }
+
+    /// Asks the bus for the signals matching `rule`, e.g. `type='signal',member='NameOwnerChanged'`
+    pub fn add_match(&self, rule: &str, reader: &mut UnixStream) -> io::Result<()> {
+        let mut args = Writer::default();
+        args.string(rule);
+        self.call_bus("AddMatch", "s", args, reader).map(|_| ())
+    }
+
+    /// Calls a method of the bus itself, waiting for its reply
+    fn call_bus(&self, member: &str, signature: &str, args: Writer, reader: &mut UnixStream) -> io::Result<Message> {
+        let reply = self.call(BUS_NAME, BUS_PATH, BUS_NAME, member, signature, args, reader)?;
+        match reply.is_error() {
+            true => Err(io::Error::other(format!("Session bus refused {member}"))),
+            false => Ok(reply),
+        }
+    }
+
+    /// Calls a method, waiting for its reply or error, before anything else reads from `reader`
+    #[allow(clippy::too_many_arguments)]
+    pub fn call(
+        &self,
+        destination: &str,
+        path: &str,
+        interface: &str,
+        member: &str,
+        signature: &str,
+        args: Writer,
+        reader: &mut UnixStream,
+    ) -> io::Result<Message> {
+        let serial = self.call_later(destination, path, interface, member, signature, args)?;
+        loop {
+            let message = Message::read(reader)?;
+            if message.reply_serial == Some(serial) {
+                return Ok(message);
+            }
+        }
+    }
+
+    /// Calls a method without waiting for its reply, returning the serial the reply will refer to
+    pub fn call_later(
+        &self,
+        destination: &str,
+        path: &str,
+        interface: &str,
+        member: &str,
+        signature: &str,
+        args: Writer,
+    ) -> io::Result<u32> {
+        let mut fields = vec![
+            (FIELD_PATH, Field::Object(path)),
+            (FIELD_INTERFACE, Field::String(interface)),
+            (FIELD_MEMBER, Field::String(member)),
+            (FIELD_DESTINATION, Field::String(destination)),
+        ];
+        if !signature.is_empty() {
+            fields.push((FIELD_SIGNATURE, Field::Signature(signature)));
+        }
+        self.send(METHOD_CALL, &fields, args)
+    }
+
+    fn send(&self, kind: u8, fields: &[(u8, Field)], body: Writer) -> io::Result<u32> {
+        let serial = self.next_serial.fetch_add(1, Ordering::Relaxed);
+        let mut message = Writer::default();
+        message
+            .byte(b'l')
+            .byte(kind)
+            .byte(0)
+            .byte(1)
+            .u32(body.data.len() as u32)
+            .u32(serial);
+        message.array(8, |writer| for (code, value) in fields {
+            writer.align(8);
+            writer.byte(*code);
+            match value {
+                Field::Object(value) => writer.signature("o").string(value),
+                Field::String(value) => writer.signature("s").string(value),
+                Field::Serial(value) => writer.signature("u").u32(*value),
+                Field::Signature(value) => writer.signature("g").signature(value),
+            };
+        });
+        message.align(8);
+        message.data.extend(body.data);
+        self.stream.lock().unwrap().write_all(&message.data)?;
+        Ok(serial)
+    }
+
+    fn reply_fields<'a>(call: &'a Message, signature: &'a str) -> Vec<(u8, Field<'a>)> {
+        let mut fields = vec![(FIELD_REPLY_SERIAL, Field::Serial(call.serial))];
+        if let Some(sender) = &call.sender {
+            fields.push((FIELD_DESTINATION, Field::String(sender)));
+        }
+        if !signature.is_empty() {
+            fields.push((FIELD_SIGNATURE, Field::Signature(signature)));
+        }
+        fields
+    }
+
+    pub fn reply(&self, call: &Message, text: &str) -> io::Result<()> {
+        let mut body = Writer::default();
+        body.string(text);
+        self.reply_with(call, "s", body)
+    }
+
+    /// Replies with a `body` of the given `signature`, empty for no values
+    pub fn reply_with(&self, call: &Message, signature: &str, body: Writer) -> io::Result<()> {
+        self.send(METHOD_RETURN, &Self::reply_fields(call, signature), body).map(|_| ())
+    }
+
+    pub fn reply_error(&self, call: &Message, name: &str, text: &str) -> io::Result<()> {
+        let mut fields = Self::reply_fields(call, "s");
+        fields.push((FIELD_ERROR_NAME, Field::String(name)));
+        let mut body = Writer::default();
+        body.string(text);
+        self.send(ERROR, &fields, body).map(|_| ())
+    }
+
+    /// Handles calls of the standard interfaces, returns `false` for any others
+    pub fn handle_standard_call(&self, call: &Message) -> io::Result<bool> {
+        match (call.interface.as_deref(), call.member.as_deref()) {
+            (Some(INTROSPECTABLE_INTERFACE), Some("Introspect")) => {
+                self.reply(call, &introspection()).map(|_| true)
+            },
+            (Some(PROPERTIES_INTERFACE), Some("Get")) => {
+                let mut body = Writer::default();
+                body.signature("s").string(&self.state.lock().unwrap());
+                self.send(METHOD_RETURN, &Self::reply_fields(call, "v"), body).map(|_| true)
+            },
+            (Some(PROPERTIES_INTERFACE), Some("GetAll")) => {
+                let mut body = Writer::default();
+                body.string_dict(&[("State", &self.state.lock().unwrap())]);
+                self.send(METHOD_RETURN, &Self::reply_fields(call, "a{sv}"), body).map(|_| true)
+            },
+            (Some(PROPERTIES_INTERFACE), _) => {
+                let error = "org.freedesktop.DBus.Error.PropertyReadOnly";
+                self.reply_error(call, error, "Properties are read-only").map(|_| true)
+            },
+            _ => Ok(false),
+        }
+    }
+
+    /// Updates the `State` property, signalling the change
+    pub fn emit_state(&self, state: &str) -> io::Result<()> {
+        *self.state.lock().unwrap() = state.to_string();
+        let mut body = Writer::default();
+        body.string(INTERFACE)
+            .string_dict(&[("State", state)])
+            .array(4, |_| ());
+        self.emit(PATH, PROPERTIES_INTERFACE, "PropertiesChanged", "sa{sv}as", body)
+    }
+
+    /// Broadcasts a signal of the object at `path`
+    pub fn emit(&self, path: &str, interface: &str, member: &str, signature: &str, body: Writer) -> io::Result<()> {
+        let mut fields = vec![
+            (FIELD_PATH, Field::Object(path)),
+            (FIELD_INTERFACE, Field::String(interface)),
+            (FIELD_MEMBER, Field::String(member)),
+        ];
+        if !signature.is_empty() {
+            fields.push((FIELD_SIGNATURE, Field::Signature(signature)));
+        }
+        self.send(SIGNAL, &fields, body).map(|_| ())
+    }
+}
+
+/// Alignment of the values of a type, by the first character of its signature
+fn alignment(signature: u8) -> usize {
+    match signature {
+        b'y' | b'g' | b'v' => 1,
+        b'n' | b'q' => 2,
+        b'x' | b't' | b'd' | b'(' | b'{' => 8,
+        _ => 4,
+    }
+}
+
+/// First `unix:` address of a bus, e.g. `unix:path=/run/user/1000/bus`
+fn parse_address(address: &str) -> io::Result<SocketAddr> {
+    for transport in address.split(';') {
+        let Some(params) = transport.strip_prefix("unix:") else { continue; };
+        for param in params.split(',') {
+            match param.split_once('=') {
+                Some(("path", path)) => return SocketAddr::from_pathname(path),
+                #[cfg(target_os = "linux")]
+                Some(("abstract", name)) => return SocketAddr::from_abstract_name(name),
+                _ => (),
+            }
+        }
+    }
+    Err(io::Error::new(ErrorKind::Unsupported, format!("Unsupported bus address '{address}'")))
+}
+
+fn introspection() -> String {
+    let methods: String = METHODS
+        .iter()
+        .map(|method| format!(r#"<method name="{method}"><arg type="s" direction="out"/></method>"#))
+        .collect();
+    format!(
+        r#"<!DOCTYPE node PUBLIC "-//freedesktop//DTD D-BUS Object Introspection 1.0//EN"
+"http://www.freedesktop.org/standards/dbus/1.0/introspect.dtd">
+<node><interface name="{INTERFACE}">{methods}<property name="State" type="s" access="read"/></interface></node>"#
+    )
+}
@@ -0,0 +1,229 @@
+use std::io::{self, BufRead, ErrorKind, Read, Write};
+
+
+/// Largest request line or header accepted
+const MAX_LINE_LEN: usize = 8 * 1024;
+/// Largest request body, read only to be thrown away
+const MAX_BODY_LEN: u64 = 64 * 1024;
+
+/// HTTP request, of which the body is ignored
+#[derive(Debug)]
+pub struct HttpRequest {
+    pub method: String,
+    /// Path along with the query, e.g. `/state?timer=tea`
+    pub target: String,
+    /// Names are lowercase
+    headers: Vec<(String, String)>,
+}
+
+impl HttpRequest {
+    pub fn read(reader: &mut impl BufRead) -> io::Result<Self> {
+        let invalid = |msg: &str| io::Error::new(ErrorKind::InvalidData, msg.to_string());
+
+        let request_line = read_line(reader)?;
+        let mut parts = request_line.split_whitespace();
+        let (Some(method), Some(target), Some(_version)) = (parts.next(), parts.next(), parts.next()) else {
+            return Err(invalid("Malformed request line"));
+        };
+        let mut headers = Vec::new();
+        loop {
+            let line = read_line(reader)?;
+            if line.is_empty() {
+                break;
+            }
+            let (name, value) = line.split_once(':').ok_or_else(|| invalid("Malformed header"))?;
+            headers.push((name.trim().to_lowercase(), value.trim().to_string()));
+        }
+        let request = Self { method: method.into(), target: target.into(), headers };
+
+        let body_len: u64 = request.header("content-length")
+            .map(|len| len.parse().map_err(|_| invalid("Malformed Content-Length")))
+            .transpose()?
+            .unwrap_or(0);
+        if body_len > MAX_BODY_LEN {
+            return Err(invalid("Request body too large"));
+        }
+        io::copy(&mut reader.take(body_len), &mut io::sink())?;
+        Ok(request)
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn is_websocket_upgrade(&self) -> bool {
+        self.header("upgrade").is_some_and(|upgrade| upgrade.eq_ignore_ascii_case("websocket"))
+    }
+}
+
+fn read_line(reader: &mut impl BufRead) -> io::Result<String> {
+    let mut line = Vec::new();
+    reader.take(MAX_LINE_LEN as u64).read_until(b'\n', &mut line)?;
+    if line.last() != Some(&b'\n') {
+        return Err(io::Error::new(ErrorKind::InvalidData, "Line too long or cut short"));
+    }
+    String::from_utf8(line)
+        .map(|line| line.trim_end_matches(['\r', '\n']).to_string())
+        .map_err(|err| io::Error::new(ErrorKind::InvalidData, err))
+}
+
+/// Writes a whole response with a JSON body, after which the connection is closed
+pub fn write_response(writer: &mut impl Write, status: u16, body: &str) -> io::Result<()> {
+    let reason = match status {
+        200 => "OK",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        404 => "Not Found",
+        _ => "",
+    };
+    let response = format!(
+        "HTTP/1.1 {status} {reason}\r\n\
+         Content-Type: application/json\r\n\
+         Content-Length: {}\r\n\
+         Connection: close\r\n\
+         \r\n\
+         {body}",
+        body.len(),
+    );
+    writer.write_all(response.as_bytes())?;
+    writer.flush()
+}
+
+/// Completes the handshake of a WebSocket upgrade request, after which
+/// the connection carries WebSocket frames
+pub fn accept_websocket(writer: &mut impl Write, request: &HttpRequest) -> io::Result<()> {
+    const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+    let key = request.header("sec-websocket-key")
+        .ok_or_else(|| io::Error::new(ErrorKind::InvalidData, "Missing Sec-WebSocket-Key"))?;
+    let accept = base64(&sha1(format!("{key}{GUID}").as_bytes()));
+    let response = format!(
+        "HTTP/1.1 101 Switching Protocols\r\n\
+         Upgrade: websocket\r\n\
+         Connection: Upgrade\r\n\
+         Sec-WebSocket-Accept: {accept}\r\n\
+         \r\n",
+    );
+    writer.write_all(response.as_bytes())?;
+    writer.flush()
+}
+
+/// Writes `text` in a single unmasked text frame, as servers do
+pub fn write_websocket_text(writer: &mut impl Write, text: &str) -> io::Result<()> {
+    let mut frame = vec![0x81];
+    match text.len() {
+        len @ 0..=125 => frame.push(len as u8),
+        len @ 126..=0xffff => {
+            frame.push(126);
+            frame.extend((len as u16).to_be_bytes());
+        },
+        len => {
+            frame.push(127);
+            frame.extend((len as u64).to_be_bytes());
+        },
+    }
+    frame.extend(text.as_bytes());
+    writer.write_all(&frame)?;
+    writer.flush()
+}
+
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut h: [u32; 5] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476, 0xc3d2e1f0];
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend((data.len() as u64 * 8).to_be_bytes());
+
+    for block in message.chunks(64) {
+        let mut w = [0u32; 80];
+        for (i, word) in block.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes(word.try_into().unwrap());
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e] = h;
+        for (i, word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5a827999),
+                20..=39 => (b ^ c ^ d, 0x6ed9eba1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8f1bbcdc),
+                _ => (b ^ c ^ d, 0xca62c1d6),
+            };
+            let temp = a.rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        for (h, value) in h.iter_mut().zip([a, b, c, d, e]) {
+            *h = h.wrapping_add(value);
+        }
+    }
+
+    let mut digest = [0u8; 20];
+    for (bytes, h) in digest.chunks_mut(4).zip(h) {
+        bytes.copy_from_slice(&h.to_be_bytes());
+    }
+    digest
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut output = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bytes = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let triple = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                output.push(ALPHABET[(triple >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                output.push('=');
+            }
+        }
+    }
+    output
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn http_requests() {
+        let mut data: &[u8] = b"POST /toggle?timer=tea HTTP/1.1\r\nHost: localhost\r\nContent-Length: 3\r\n\r\nabcGET";
+        let request = HttpRequest::read(&mut data).unwrap();
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.target, "/toggle?timer=tea");
+        assert_eq!(request.header("host"), Some("localhost"));
+        // the body is skipped
+        assert_eq!(data, b"GET");
+
+        assert!(HttpRequest::read(&mut &b"GET /state\r\n\r\n"[..]).is_err());
+        assert!(HttpRequest::read(&mut &b"GET /state HTTP/1.1\r\nHost"[..]).is_err());
+    }
+
+    #[test]
+    fn websocket_handshake() {
+        let mut data: &[u8] = b"GET /events HTTP/1.1\r\nUpgrade: websocket\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n";
+        let request = HttpRequest::read(&mut data).unwrap();
+        assert!(request.is_websocket_upgrade());
+        let mut response = Vec::new();
+        accept_websocket(&mut response, &request).unwrap();
+        // example from RFC 6455
+        assert!(String::from_utf8(response).unwrap().contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+        assert_eq!(base64(b"ab"), "YWI=");
+    }
+}
@@ -0,0 +1,73 @@
+use log::{LevelFilter, Log, Metadata, Record};
+
+
+/// Logs to stderr, at the level of the most specific directive of `RUST_LOG`
+/// matching the target, e.g. `info,pomidoro::socket=trace`
+struct Logger {
+    /// Level of targets no directive is about
+    default: LevelFilter,
+    /// Target prefixes with their levels, the longest first
+    directives: Vec<(String, LevelFilter)>,
+}
+
+impl Logger {
+    fn level(&self, target: &str) -> LevelFilter {
+        self.directives
+            .iter()
+            .find(|(prefix, _)| target == prefix || target.starts_with(&format!("{prefix}::")))
+            .map_or(self.default, |(_, level)| *level)
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level(metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            let time = chrono::Local::now().format("%H:%M:%S%.3f");
+            eprintln!("{time} {:5} {}: {}", record.level(), record.target(), record.args());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Sets up logging at `warn`, `info` with one `-v`, `debug` with two and `trace`
+/// with more, unless `RUST_LOG` says otherwise for a target
+pub fn init(verbosity: u8) {
+    let mut default = match verbosity {
+        0 => LevelFilter::Warn,
+        1 => LevelFilter::Info,
+        2 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    };
+    let mut directives = Vec::new();
+    let filter = std::env::var("RUST_LOG").unwrap_or_default();
+    for directive in filter.split(',').map(str::trim).filter(|directive| !directive.is_empty()) {
+        match directive.split_once('=') {
+            Some((target, level)) => match level.parse() {
+                Ok(level) => directives.push((target.to_string(), level)),
+                Err(_) => eprintln!("Ignoring '{directive}' in RUST_LOG, '{level}' isn't a level"),
+            },
+            // a bare level is only the default when the flags don't set one
+            None => match directive.parse() {
+                Ok(level) if verbosity == 0 => default = level,
+                Ok(_) => (),
+                Err(_) => directives.push((directive.to_string(), LevelFilter::Trace)),
+            },
+        }
+    }
+    directives.sort_by_key(|(target, _)| std::cmp::Reverse(target.len()));
+
+    let max_level = directives
+        .iter()
+        .map(|(_, level)| *level)
+        .chain([default])
+        .max()
+        .unwrap_or(default);
+    if log::set_boxed_logger(Box::new(Logger { default, directives })).is_ok() {
+        log::set_max_level(max_level);
+    }
+}
@@ -1,136 +1,1173 @@
 mod cli;
 mod config;
+mod dbus;
+mod http;
+mod logger;
 mod socket;
 mod pomodoro_clock;
+mod schedule;
+mod time_source;
+mod timers;
+mod tray;
+mod tui;
 
-use cli::{Cli, Command, Request};
-use config::{Config, TomlConfig};
-use pomodoro_clock::{PomodoroClock, Response};
+use cli::{Cli, ColorChoice, Command, ConfigCommand, FetchArgs, Request};
+use config::{Config, Session, SessionKind, TomlConfig};
+use pomodoro_clock::{Handover, PomodoroClock, PomodoroState, Response};
+use socket::{Connection, Protocol, SocketKind, SocketType, Transports};
+use time_source::TimeSource;
+use timers::{TimerRequest, TimerResponse, Timers};
 
 use clap::Parser;
 use rand::Rng;
 use serde::Serialize;
 
 use std::fs;
+use std::io::{self, IsTerminal};
+use std::os::unix::fs::DirBuilderExt;
+use std::os::unix::process::CommandExt;
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::net::SocketAddr;
 use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::{Duration, Instant};
+
+
+/// Exit status when the server isn't running or doesn't respond in time
+const EXIT_NO_RESPONSE: i32 = 3;
+/// Exit statuses of `fetch --exit-status`
+const EXIT_PAUSED: i32 = 10;
+const EXIT_BREAK: i32 = 11;
+/// Written by `init`, with the same values as when there's no config
+const DEFAULT_CONFIG: &str = include_str!("default_config.toml");
+/// Characters of the `{{bar}}` of templates
+const PROGRESS_BAR_WIDTH: usize = 20;
+/// Rendered by `status`, the lines of fields that may be missing are left out
+const STATUS_TEMPLATE: &str = "\
+Session:   {{kind_color}}{{session}}{{reset}} ({{kind}})
+Time left: {{time}} of {{duration}}
+Progress:  {{kind_color}}{{bar}}{{reset}} {{percent}}%
+Clock:     {{clock_state}}\
+{{#overtime}}\nOvertime:  {{overtime}}{{/overtime}}\
+{{#next_session}}\nNext:      {{next_session}}{{/next_session}}\
+";
 
 
 #[derive(Debug, Serialize)]
 struct TemplateSource {
     /// Server id
-    id: u32,
-    /// "running" | "paused"
+    id: String,
+    /// "running" | "paused" | "finished"
     clock_state: String,
     /// Session name
     session: String,
+    /// "work" | "break" | "long-break" | "interrupt"
+    kind: SessionKind,
     /// Whole session duration
     duration: String,
+    /// Name of the session coming next, unless the last cycle is under way
+    next_session: Option<String>,
+    /// Names of the next few sessions, starting with `next_session`
+    upcoming_sessions: Vec<String>,
     /// `0..=100`
     percent: u32,
+    /// Progress drawn with block characters
+    bar: String,
+    /// Escape code of the color of the session's kind, empty without colors
+    kind_color: &'static str,
+    /// Escape code ending `kind_color`, empty without colors
+    reset: &'static str,
+    /// Fractional `percent`, with `precise` enabled
+    exact_percent: Option<f64>,
+    /// Time left in fractional seconds, with `precise` enabled
+    exact_time: Option<f64>,
     /// Time left
     time: String,
+    /// Time counted past the end of the session, if any
+    overtime: Option<String>,
+    /// Index of the current checkpoint interval within the session, if it has any
+    checkpoint_index: Option<u32>,
+    /// Time left until the next checkpoint
+    checkpoint_time: Option<String>,
+    /// Interruptions recorded against the current work session
+    internal_interruptions: u32,
+    external_interruptions: u32,
+    /// Number of work sessions completed since the server started
+    pomodoros_done: u32,
+    /// Number of work sessions completed today
+    goal_done: u32,
+    /// Daily goal, if any
+    goal_total: Option<u32>,
+    /// The cycle is suspended by an interrupt session
+    interrupted: bool,
+    /// The session has just started on its own, and can still be skipped
+    /// before it really starts
+    transition: bool,
+    /// The clock is paused for being outside of the active hours
+    suspended_by_schedule: bool,
+    /// Local time the server was started at
+    started_at: String,
+    /// Time the server has been running for
+    uptime: String,
+}
+
+fn progress_bar(percent: f64, width: usize) -> String {
+    let filled = ((percent.clamp(0.0, 100.0) / 100.0) * width as f64).round() as usize;
+    "█".repeat(filled) + &"░".repeat(width - filled)
+}
+
+fn kind_color(kind: SessionKind) -> &'static str {
+    match kind {
+        SessionKind::Work => "\x1b[1;31m",
+        SessionKind::Break => "\x1b[1;32m",
+        SessionKind::LongBreak => "\x1b[1;34m",
+        SessionKind::Interrupt => "\x1b[1;33m",
+    }
 }
 
+impl TemplateSource {
+    fn new(server_id: &str, state: PomodoroState, config: &Config) -> Self {
+        Self {
+            id: server_id.into(),
+            clock_state: if state.is_finished {
+                config.finished_state_text.clone()
+            } else if state.is_paused {
+                config.paused_state_text.clone()
+            } else {
+                config.running_state_text.clone()
+            },
+            session: state.session_name,
+            kind: state.session_kind,
+            duration: state.session_duration,
+            next_session: state.next_session,
+            upcoming_sessions: state.upcoming_sessions,
+            time: state.time,
+            percent: state.percent,
+            bar: progress_bar(state.exact_percent.unwrap_or(state.percent.into()), PROGRESS_BAR_WIDTH),
+            kind_color: if config.color { kind_color(state.session_kind) } else { "" },
+            reset: if config.color { "\x1b[0m" } else { "" },
+            exact_percent: state.exact_percent,
+            exact_time: state.exact_time,
+            overtime: state.overtime,
+            checkpoint_index: state.checkpoint_index,
+            checkpoint_time: state.checkpoint_time,
+            internal_interruptions: state.internal_interruptions,
+            external_interruptions: state.external_interruptions,
+            pomodoros_done: state.pomodoros_done,
+            goal_done: state.goal_done,
+            goal_total: state.goal_total,
+            interrupted: state.is_interrupted,
+            transition: state.is_in_transition,
+            suspended_by_schedule: state.is_suspended_by_schedule,
+            started_at: state.started_at,
+            uptime: state.uptime,
+        }
+    }
+}
+
+
+fn default_config_path() -> PathBuf {
+    let config_dir: PathBuf = std::env::var("XDG_CONFIG_HOME")
+        .unwrap_or_else(|_| {
+            let home = std::env::var("HOME")
+                .expect("Could not find the 'HOME' variable");
+            format!("{home}/.config/")
+        })
+        .into();
+    config_dir.join("pomidoro/config.toml")
+}
 
-fn get_config(config_path: Option<&Path>) -> Config {
-    let config_path = match config_path {
+/// The path given if any, or the default one if there's a file there
+fn find_config_path(config_path: Option<&Path>) -> Option<PathBuf> {
+    match config_path {
         Some(path) => Some(PathBuf::from(path)),
-        None => {
-            let config_dir: PathBuf = std::env::var("XDG_CONFIG_HOME")
-                .unwrap_or_else(|_| {
-                    let home = std::env::var("HOME")
-                        .expect("Could not find the 'HOME' variable");
-                    format!("{home}/.config/")
-                })
-                .into();
-            let path = config_dir.join("pomidoro/config.toml");
-            path.exists().then_some(path)
-        },
+        None => Some(default_config_path()).filter(|path| path.exists()),
+    }
+}
+
+fn get_config(config_path: Option<&Path>) -> io::Result<Config> {
+    let Some(config_path) = find_config_path(config_path) else {
+        return Ok(TomlConfig::default().into());
     };
-    let config_file = config_path.map(|config_path| {
-        fs::read_to_string(&config_path)
-            .expect(format!(
-                "Could not open the config file '{}'",
-                config_path.display(),
-            ).as_str())
-    });
-    config_file
-        .map(|config_file| {
-            toml::from_str::<TomlConfig>(&config_file)
-                .expect("Error in config file")
-        })
-        .unwrap_or_default()
-        .into()
+    let config_file = fs::read_to_string(&config_path).map_err(|err| io::Error::other(format!(
+        "Could not open the config file '{}': {err}",
+        config_path.display(),
+    )))?;
+    let toml_config = toml::from_str::<TomlConfig>(&config_file).map_err(|err| io::Error::other(format!(
+        "Invalid config file '{}': {}",
+        config_path.display(),
+        err.to_string().trim_end(),
+    )))?;
+    Ok(toml_config.into())
 }
 
 
-fn main() -> std::io::Result<()> {
+fn main() -> io::Result<()> {
+    match run() {
+        Err(err) if matches!(err.kind(), io::ErrorKind::TimedOut | io::ErrorKind::NotConnected) => {
+            eprintln!("Error: {err}");
+            std::process::exit(EXIT_NO_RESPONSE);
+        },
+        // e.g. a request the server refused, the message says it all
+        Err(err) if err.kind() == io::ErrorKind::Other => {
+            eprintln!("Error: {err}");
+            std::process::exit(1);
+        },
+        result => result,
+    }
+}
+
+fn run() -> io::Result<()> {
     let cli = Cli::parse();
+    let quiet = cli.quiet;
+    logger::init(cli.verbose);
 
-    let config = get_config(cli.config_path.as_deref());
+    // the config written by `init` doesn't have to exist yet
+    if let Command::Init { force } | Command::Setup { force } = cli.command {
+        let config_path = cli.config_path.unwrap_or_else(default_config_path);
+        if config_path.exists() && !force {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("'{}' already exists, pass --force to overwrite it", config_path.display()),
+            ));
+        }
+        let config = match cli.command {
+            Command::Setup { .. } => setup_config()?,
+            _ => DEFAULT_CONFIG.into(),
+        };
+        if let Some(config_dir) = config_path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(config_dir)?;
+        }
+        fs::write(&config_path, config)?;
+        if !quiet {
+            println!("Wrote '{}'", config_path.display());
+        }
+        return Ok(());
+    }
+    // a config that doesn't load is when the path matters most
+    if let Command::Config { command: ConfigCommand::Path } = cli.command {
+        match find_config_path(cli.config_path.as_deref()) {
+            Some(path) => println!("{}", path.display()),
+            None => println!("defaults, no file found at '{}'", default_config_path().display()),
+        }
+        return Ok(());
+    }
+    if let Command::Check = cli.command {
+        let Some(config_path) = find_config_path(cli.config_path.as_deref()) else {
+            if !quiet {
+                println!("There's no config file, the defaults are fine");
+            }
+            return Ok(());
+        };
+        let problems = match toml::from_str::<TomlConfig>(&fs::read_to_string(&config_path)?) {
+            Ok(toml_config) => Config::from(toml_config).problems(),
+            // the error points at the line
+            Err(err) => vec![err.to_string().trim_end().to_string()],
+        };
+        if problems.is_empty() {
+            if !quiet {
+                println!("'{}' is fine", config_path.display());
+            }
+            return Ok(());
+        }
+        for problem in problems {
+            println!("{}: {problem}", config_path.display());
+        }
+        std::process::exit(1);
+    }
+    let mut config = get_config(cli.config_path.as_deref())?;
+    config.color = match cli.color {
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+        // https://no-color.org
+        ColorChoice::Auto => std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+            && io::stdout().is_terminal(),
+    };
+    config.quiet = quiet;
 
     match cli.command {
-        Command::Start { server_id } => {
-            let server_path = config.server_path(server_id);
-            if server_path.exists() {
-                fs::remove_file(&server_path)?;
+        Command::Init { .. } | Command::Setup { .. } | Command::Check | Command::Config { command: ConfigCommand::Path } => unreachable!(),
+        Command::Start {
+            server_id, socket_path, cycles, timer, until, sessions, preset, running, force, takeover, pid_file, daemon, log_file, listen, http, dbus,
+            speed,
+        } => {
+            if cycles.is_some() {
+                config.cycles = cycles;
+            }
+            if running {
+                config.start_running = true;
+            }
+            if !sessions.is_empty() {
+                config.sessions = sessions;
+                config.long_rest = None;
+                config.schedule = None;
+            }
+            if let Some(preset) = preset {
+                (config.sessions, config.long_rest) = preset.sessions();
+                config.schedule = None;
+            }
+            let is_one_shot = timer.is_some() || until.is_some();
+            if is_one_shot {
+                config.sessions = vec![Session {
+                    name: "timer".into(),
+                    duration: timer,
+                    end_at: until,
+                    time_format: None,
+                    repeat: 1,
+                    kind: SessionKind::Work,
+                    pause_on_start: false,
+                    checkpoint_every: None,
+                }];
+                config.long_rest = None;
+                config.schedule = None;
+                config.cycles = Some(1);
+                config.overtime = false;
             }
 
-            let sessions = config.sessions.iter();
-            let pomodoro_clock = PomodoroClock::paused(sessions, &config.time_format);
-            socket::start_server(&server_path, pomodoro_clock)?; 
+            // rather than the server dying on its first request
+            let problems = config.problems();
+            if !problems.is_empty() {
+                return Err(io::Error::other(format!(
+                    "Not starting with problems in the config:\n{}",
+                    problems.join("\n"),
+                )));
+            }
+            // the server checks again, but would leave its files behind by then
+            if (listen.is_some() || http.is_some()) && config.auth_token.is_none() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "Accepting requests over TCP requires an `auth_token` in the config",
+                ));
+            }
+            use_socket_path(&mut config, socket_path);
+            // the log goes next to the socket even when it's abstract
+            let needs_socket_dir = matches!(config.socket_kind, SocketKind::File) || daemon && log_file.is_none();
+            if needs_socket_dir && config.socket_path.is_none() {
+                fs::DirBuilder::new().recursive(true).mode(0o700).create(&config.socket_dir)?;
+            }
+            let server_addr = config.server_addr(&server_id)?;
+            let permissions = config.socket_permissions()?;
+            let is_socket_activated = socket::is_socket_activated();
+            if !is_socket_activated && !force && !takeover && socket::is_server_alive(&server_addr) {
+                return Err(io::Error::new(
+                    io::ErrorKind::AddrInUse,
+                    format!("Server {server_id} is already running, use '--force' to replace it"),
+                ));
+            }
+            if daemon {
+                let log_path = log_file.unwrap_or_else(|| config.log_path(&server_id));
+                return daemonize(&server_id, &log_path, &config);
+            }
+            let handover = if takeover && socket::is_server_alive(&server_addr) {
+                Some(take_over(&server_id, &server_addr, &config)?)
+            } else {
+                None
+            };
+            // an abstract socket goes away with its server, unlike a file
+            let server_path = server_addr.as_pathname().filter(|_| !is_socket_activated);
+            if let Some(server_path) = server_path.filter(|path| path.exists()) {
+                fs::remove_file(server_path)?;
+            }
 
-            fs::remove_file(&server_path)?;
-        },
-        Command::Send { request, server_id } => {
-            let random_digits = |len: usize| -> String {
-                let mut rng = rand::thread_rng();
-                (0..len)
-                    .map(|_| rng.gen_range('0'..='9'))
-                    .collect()
+            // the daemon may run from elsewhere, and `info` is asked from anywhere
+            let config_path = find_config_path(cli.config_path.as_deref())
+                .map(|path| fs::canonicalize(&path).unwrap_or(path));
+            let socket = match (server_addr.as_pathname(), server_addr.as_abstract_name()) {
+                (Some(path), _) => path.display().to_string(),
+                (None, Some(name)) => format!("@{}", String::from_utf8_lossy(name)),
+                (None, None) => String::new(),
+            };
+            let cycle = config.cycle()?;
+            let new_clock = |running: bool| {
+                let sessions = cycle.iter().copied();
+                let pomodoro_clock = if running {
+                    PomodoroClock::running(sessions, &config.time_format, Instant::now())
+                } else {
+                    PomodoroClock::paused(sessions, &config.time_format)
+                };
+                pomodoro_clock
+                    .with_cycles(config.cycles)
+                    .with_auto_skip_breaks(config.auto_skip_breaks)
+                    .with_overtime(config.overtime)
+                    .with_strict(config.strict)
+                    .with_stop_when_finished(is_one_shot)
+                    .with_transition_grace(config.transition_grace)
+                    .with_precise(config.precise)
+                    .with_time_source(TimeSource::new(config.clock_source, speed))
+                    .with_suspend_policy(config.suspend_policy)
+                    .with_active_hours(config.active_hours)
+                    .with_daily_goal(config.daily_goal)
+                    .with_config_path(config_path.clone())
+                    .with_socket(socket.clone())
             };
+            let mut pomodoro_clock = new_clock(config.start_running || is_one_shot);
+            if let Some(handover) = handover {
+                if !pomodoro_clock.take_over(Instant::now(), &handover) {
+                    eprintln!("There's no session named '{}' anymore, starting over", handover.session_name);
+                }
+            }
+            // abstract sockets don't need the socket directory, so neither should the pid file
+            let pid_path = pid_file.or_else(|| {
+                matches!(config.socket_kind, SocketKind::File).then(|| config.pid_path(&server_id))
+            });
+            if let Some(pid_path) = &pid_path {
+                fs::write(pid_path, format!("{}\n", std::process::id()))?;
+            }
+            // named timers start paused, whatever the main one does
+            let timers = Timers::new(pomodoro_clock, move || new_clock(false));
+            let transports = Transports { listen, http, dbus, token: config.auth_token.clone() };
+            socket::start_server(&server_addr, config.socket_type, permissions, transports, timers)?;
 
-            // generate an unexisting client socket filename
-            let client_path = loop {
-                let file_name = format!("client{}.sock", random_digits(6));
-                let path = config.socket_dir.join(file_name);
-                if !path.exists() {
-                    break path;
+            if let Some(server_path) = server_path {
+                fs::remove_file(server_path)?;
+            }
+            if let Some(pid_path) = pid_path {
+                fs::remove_file(pid_path)?;
+            }
+        },
+        Command::Send { request, server_id, socket_path, host, timer, all, protocol, json, then_fetch } => {
+            use_socket_path(&mut config, socket_path);
+            let protocol = protocol.unwrap_or(config.protocol);
+            let servers = if all { "every server".to_string() } else { format!("server {server_id}") };
+            if !confirm_reset(&request, &servers)? {
+                return Ok(());
+            }
+            if !all {
+                send(&server_id, host.as_deref(), protocol, timer.as_deref(), &request, json, &config)?;
+                if let Some(template) = then_fetch {
+                    let fetch = Request::Fetch(FetchArgs { template: Some(template), ..FetchArgs::default() });
+                    send(&server_id, host.as_deref(), protocol, timer.as_deref(), &fetch, json, &config)?;
                 }
+                return Ok(());
+            }
+            if matches!(request, Request::Fetch(FetchArgs { exit_status: true, .. })) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "Can't exit with the status of every server at once",
+                ));
+            }
+            if then_fetch.is_some() {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "Can't fetch from every server at once"));
+            }
+            if matches!(request, Request::Subscribe { .. } | Request::Fetch(FetchArgs { follow: true, .. })) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "Can't subscribe to every server at once",
+                ));
+            }
+            let server_ids: Vec<String> = config.server_ids()?
+                .into_iter()
+                .filter(|server_id| config.server_addr(server_id).is_ok_and(|addr| socket::is_server_alive(&addr)))
+                .collect();
+            if server_ids.is_empty() {
+                return Err(io::Error::new(io::ErrorKind::NotConnected, "No servers are running"));
+            }
+            let failed = server_ids
+                .iter()
+                .filter(|server_id| send(server_id, None, protocol, timer.as_deref(), &request, json, &config)
+                    .inspect_err(|err| eprintln!("Server {server_id}: {err}"))
+                    .is_err())
+                .count();
+            if failed > 0 {
+                return Err(io::Error::other(format!(
+                    "The request failed for {failed} of {} servers",
+                    server_ids.len(),
+                )));
+            }
+        },
+        Command::Schedule { from, cycles } => {
+            let today = chrono::Local::now().date_naive();
+            let format_time = |time: chrono::NaiveDateTime| match time.date() == today {
+                true => time.format("%H:%M").to_string(),
+                false => time.format("%a %H:%M").to_string(),
             };
-
-            let response: Response = socket::send_and_receive(
-                &client_path,
-                &config.server_path(server_id),
-                &pomodoro_clock::Request::from(&request),
-            )?;
-            match request {
-                Request::Fetch { template } => {
-                    let Response::State(state) = response else { unreachable!(); };
-
-                    let template_src = TemplateSource {
-                        id: server_id,
-                        clock_state: if state.is_paused {
-                            config.paused_state_text
-                        } else {
-                            config.running_state_text
-                        },
-                        session: state.session_name,
-                        duration: state.session_duration,
-                        time: state.time,
-                        percent: state.percent,
+            let rows: Vec<[String; 4]> = config.timeline(today.and_time(from), cycles)?
+                .into_iter()
+                .map(|(start, end, session)| [
+                    format_time(start),
+                    format_time(end),
+                    session.name.clone(),
+                    kind_name(session.kind),
+                ])
+                .collect();
+            print_table(["START", "END", "SESSION", "KIND"], &rows);
+        },
+        Command::List => {
+            for server_id in config.server_ids()? {
+                match request(&server_id, pomodoro_clock::Request::Fetch, &config) {
+                    Ok(Response::State(state)) => {
+                        let source = TemplateSource::new(&server_id, state, &config);
+                        println!("{server_id}\talive\t{}\t{}", source.session, source.clock_state);
+                    },
+                    Ok(_) => unreachable!(),
+                    // a socket file left behind by a server that's gone
+                    Err(err) if matches!(err.kind(), io::ErrorKind::NotFound | io::ErrorKind::ConnectionRefused) => {
+                        println!("{server_id}\tstale");
+                    },
+                    Err(_) => println!("{server_id}\tnot responding"),
+                }
+            }
+        },
+        Command::Stop { all: true, yes, .. } | Command::StopAll { yes } => return stop_all(yes, quiet, &config),
+        Command::Stop { server_id, .. } => {
+            let request = Request::Stop;
+            return send(&server_id, None, config.protocol, None, &request, false, &config);
+        },
+        Command::Status { all: true, json: true, .. } => {
+            let states: serde_json::Map<String, serde_json::Value> = config.server_ids()?
+                .into_iter()
+                .map(|server_id| {
+                    let state = match request(&server_id, pomodoro_clock::Request::Fetch, &config) {
+                        Ok(Response::State(state)) => serde_json::to_value(state)?,
+                        Ok(_) => unreachable!(),
+                        Err(_) => serde_json::Value::Null,
                     };
-                    let output = template.render_to_string(&template_src)
-                        .expect("Couldn't populate mustache template");
-                    println!("{}", output);
-                },
-                _ => (),
+                    Ok((server_id, state))
+                })
+                .collect::<serde_json::Result<_>>()?;
+            println!("{}", serde_json::Value::Object(states));
+        },
+        Command::Status { all: true, .. } => {
+            let rows = status_rows(&config)?;
+            print_table(["ID", "STATE", "SESSION", "TIME LEFT"], &rows);
+        },
+        Command::Status { server_id, json, .. } => {
+            let template = mustache::compile_str(STATUS_TEMPLATE).expect("Status template is valid");
+            let request = Request::Fetch(FetchArgs { template: Some(template), ..FetchArgs::default() });
+            return send(&server_id, None, config.protocol, None, &request, json, &config);
+        },
+        Command::Watch { server_id, no_clear, template } => {
+            let template = template
+                .unwrap_or_else(|| mustache::compile_str(STATUS_TEMPLATE).expect("Status template is valid"));
+            loop {
+                let state = match request(&server_id, pomodoro_clock::Request::Fetch, &config) {
+                    Ok(Response::State(state)) => state,
+                    Ok(_) => unreachable!(),
+                    Err(err) => return Err(not_running(&server_id, err)),
+                };
+                let output = template.render_to_string(&TemplateSource::new(&server_id, state, &config))
+                    .expect("Couldn't populate mustache template");
+                if no_clear {
+                    println!("{output}");
+                } else {
+                    // back to the top left corner of a cleared screen
+                    println!("\x1b[H\x1b[2J{output}");
+                }
+                std::thread::sleep(Duration::from_secs(1));
+            }
+        },
+        Command::Tui { mut server_ids, all } => {
+            if all {
+                server_ids = config.server_ids()?;
+                if server_ids.is_empty() {
+                    return Err(io::Error::other("No server is running"));
+                }
+            } else if server_ids.is_empty() {
+                server_ids.push(cli::default_server_id());
+            }
+            return tui::run(server_ids, &config);
+        },
+        Command::Tray { server_id, template } => {
+            // tooltips don't take escape codes
+            config.color = false;
+            return tray::run(server_id, template, &config);
+        },
+        Command::IsRunning { server_id } => {
+            let is_running = matches!(request(&server_id, pomodoro_clock::Request::Ping, &config), Ok(Response::Pong));
+            std::process::exit(if is_running { 0 } else { 1 });
+        },
+        Command::Info { server_id, json } => {
+            let info = match request(&server_id, pomodoro_clock::Request::Info, &config) {
+                Ok(Response::Info(info)) => info,
+                Ok(_) => unreachable!(),
+                Err(err) => return Err(not_running(&server_id, err)),
+            };
+            if json {
+                println!("{}", serde_json::to_string(&info)?);
+                return Ok(());
+            }
+            let config_path = info.config_path
+                .map_or_else(|| "defaults".to_string(), |path| path.display().to_string());
+            // edited sessions or another config make the server run a different cycle
+            let cycle_hash = config.cycle().ok().map(pomodoro_clock::sessions_hash);
+            let sessions_hash = match cycle_hash.as_ref() == Some(&info.sessions_hash) {
+                true => info.sessions_hash,
+                false => format!("{}, not the cycle of this config", info.sessions_hash),
+            };
+            println!("Version:    {}", info.version);
+            println!("PID:        {}", info.pid);
+            println!("Config:     {config_path}");
+            println!("Socket:     {}", info.socket);
+            println!("Sessions:   {sessions_hash}");
+            println!("Started at: {}", info.started_at);
+            println!("Uptime:     {}", info.uptime);
+        },
+        Command::Sessions { server_id, json } => {
+            let sessions = match request(&server_id, pomodoro_clock::Request::Sessions, &config) {
+                Ok(Response::Sessions(sessions)) => sessions,
+                Ok(_) => unreachable!(),
+                Err(err) => return Err(not_running(&server_id, err)),
+            };
+            if json {
+                println!("{}", serde_json::to_string(&sessions)?);
+                return Ok(());
             }
+            let rows: Vec<[String; 5]> = sessions
+                .into_iter()
+                .map(|session| [
+                    if session.is_current { "*" } else { "" }.to_string(),
+                    session.name,
+                    match (session.end_at, session.duration) {
+                        (Some(end_at), _) => format!("until {end_at}"),
+                        (None, Some(duration)) => duration,
+                        (None, None) => "until skipped".to_string(),
+                    },
+                    kind_name(session.kind),
+                    session.time_format,
+                ])
+                .collect();
+            print_table(["", "SESSION", "DURATION", "KIND", "FORMAT"], &rows);
+        },
+        Command::Config { command: ConfigCommand::Dump } => {
+            let dump = toml::to_string(&config).map_err(io::Error::other)?;
+            print!("{dump}");
+        },
+        Command::Shortcut(shortcut) => {
+            let server_id = cli::default_server_id();
+            let request = shortcut.into();
+            if !confirm_reset(&request, &format!("server {server_id}"))? {
+                return Ok(());
+            }
+            return send(&server_id, None, config.protocol, None, &request, false, &config);
+        },
+    }
 
-            fs::remove_file(&client_path)?;
+    Ok(())
+}
+
+/// Asks on the terminal until the answer parses, an empty one being `default`
+fn prompt<T>(question: &str, default: &str, parse: impl Fn(&str) -> Result<T, String>) -> io::Result<T> {
+    use io::{BufRead, Write};
+
+    let mut stdin = io::stdin().lock();
+    loop {
+        // stdout is left to what was asked for, e.g. JSON
+        eprint!("{question} [{default}]: ");
+        io::stderr().flush()?;
+        let mut answer = String::new();
+        if stdin.read_line(&mut answer)? == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Cancelled"));
+        }
+        let answer = match answer.trim() {
+            "" => default,
+            answer => answer,
+        };
+        match parse(answer) {
+            Ok(value) => return Ok(value),
+            Err(err) => eprintln!("{err}"),
+        }
+    }
+}
+
+/// Asks before a reset run from a terminal without `--force`, returns whether to go ahead
+fn confirm_reset(request: &Request, servers: &str) -> io::Result<bool> {
+    if !matches!(request, Request::Reset { force: false }) || !io::stdin().is_terminal() {
+        return Ok(true);
+    }
+    let question = format!("Reset {servers}, throwing away the progress of the cycle");
+    match prompt(&question, "n", parse_yes_no) {
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => {
+            eprintln!();
+            Ok(false)
         },
+        result => result,
+    }
+}
+
+/// `y` or `n`, in either case or spelled out
+fn parse_yes_no(answer: &str) -> Result<bool, String> {
+    match answer.to_lowercase().as_str() {
+        "y" | "yes" => Ok(true),
+        "n" | "no" => Ok(false),
+        _ => Err("Answer y or n".to_string()),
     }
+}
 
+/// Config of the answers to `setup`
+fn setup_config() -> io::Result<String> {
+    let duration = |answer: &str| humantime::parse_duration(answer)
+        .map_err(|err| format!("Not a duration, e.g. 25m: {err}"))
+        .and_then(|duration| match duration.is_zero() {
+            true => Err("Sessions can't be empty".into()),
+            false => Ok(humantime::format_duration(duration).to_string()),
+        });
+    let count = |answer: &str| match answer {
+        "none" => Ok(None),
+        answer => answer.parse::<u32>()
+            .ok()
+            .filter(|count| *count > 0)
+            .map(Some)
+            .ok_or_else(|| "Not a positive number, or none".to_string()),
+    };
+
+    let work = prompt("Length of work sessions", "25m", duration)?;
+    let rest = prompt("Length of breaks", "5m", duration)?;
+    let long_rest_every = prompt("Work sessions before a long break", "4", count)?;
+    let long_rest = long_rest_every
+        .map(|_| prompt("Length of long breaks", "15m", duration))
+        .transpose()?;
+    let daily_goal = prompt("Work sessions to complete every day", "none", count)?;
+    let start_running = prompt("Start with the clock running", "n", parse_yes_no)?;
+    let overtime = prompt("Keep counting past the end of sessions until they're skipped", "n", parse_yes_no)?;
+    let auto_skip_breaks = prompt("Skip breaks", "n", parse_yes_no)?;
+
+    let mut config = String::from("# Written by `pomidoro setup`, `pomidoro init` writes every option\n\n");
+    config += &format!("start_running = {start_running}\n");
+    config += &format!("overtime = {overtime}\n");
+    config += &format!("auto_skip_breaks = {auto_skip_breaks}\n");
+    if let Some(daily_goal) = daily_goal {
+        config += &format!("daily_goal = {daily_goal}\n");
+    }
+    if let (Some(every), Some(duration)) = (long_rest_every, long_rest) {
+        config += &format!("\nlong_rest_every = {every}\n");
+        config += &format!("[long_rest]\nname = \"long rest\"\nduration = \"{duration}\"\nkind = \"long-break\"\n");
+    }
+    config += &format!("\n[[sessions]]\nname = \"work\"\nduration = \"{work}\"\n");
+    config += &format!("\n[[sessions]]\nname = \"rest\"\nduration = \"{rest}\"\nkind = \"break\"\n");
+    Ok(config)
+}
+
+/// Id, state, session and time left of every server with a socket
+fn status_rows(config: &Config) -> io::Result<Vec<[String; 4]>> {
+    let rows = config.server_ids()?
+        .into_iter()
+        .map(|server_id| match request(&server_id, pomodoro_clock::Request::Fetch, config) {
+            Ok(Response::State(state)) => {
+                let source = TemplateSource::new(&server_id, state, config);
+                [server_id, source.clock_state, source.session, source.time]
+            },
+            Ok(_) => unreachable!(),
+            Err(err) => {
+                let state = match err.kind() {
+                    io::ErrorKind::NotFound | io::ErrorKind::ConnectionRefused => "stale",
+                    _ => "not responding",
+                };
+                [server_id, state.into(), "-".into(), "-".into()]
+            },
+        })
+        .collect();
+    Ok(rows)
+}
+
+/// Stops every server with a socket once the list of them is confirmed, unless `yes`
+fn stop_all(yes: bool, quiet: bool, config: &Config) -> io::Result<()> {
+    let rows = status_rows(config)?;
+    if rows.is_empty() {
+        if !quiet {
+            println!("No server is running");
+        }
+        return Ok(());
+    }
+    if !yes {
+        print_table(["ID", "STATE", "SESSION", "TIME LEFT"], &rows);
+        let question = match rows.len() {
+            1 => "Stop this server".to_string(),
+            count => format!("Stop these {count} servers"),
+        };
+        match prompt(&question, "n", parse_yes_no) {
+            Ok(true) => (),
+            Ok(false) => return Ok(()),
+            // e.g. from a script, which should say so with `--yes`
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => {
+                eprintln!();
+                return Err(io::Error::other("Stopped nothing, pass --yes to stop without asking"));
+            },
+            Err(err) => return Err(err),
+        }
+    }
+    let server_ids = rows.into_iter().map(|[server_id, ..]| server_id).collect();
+    stop_servers(server_ids, quiet, config)
+}
+
+/// Stops the servers, going on with the others when one can't be
+fn stop_servers(server_ids: Vec<String>, quiet: bool, config: &Config) -> io::Result<()> {
+    let mut failures = 0;
+    for server_id in server_ids {
+        if let Err(err) = stop_server(&server_id, quiet, config) {
+            eprintln!("Couldn't stop server {server_id}: {err}");
+            failures += 1;
+        }
+    }
+    match failures {
+        0 => Ok(()),
+        1 => Err(io::Error::other("A server couldn't be stopped")),
+        count => Err(io::Error::other(format!("{count} servers couldn't be stopped"))),
+    }
+}
+
+/// Stops the server, or removes its files if it's gone without removing them
+fn stop_server(server_id: &str, quiet: bool, config: &Config) -> io::Result<()> {
+    let err = match request(server_id, pomodoro_clock::Request::Stop, config) {
+        Ok(_) => {
+            if !quiet {
+                println!("Stopped server {server_id}");
+            }
+            return Ok(());
+        },
+        Err(err) => err,
+    };
+    let pid_path = config.pid_path(server_id);
+    // a refused connection only means the server is gone already, while one that
+    // doesn't respond may just be busy, and can't be reached again without its socket
+    if !matches!(err.kind(), io::ErrorKind::NotFound | io::ErrorKind::ConnectionRefused) {
+        match read_pid(&pid_path) {
+            Some(pid) if !is_process_running(pid) => (),
+            Some(pid) => return Err(io::Error::other(format!("It's not responding ({err}), but process {pid} is still running"))),
+            None => return Err(io::Error::other(format!("It's not responding ({err}), and has no pid file to tell if it's gone"))),
+        }
+    }
+    let server_path = config.server_addr(server_id)?.as_pathname().map(Path::to_path_buf);
+    for path in server_path.into_iter().chain([pid_path]) {
+        match fs::remove_file(&path) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => {
+                return Err(io::Error::new(err.kind(), format!("Couldn't remove {}: {err}", path.display())));
+            },
+            _ => (),
+        }
+    }
+    if !quiet {
+        println!("Removed the files of server {server_id}");
+    }
+    Ok(())
+}
+
+/// Process id written by a server in its pid file
+fn read_pid(pid_path: &Path) -> Option<libc::pid_t> {
+    fs::read_to_string(pid_path).ok()?.trim().parse().ok().filter(|pid| *pid > 0)
+}
+
+fn is_process_running(pid: libc::pid_t) -> bool {
+    // SAFETY: signal 0 is never delivered, kill only checks the process exists
+    let result = unsafe { libc::kill(pid, 0) };
+    // a process of another user exists just as well
+    result == 0 || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+/// Name of `kind` as written in the config, e.g. `long-break`
+fn kind_name(kind: SessionKind) -> String {
+    serde_json::to_value(kind)
+        .ok()
+        .and_then(|kind| kind.as_str().map(String::from))
+        .unwrap_or_default()
+}
+
+/// Prints rows under a header, each column as wide as its widest cell
+fn print_table<const N: usize>(header: [&str; N], rows: &[[String; N]]) {
+    let mut widths = header.map(str::len);
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let print_row = |cells: [&str; N]| {
+        let line: Vec<String> = cells
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{cell:width$}"))
+            .collect();
+        println!("{}", line.join("  ").trim_end());
+    };
+    print_row(header);
+    for row in rows {
+        print_row(row.each_ref().map(String::as_str));
+    }
+}
+
+/// Talks to the server over the socket file given with `--socket`, if any
+fn use_socket_path(config: &mut Config, socket_path: Option<PathBuf>) {
+    if socket_path.is_some() {
+        config.socket_path = socket_path;
+        config.socket_kind = SocketKind::File;
+    }
+}
+
+/// Runs the server again in the background with the same arguments, its output
+/// going to `log_path`, returning once it's listening
+fn daemonize(server_id: &str, log_path: &Path, config: &Config) -> io::Result<()> {
+    const STARTUP_TIMEOUT: Duration = Duration::from_secs(5);
+
+    let log = fs::OpenOptions::new().create(true).append(true).open(log_path)?;
+    let mut server = std::process::Command::new(std::env::current_exe()?)
+        .args(std::env::args_os().skip(1).filter(|arg| arg != "--daemon"))
+        .stdin(Stdio::null())
+        .stdout(log.try_clone()?)
+        .stderr(log)
+        // out of the terminal's process group, so that it doesn't get its signals
+        .process_group(0)
+        .spawn()?;
+
+    let server_addr = config.server_addr(server_id)?;
+    let deadline = Instant::now() + STARTUP_TIMEOUT;
+    while !socket::is_server_alive(&server_addr) {
+        if server.try_wait()?.is_some() {
+            return Err(io::Error::other(format!(
+                "Server {server_id} exited, see '{}'",
+                log_path.display(),
+            )));
+        }
+        if Instant::now() > deadline {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("Server {server_id} did not start listening, see '{}'", log_path.display()),
+            ));
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    Ok(())
+}
+
+/// Sends the request to the server with the id, or at `host` over TCP
+fn send(
+    server_id: &str,
+    host: Option<&str>,
+    protocol: Protocol,
+    timer: Option<&str>,
+    request: &Request,
+    json: bool,
+    config: &Config,
+) -> io::Result<()> {
+    let (mut connection, client_addr) = connect(server_id, host, protocol, config)
+        .map_err(|err| not_running(server_id, err))?;
+    let result = exchange(&mut connection, timer, request, json, server_id, config);
+
+    // the client socket is left behind when subscribed, until interrupted
+    if let Some(client_path) = client_addr.as_ref().and_then(|addr| addr.as_pathname()) {
+        fs::remove_file(client_path)?;
+    }
+    let status = result.map_err(|err| not_running(server_id, err))?;
+    if status != 0 {
+        std::process::exit(status);
+    }
     Ok(())
 }
 
+/// Values of the `fields` of `source` separated by tabs, with lists joined by commas
+/// and the fields that aren't set left empty
+fn tab_separated(source: &TemplateSource, fields: &[String]) -> io::Result<String> {
+    let serde_json::Value::Object(values) = serde_json::to_value(source)? else {
+        unreachable!("Template sources serialize to objects");
+    };
+    let format = |value: &serde_json::Value| match value {
+        serde_json::Value::Null => String::new(),
+        serde_json::Value::String(value) => value.clone(),
+        serde_json::Value::Array(values) => values
+            .iter()
+            .map(|value| value.as_str().map_or_else(|| value.to_string(), String::from))
+            .collect::<Vec<_>>()
+            .join(","),
+        value => value.to_string(),
+    };
+    let values = fields
+        .iter()
+        .map(|field| values
+            .get(field)
+            .map(format)
+            .ok_or_else(|| io::Error::other(format!("There's no field named '{field}'"))))
+        .collect::<io::Result<Vec<_>>>()?;
+    Ok(values.join("\t"))
+}
+
+fn clock_exit_status(state: &PomodoroState) -> i32 {
+    if state.is_paused || state.is_finished {
+        EXIT_PAUSED
+    } else if state.session_kind.is_break() {
+        EXIT_BREAK
+    } else {
+        0
+    }
+}
+
+/// Tells that the server isn't running for errors connecting to a socket nobody's bound to
+fn not_running(server_id: &str, err: io::Error) -> io::Error {
+    match err.kind() {
+        io::ErrorKind::NotFound | io::ErrorKind::ConnectionRefused => io::Error::new(
+            io::ErrorKind::NotConnected,
+            format!("Server {server_id} is not running"),
+        ),
+        _ => err,
+    }
+}
+
+/// Connects to the server with the id, or at `host` over TCP, along with
+/// the address of the client socket bound for it if any
+fn connect(
+    server_id: &str,
+    host: Option<&str>,
+    protocol: Protocol,
+    config: &Config,
+) -> io::Result<(Connection, Option<SocketAddr>)> {
+    let random_digits = |len: usize| -> String {
+        let mut rng = rand::thread_rng();
+        (0..len)
+            .map(|_| rng.gen_range('0'..='9'))
+            .collect()
+    };
+
+    log::debug!("Connecting to server {server_id} over {:?}", host.map_or(config.socket_type, |_| SocketType::Stream));
+    match (host, config.socket_type) {
+        (Some(host), _) => Ok((Connection::tcp(host, protocol)?.with_token(config.auth_token.clone()), None)),
+        (None, SocketType::Stream) => {
+            Ok((Connection::unix_stream(&config.server_addr(server_id)?, protocol)?, None))
+        },
+        // bind to an unused client socket name
+        (None, SocketType::Datagram) => loop {
+            let addr = config.socket_addr(&format!("client{}.sock", random_digits(6)))?;
+            if addr.as_pathname().is_some_and(|path| path.exists()) {
+                continue;
+            }
+            match Connection::unix(&addr, config.server_addr(server_id)?, protocol) {
+                Err(err) if err.kind() == io::ErrorKind::AddrInUse => continue,
+                connection => break Ok((connection?, Some(addr))),
+            }
+        },
+    }
+}
+
+/// Sends a single request to the server with the id, retrying it if it's safe to
+fn request(server_id: &str, request: pomodoro_clock::Request, config: &Config) -> io::Result<Response> {
+    let (mut connection, client_addr) = connect(server_id, None, config.protocol, config)?;
+    connection.set_timeout(Some(config.response_timeout))?;
+    let retries = if request.is_repeatable() { config.retries } else { 0 };
+    let request = TimerRequest { timer: None, request };
+    let response = connection.request(&request, retries).map(|response: TimerResponse| response.response);
+    if let Some(client_path) = client_addr.as_ref().and_then(|addr| addr.as_pathname()) {
+        fs::remove_file(client_path)?;
+    }
+    response
+}
+
+/// Stops the server running at `server_addr`, returning its progress
+/// once it's gone, along with its socket
+fn take_over(server_id: &str, server_addr: &SocketAddr, config: &Config) -> io::Result<Handover> {
+    const TAKEOVER_TIMEOUT: Duration = Duration::from_secs(5);
+
+    let Response::Handover(handover) = request(server_id, pomodoro_clock::Request::Handover, config)? else {
+        unreachable!();
+    };
+
+    let deadline = Instant::now() + TAKEOVER_TIMEOUT;
+    while socket::is_server_alive(server_addr) || server_addr.as_pathname().is_some_and(Path::exists) {
+        if Instant::now() > deadline {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("Server {server_id} did not exit"),
+            ));
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    Ok(handover)
+}
+
+/// Sends the request to the timer and prints whatever the response should be rendered into,
+/// returning the status to exit with
+fn exchange(
+    connection: &mut Connection,
+    timer: Option<&str>,
+    request: &Request,
+    json: bool,
+    server_id: &str,
+    config: &Config,
+) -> io::Result<i32> {
+    let to_json = |response: Response| match response {
+        Response::State(state) => serde_json::to_string(&state),
+        response => serde_json::to_string(&response),
+    }.expect("Responses serialize to JSON");
+    let fields = match request {
+        Request::Fetch(FetchArgs { fields: Some(fields), .. }) if !json => Some(fields),
+        _ => None,
+    };
+    // only looked up when there's something to render it with
+    let template = match request {
+        Request::Fetch(FetchArgs { template, template_name, .. }) | Request::Subscribe { template, template_name }
+            if !json && fields.is_none() => {
+            match template {
+                Some(template) => Some(template.clone()),
+                None => {
+                    let template = config.template(template_name.as_deref())?.unwrap_or(STATUS_TEMPLATE);
+                    Some(mustache::compile_str(template).map_err(|err| io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("Invalid template in the config: {err}"),
+                    ))?)
+                },
+            }
+        },
+        _ => None,
+    };
+    let render = |response: Response| {
+        if let Some(fields) = fields {
+            let Response::State(state) = response else { unreachable!(); };
+            return tab_separated(&TemplateSource::new(server_id, state, config), fields);
+        }
+        let Some(template) = &template else {
+            return Ok(to_json(response));
+        };
+        let Response::State(state) = response else { unreachable!(); };
+        Ok(template.render_to_string(&TemplateSource::new(server_id, state, config))
+            .expect("Couldn't populate mustache template"))
+    };
+
+    let pomodoro_request = pomodoro_clock::Request::from(request);
+    let retries = if pomodoro_request.is_repeatable() { config.retries } else { 0 };
+    let timer_request = TimerRequest { timer: timer.map(String::from), request: pomodoro_request };
+    connection.set_timeout(Some(config.response_timeout))?;
+    let response: TimerResponse = connection.request(&timer_request, retries)?;
+    match response.response {
+        // refused, e.g. pausing in strict mode or fetching a timer that doesn't exist
+        Response::Confirmation(Err(err)) if !json => return Err(io::Error::other(err)),
+        _ => (),
+    }
+    match request {
+        Request::Subscribe { .. } | Request::Fetch(FetchArgs { follow: true, interval: None, .. }) => {
+            // updates only come when something changes, however long that takes
+            connection.set_timeout(None)?;
+            println!("{}", render(response.response)?);
+            loop {
+                // every timer of the server publishes to the same subscribers
+                let update: TimerResponse = connection.receive()?;
+                if update.timer.as_deref() == timer {
+                    println!("{}", render(update.response)?);
+                }
+            }
+        },
+        Request::Fetch(FetchArgs { follow: true, interval: Some(interval), .. }) => {
+            let mut last = render(response.response)?;
+            println!("{last}");
+            loop {
+                std::thread::sleep(*interval);
+                let response: TimerResponse = connection.request(&timer_request, retries)?;
+                let output = render(response.response)?;
+                // lines only come when something changes, as with pushed updates
+                if output != last {
+                    println!("{output}");
+                    last = output;
+                }
+            }
+        },
+        Request::Fetch(FetchArgs { exit_status, .. }) => {
+            let status = match &response.response {
+                Response::State(state) if *exit_status => clock_exit_status(state),
+                _ => 0,
+            };
+            println!("{}", render(response.response)?);
+            return Ok(status);
+        },
+        _ if json => println!("{}", to_json(response.response)),
+        _ => if let Response::Reset(discarded) = response.response {
+            if !config.quiet {
+                println!("{discarded}");
+            }
+        },
+    }
+    Ok(0)
+}
+
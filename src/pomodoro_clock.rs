@@ -1,23 +1,105 @@
 use super::cli;
-use super::config::Session;
-use super::socket::{ServerState, ServerAction};
+use super::config::{self, ActiveHours, Session, SessionKind};
+use super::socket::{ServerState, ServerAction, UserSignal};
+use super::time_source::{ClockSource, SuspendPolicy, TimeSource};
 
 use serde::{Serialize, Deserialize};
-use chrono::NaiveTime;
+use chrono::{DateTime, Local, NaiveDate, NaiveTime, SecondsFormat, TimeDelta};
 
+use std::borrow::Cow;
 use std::fmt;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::path::PathBuf;
 use std::time::{Duration, Instant};
 use std::error::Error;
-use std::ops::Range;
+use std::str::FromStr;
 
+/// Number of sessions listed in `PomodoroState::upcoming_sessions`
+const UPCOMING_SESSIONS: usize = 5;
 
+/// Fingerprint of `sessions`, telling whether two servers or configs run the same cycle,
+/// only comparable between builds of the same Rust version
+pub fn sessions_hash<'a>(sessions: impl IntoIterator<Item = &'a Session>) -> String {
+    let mut hasher = DefaultHasher::new();
+    for session in sessions {
+        session.hash(&mut hasher);
+    }
+    format!("{:016x}", hasher.finish())
+}
+
+/// Time from `started` until the following occurrence of `end_at` in local time
+fn duration_until_time_of_day(end_at: NaiveTime, started: Instant) -> Duration {
+    let now = Instant::now();
+    let started = if started <= now {
+        Local::now() - (now - started)
+    } else {
+        Local::now() + (started - now)
+    };
+    let started = started.naive_local();
+    let mut end = started.date().and_time(end_at);
+    if end <= started {
+        end += TimeDelta::days(1);
+    }
+    (end - started).to_std().unwrap_or_default()
+}
+
+/// Formats `duration` with `strftime`-like specifiers:
+/// - `%H` hours, never wrapping around (`00`, `07`, `26`, `100`)
+/// - `%M` minutes of the hour (`00`..`59`), `%S` seconds of the minute (`00`..`59`)
+/// - `%h`, `%m`, `%s` total hours, minutes and seconds, without padding
+/// - `%f` milliseconds of the second (`000`..`999`)
+/// - `%T` same as `%H:%M:%S`, `%R` same as `%H:%M`
+/// - `%%` a literal `%`
+///
+/// Unknown specifiers are left as they are
 pub fn duration_fmt(duration: Duration, fmt: &str) -> String {
-    let seconds = (duration.as_secs() % 60) as u32;
-    let minutes = ((duration.as_secs() % 3600) / 60) as u32;
-    let hours = (duration.as_secs() / 3600) as u32;
+    let total_seconds = duration.as_secs();
+    let seconds = total_seconds % 60;
+    let minutes = (total_seconds % 3600) / 60;
+    let hours = total_seconds / 3600;
+
+    let mut output = String::with_capacity(fmt.len());
+    let mut chars = fmt.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            output.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('H') => output.push_str(&format!("{hours:02}")),
+            Some('M') => output.push_str(&format!("{minutes:02}")),
+            Some('S') => output.push_str(&format!("{seconds:02}")),
+            Some('h') => output.push_str(&hours.to_string()),
+            Some('m') => output.push_str(&(total_seconds / 60).to_string()),
+            Some('s') => output.push_str(&total_seconds.to_string()),
+            Some('f') => output.push_str(&format!("{:03}", duration.subsec_millis())),
+            Some('T') => output.push_str(&format!("{hours:02}:{minutes:02}:{seconds:02}")),
+            Some('R') => output.push_str(&format!("{hours:02}:{minutes:02}")),
+            Some('%') => output.push('%'),
+            Some(other) => {
+                output.push('%');
+                output.push(other);
+            },
+            None => output.push('%'),
+        }
+    }
+    output
+}
 
-    let time = NaiveTime::from_hms_opt(hours, minutes, seconds).unwrap();
-    time.format(fmt).to_string()
+/// Fails on the first specifier `duration_fmt` doesn't know and would print as is
+pub fn check_duration_fmt(fmt: &str) -> Result<(), String> {
+    let mut chars = fmt.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            continue;
+        }
+        match chars.next() {
+            Some('H' | 'M' | 'S' | 'h' | 'm' | 's' | 'f' | 'T' | 'R' | '%') => (),
+            Some(other) => return Err(format!("unknown specifier '%{other}'")),
+            None => return Err("lone '%' at the end".into()),
+        }
+    }
+    Ok(())
 }
 
 
@@ -33,6 +115,7 @@ impl fmt::Display for ClockError {
     }
 }
 
+#[derive(Clone, Copy)]
 enum Clock {
     Running { resumed: Instant, offset: Duration },
     Paused { elapsed: Duration },
@@ -60,15 +143,14 @@ impl Clock {
         }
     }
 
-    pub fn skip_by(&self, time: Duration) -> Self {
+    /// Keeps the clock running or paused, but sets its elapsed time at `now`
+    pub fn with_elapsed(&self, now: Instant, elapsed: Duration) -> Self {
         match *self {
-            Self::Running { resumed, offset } => Self::Running {
-                resumed,
-                offset: offset + time,
-            },
-            Self::Paused { elapsed } => Self::Paused {
-                elapsed: elapsed + time,
+            Self::Running { .. } => Self::Running {
+                resumed: now,
+                offset: elapsed,
             },
+            Self::Paused { .. } => Self::Paused { elapsed },
         }
     }
 }
@@ -77,242 +159,2412 @@ impl Clock {
 #[cfg_attr(test, derive(PartialEq))]
 pub struct PomodoroState {
     pub is_paused: bool,
+    /// All of the configured cycles have elapsed
+    pub is_finished: bool,
     pub time: String,
     pub session_name: String,
+    pub session_kind: SessionKind,
     pub session_duration: String,
+    /// Session coming after the current one, skipping the ones taking no time,
+    /// `None` once the last cycle is under way
+    pub next_session: Option<String>,
+    /// Sessions coming after the current one, up to a few, like `next_session`
+    pub upcoming_sessions: Vec<String>,
     pub percent: u32,
+    /// Unrounded `percent`, only with sub-second precision enabled
+    pub exact_percent: Option<f64>,
+    /// Time left in fractional seconds, only with sub-second precision enabled
+    pub exact_time: Option<f64>,
+    /// Time counted past the end of the session
+    pub overtime: Option<String>,
+    /// Index of the current checkpoint interval within the session, if it has any
+    pub checkpoint_index: Option<u32>,
+    /// Time left until the next checkpoint, or the end of the session
+    pub checkpoint_time: Option<String>,
+    /// Interruptions from within recorded against the current work session
+    pub internal_interruptions: u32,
+    /// Interruptions from others recorded against the current work session
+    pub external_interruptions: u32,
+    /// Number of work sessions completed since the server started
+    pub pomodoros_done: u32,
+    /// Number of work sessions completed today
+    pub goal_done: u32,
+    /// Number of work sessions to complete every day
+    pub goal_total: Option<u32>,
+    /// The cycle is suspended by an interrupt session
+    pub is_interrupted: bool,
+    /// The session has just started on its own, but isn't counting down yet
+    pub is_in_transition: bool,
+    /// The clock has been paused for being outside of the active hours
+    pub is_suspended_by_schedule: bool,
+    /// Local time the server was started at, in RFC 3339
+    pub started_at: String,
+    /// Time the server has been running for
+    pub uptime: String,
+}
+
+fn exact_percent(elapsed: Duration, duration: Duration) -> f64 {
+    let fraction = elapsed.as_secs_f64() / duration.as_secs_f64();
+    if fraction.is_infinite() || fraction.is_nan() {
+        0.0
+    } else {
+        fraction.min(1.0) * 100.0
+    }
+}
+
+fn percent(elapsed: Duration, duration: Duration) -> u32 {
+    exact_percent(elapsed, duration) as u32
+}
+
+/// Session suspending the cycle until explicitly ended
+#[derive(Clone)]
+struct Interrupt {
+    name: String,
+    /// Counts up if `None`
+    duration: Option<Duration>,
+    clock: Clock,
+    /// The cycle's clock was running when interrupted
+    was_running: bool,
+}
+
+impl Interrupt {
+    fn state_at(&self, instant: Instant, time_format: &str, pomodoros_done: u32) -> Result<PomodoroState, ClockError> {
+        let elapsed = self.clock.duration_until(instant)?;
+        let (duration, time) = match self.duration {
+            Some(duration) => (duration, duration.checked_sub(elapsed).unwrap_or_default()),
+            None => (elapsed, elapsed),
+        };
+        Ok(PomodoroState {
+            is_paused: matches!(self.clock, Clock::Paused { .. }),
+            is_finished: false,
+            time: duration_fmt(time, time_format),
+            session_name: self.name.clone(),
+            session_kind: SessionKind::Interrupt,
+            session_duration: duration_fmt(duration, time_format),
+            next_session: None,
+            upcoming_sessions: Vec::new(),
+            percent: self.duration.map_or(0, |duration| percent(elapsed, duration)),
+            exact_percent: Some(self.duration.map_or(0.0, |duration| exact_percent(elapsed, duration))),
+            exact_time: Some(time.as_secs_f64()),
+            overtime: (elapsed > duration).then(|| duration_fmt(elapsed - duration, time_format)),
+            checkpoint_index: None,
+            checkpoint_time: None,
+            internal_interruptions: 0,
+            external_interruptions: 0,
+            pomodoros_done,
+            goal_done: 0,
+            goal_total: None,
+            is_interrupted: true,
+            is_in_transition: false,
+            is_suspended_by_schedule: false,
+            started_at: String::new(),
+            uptime: String::new(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum InterruptionKind {
+    Internal,
+    External,
+}
+
+/// Interruptions recorded against a single session
+#[derive(Debug, Clone, Copy, Default)]
+struct Interruptions {
+    /// Completed cycles and index of the session they were recorded against
+    session: (u32, usize),
+    internal: u32,
+    external: u32,
+}
+
+/// Position of the clock within the cycle
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Position {
+    /// Index of the current session
+    index: usize,
+    /// Time elapsed within the current session
+    elapsed: Duration,
+    /// Time added on top of the current session's duration
+    extension: Duration,
+    /// Grace period preceding the current session, part of `elapsed`
+    grace: Duration,
+    /// Number of fully completed cycles
+    cycles_done: u32,
+    /// Number of work sessions run until their end
+    pomodoros_done: u32,
+    /// The current session is `queue[queue_consumed]` rather than `sessions[index]`
+    in_queue: bool,
+    /// Number of queued sessions already passed
+    queue_consumed: usize,
+    /// Duration of the current session resolved from its `end_at` when it started
+    end_at_duration: Option<Duration>,
+    /// The clock has to be paused at the start of the current session
+    paused_on_start: bool,
+}
+
+/// Progress of a server handed over to the one taking over from it
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Handover {
+    pub session_name: String,
+    /// Time elapsed within the current session, past its grace period
+    elapsed: Duration,
+    extension: Duration,
+    cycles_done: u32,
+    pomodoros_done: u32,
+    /// Part of `pomodoros_done` counting towards today's goal
+    goal_done: u32,
+    is_paused: bool,
+}
+
+/// Everything an undoable request may change
+struct Snapshot<'a> {
+    clock: Clock,
+    sessions: Vec<Cow<'a, Session>>,
+    session_index: usize,
+    extension: Duration,
+    grace: Duration,
+    end_at_duration: Option<Duration>,
+    cycles_done: u32,
+    pomodoros_done: u32,
+    in_queue: bool,
+    queue: Vec<Session>,
+    interrupt: Option<Interrupt>,
+    interruptions: Interruptions,
+    auto_skip_breaks: bool,
+    is_suspended_by_schedule: bool,
 }
 
 pub struct PomodoroClock<'a> {
+    /// Measures time elapsed within the current session
     clock: Clock,
+    /// Index of the current session, as of the last `advance`
+    session_index: usize,
+    /// Time added on top of the current session's duration
+    extension: Duration,
+    grace: Duration,
+    end_at_duration: Option<Duration>,
+    cycles_done: u32,
+    pomodoros_done: u32,
+    /// The current session is the front of `queue`
+    in_queue: bool,
     default_time_format: &'a str,
-    sessions: Vec<&'a Session>,
+    /// Sessions of the cycle, owned once edited
+    sessions: Vec<Cow<'a, Session>>,
+    /// One-off sessions run after the current one, before the cycle continues
+    queue: Vec<Session>,
+    interrupt: Option<Interrupt>,
+    interruptions: Interruptions,
+    cycles: Option<u32>,
+    auto_skip_breaks: bool,
+    overtime: bool,
+    strict: bool,
+    stop_when_finished: bool,
+    /// Time before a session following an automatic transition starts counting
+    transition_grace: Duration,
+    /// Reports fractional percent and time in the state
+    precise: bool,
+    time_source: TimeSource,
+    suspend_policy: SuspendPolicy,
+    daily_goal: Option<u32>,
+    /// Local date the daily goal is tracked for
+    goal_day: NaiveDate,
+    /// Value of `pomodoros_done` at the start of `goal_day`
+    pomodoros_before_goal_day: u32,
+    active_hours: Option<ActiveHours>,
+    /// The local time was within the active hours as of the last check
+    was_active: bool,
+    /// The clock has been paused by leaving the active hours, and is going
+    /// to be resumed when entering them again
+    is_suspended_by_schedule: bool,
+    /// States preceding the most recent undoable requests, latest last
+    history: Vec<Snapshot<'a>>,
+    /// Changed since the state was last published to subscribers
+    has_changed: bool,
+    /// Wall and monotonic time the clock was created at
+    started_at: (DateTime<Local>, Instant),
+    /// Config file the sessions were read from, `None` for the defaults
+    config_path: Option<PathBuf>,
+    /// Address of the server, as reported by `Request::Info`
+    socket: String,
 }
 
 impl<'a> PomodoroClock<'a> {
     const NO_SESSIONS_MSG: &'static str = "There should be at least one session defined";
+    /// Going back later than this into a session only restarts it
+    const BACK_RESTART_THRESHOLD: Duration = Duration::from_secs(3);
+    /// Number of requests that can be undone
+    const HISTORY_LEN: usize = 16;
 
     pub fn paused(sessions: impl Iterator<Item = &'a Session>, default_time_format: &'a str) -> Self {
         Self {
             clock: Clock::Paused { elapsed: Duration::ZERO },
+            session_index: 0,
+            extension: Duration::ZERO,
+            grace: Duration::ZERO,
+            end_at_duration: None,
+            cycles_done: 0,
+            pomodoros_done: 0,
+            in_queue: false,
             default_time_format,
-            sessions: sessions.collect(),
+            sessions: sessions.map(Cow::Borrowed).collect(),
+            queue: Vec::new(),
+            interrupt: None,
+            interruptions: Interruptions::default(),
+            cycles: None,
+            auto_skip_breaks: false,
+            overtime: false,
+            strict: false,
+            stop_when_finished: false,
+            transition_grace: Duration::ZERO,
+            precise: false,
+            time_source: TimeSource::default(),
+            suspend_policy: SuspendPolicy::default(),
+            daily_goal: None,
+            goal_day: Local::now().date_naive(),
+            pomodoros_before_goal_day: 0,
+            active_hours: None,
+            was_active: true,
+            is_suspended_by_schedule: false,
+            history: Vec::new(),
+            has_changed: false,
+            started_at: (Local::now(), Instant::now()),
+            config_path: None,
+            socket: String::new(),
+        }
+    }
+
+    pub fn running(sessions: impl Iterator<Item = &'a Session>, default_time_format: &'a str, now: Instant) -> Self {
+        Self {
+            clock: Clock::Running { resumed: now, offset: Duration::ZERO },
+            ..Self::paused(sessions, default_time_format)
+        }
+    }
+
+    /// Finishes the clock after `cycles` full cycles, runs forever if `None`
+    pub fn with_cycles(self, cycles: Option<u32>) -> Self {
+        Self { cycles, ..self }
+    }
+
+    pub fn with_auto_skip_breaks(self, auto_skip_breaks: bool) -> Self {
+        Self { auto_skip_breaks, ..self }
+    }
+
+    /// Keeps counting past the end of a session until it's skipped
+    pub fn with_overtime(self, overtime: bool) -> Self {
+        Self { overtime, ..self }
+    }
+
+    /// Refuses pausing running work sessions
+    pub fn with_strict(self, strict: bool) -> Self {
+        Self { strict, ..self }
+    }
+
+    /// Delays sessions following automatic transitions by `transition_grace`,
+    /// during which they can be skipped before they really start
+    pub fn with_transition_grace(self, transition_grace: Duration) -> Self {
+        Self { transition_grace, ..self }
+    }
+
+    /// Reports percent and time with sub-second precision, for smooth animations
+    pub fn with_precise(self, precise: bool) -> Self {
+        Self { precise, ..self }
+    }
+
+    pub fn with_time_source(self, time_source: TimeSource) -> Self {
+        Self { time_source, ..self }
+    }
+
+    pub fn with_suspend_policy(self, suspend_policy: SuspendPolicy) -> Self {
+        Self { suspend_policy, ..self }
+    }
+
+    /// Number of work sessions to complete every day
+    pub fn with_daily_goal(self, daily_goal: Option<u32>) -> Self {
+        Self { daily_goal, ..self }
+    }
+
+    /// Pauses the clock outside of `active_hours`, and resumes it inside of them
+    pub fn with_active_hours(self, active_hours: Option<ActiveHours>) -> Self {
+        Self { active_hours, ..self }
+    }
+
+    /// Stops the server as soon as all of the cycles have elapsed
+    pub fn with_stop_when_finished(self, stop_when_finished: bool) -> Self {
+        Self { stop_when_finished, ..self }
+    }
+
+    /// Config file reported by `Request::Info`
+    pub fn with_config_path(self, config_path: Option<PathBuf>) -> Self {
+        Self { config_path, ..self }
+    }
+
+    /// Socket address reported by `Request::Info`
+    pub fn with_socket(self, socket: String) -> Self {
+        Self { socket, ..self }
+    }
+
+    /// Duration `session` actually lasts, taking auto-skipping into account,
+    /// `None` for sessions counting up until skipped
+    fn effective_duration(&self, session: &Session) -> Option<Duration> {
+        if self.auto_skip_breaks && session.kind.is_break() {
+            Some(Duration::ZERO)
+        } else {
+            session.duration
+        }
+    }
+
+    fn session_at(&self, position: &Position) -> &Session {
+        if position.in_queue {
+            &self.queue[position.queue_consumed]
+        } else {
+            self.sessions
+                .get(position.index)
+                .expect(Self::NO_SESSIONS_MSG)
+        }
+    }
+
+    /// Duration of the session `position` is in, including its extension
+    fn position_duration(&self, position: &Position) -> Option<Duration> {
+        let session = self.session_at(position);
+        let duration = if self.auto_skip_breaks && session.kind.is_break() {
+            Some(Duration::ZERO)
+        } else {
+            position.end_at_duration.or(session.duration)
+        };
+        duration.map(|duration| duration + position.extension + position.grace)
+    }
+
+    /// Grace period preceding `position`'s session when transitioned to on its own
+    fn grace_before(&self, position: &Position) -> Duration {
+        if self.effective_duration(self.session_at(position)) == Some(Duration::ZERO) {
+            Duration::ZERO
+        } else {
+            self.transition_grace
         }
     }
 
-    fn sessions_bounds(&self) -> impl Iterator<Item = Range<Duration>> + '_ {
+    /// Resolves the duration of a session ending at a time of day,
+    /// given it started at `started`
+    fn resolve_end_at(&self, position: Position, started: Instant) -> Position {
+        if position.end_at_duration.is_some() {
+            return position;
+        }
+        Position {
+            end_at_duration: self.session_at(&position).end_at
+                .map(|end_at| duration_until_time_of_day(end_at, started)),
+            ..position
+        }
+    }
+
+    /// Every session of the cycle takes no time at all, so there's nothing to walk through
+    fn is_cycle_empty(&self) -> bool {
         self.sessions
             .iter()
-            .map(|session| session.duration)
-            .scan(Duration::ZERO, |pref_sum, duration| {
-                let bounds = *pref_sum..(*pref_sum + duration);
-                *pref_sum = bounds.end;
-                Some(bounds)
-            })
+            .all(|session| self.effective_duration(session) == Some(Duration::ZERO))
+    }
+
+    fn is_finished(&self, position: &Position) -> bool {
+        self.cycles.is_some_and(|cycles| position.cycles_done >= cycles)
     }
 
-    fn elapsed_until(&self, instant: Instant) -> Result<Duration, ClockError> {
-        fn duration_rem(dividend: Duration, divisor: Duration) -> Duration {
-            let nanos_per_sec = 1_000_000_000;
-            let nanos: u128 = dividend.as_nanos() % divisor.as_nanos();
-            Duration::new((nanos / nanos_per_sec) as u64, (nanos % nanos_per_sec) as u32)
+    /// Walks forward from the last `advance`d session through every session
+    /// boundary crossed until `instant`
+    fn position_at(&self, instant: Instant) -> Result<Position, ClockError> {
+        let elapsed = self.clock.duration_until(instant)?;
+        let mut position = self.resolve_end_at(Position {
+            index: self.session_index,
+            elapsed,
+            extension: self.extension,
+            grace: self.grace,
+            cycles_done: self.cycles_done,
+            pomodoros_done: self.pomodoros_done,
+            in_queue: self.in_queue,
+            queue_consumed: 0,
+            end_at_duration: self.end_at_duration,
+            paused_on_start: false,
+        }, instant.checked_sub(elapsed.saturating_sub(self.grace)).unwrap_or(instant));
+        if self.is_cycle_empty() || self.is_finished(&position) {
+            return Ok(position);
         }
-        let cycle_duration: Duration = self.sessions
-            .iter()
-            .map(|session| session.duration)
-            .sum();
-        let elapsed = duration_rem(
-            self.clock.duration_until(instant)?,
-            cycle_duration,
+
+        loop {
+            // sessions counting up only end when skipped
+            let Some(duration) = self.position_duration(&position) else {
+                break Ok(position);
+            };
+            // in overtime a session only ends when skipped, unless it takes no time at all
+            if position.elapsed < duration || (self.overtime && !duration.is_zero()) {
+                break Ok(position);
+            }
+
+            let next = self.next_position(&position);
+            if self.is_finished(&next) {
+                break Ok(next);
+            }
+            // time stops counting as soon as the session starts
+            if next.paused_on_start {
+                break Ok(self.resolve_end_at(next, instant));
+            }
+            let elapsed = position.elapsed - duration;
+            let grace = self.grace_before(&next);
+            position = self.resolve_end_at(
+                Position { elapsed, grace, ..next },
+                instant.checked_sub(elapsed.saturating_sub(grace)).unwrap_or(instant),
+            );
+        }
+    }
+
+    /// Position at the start of the session following `position`
+    fn next_position(&self, position: &Position) -> Position {
+        // work sessions skipped before their end don't count, unless they count up
+        let is_pomodoro_done = self.session_at(position).kind == SessionKind::Work
+            && self.position_duration(position).is_none_or(|duration| position.elapsed >= duration);
+        let pomodoros_done = position.pomodoros_done + is_pomodoro_done as u32;
+
+        let queue_consumed = position.queue_consumed + position.in_queue as usize;
+        if queue_consumed < self.queue.len() {
+            let paused_on_start = self.queue[queue_consumed].pause_on_start;
+            return Position {
+                elapsed: Duration::ZERO,
+                extension: Duration::ZERO,
+                grace: Duration::ZERO,
+                pomodoros_done,
+                in_queue: true,
+                queue_consumed,
+                end_at_duration: None,
+                paused_on_start,
+                ..*position
+            };
+        }
+
+        let is_last = position.index + 1 == self.sessions.len();
+        let cycles_done = position.cycles_done + is_last as u32;
+        if self.cycles.is_some_and(|cycles| cycles_done >= cycles) {
+            // a finished clock stays at the very end of the last session
+            return Position {
+                elapsed: self.position_duration(position).unwrap_or(position.elapsed),
+                cycles_done,
+                pomodoros_done,
+                paused_on_start: false,
+                ..*position
+            };
+        }
+        let index = (position.index + 1) % self.sessions.len();
+        Position {
+            index,
+            elapsed: Duration::ZERO,
+            extension: Duration::ZERO,
+            grace: Duration::ZERO,
+            cycles_done,
+            pomodoros_done,
+            in_queue: false,
+            queue_consumed,
+            end_at_duration: None,
+            paused_on_start: self.sessions[index].pause_on_start,
+        }
+    }
+
+    fn set_position(&mut self, now: Instant, position: &Position) {
+        let position = self.resolve_end_at(
+            *position,
+            now.checked_sub(position.elapsed.saturating_sub(position.grace)).unwrap_or(now),
         );
-        Ok(elapsed)
+        self.end_at_duration = position.end_at_duration;
+        self.queue.drain(..position.queue_consumed);
+        self.in_queue = position.in_queue;
+        self.session_index = position.index;
+        self.extension = position.extension;
+        self.grace = position.grace;
+        self.cycles_done = position.cycles_done;
+        self.pomodoros_done = position.pomodoros_done;
+        self.clock = if position.paused_on_start {
+            Clock::Paused { elapsed: position.elapsed }
+        } else {
+            self.clock.with_elapsed(now, position.elapsed)
+        };
+    }
+
+    /// Commits every session transition that happened until `now`
+    fn advance(&mut self, now: Instant) -> Result<Position, ClockError> {
+        let position = self.position_at(now)?;
+        self.set_position(now, &position);
+        Ok(Position { queue_consumed: 0, paused_on_start: false, ..position })
     }
 
     pub fn state_at(&self, instant: Instant) -> Result<PomodoroState, ClockError> {
-        let elapsed = self.elapsed_until(instant)?;
+        if let Some(interrupt) = &self.interrupt {
+            let state = interrupt.state_at(instant, self.default_time_format, self.pomodoros_done)?;
+            // ending the interrupt resumes the cycle where it was
+            let position = self.position_at(instant)?;
+            let mut upcoming_sessions = vec![self.session_at(&position).name.clone()];
+            upcoming_sessions.extend(self.upcoming_session_names(&position, UPCOMING_SESSIONS - 1));
+            return Ok(PomodoroState {
+                next_session: Some(self.session_at(&position).name.clone()),
+                upcoming_sessions,
+                exact_percent: state.exact_percent.filter(|_| self.precise),
+                exact_time: state.exact_time.filter(|_| self.precise),
+                goal_done: self.goal_done(self.pomodoros_done),
+                goal_total: self.daily_goal,
+                started_at: self.started_at(),
+                uptime: self.uptime(),
+                ..state
+            });
+        }
+        let position = self.position_at(instant)?;
 
-        let (session, time_left) = self.sessions
-            .iter()
-            .zip(self.sessions_bounds())
-            .map_while(|(session, bounds)| {
-                let is_current_session = bounds.contains(&elapsed);
-                (elapsed >= bounds.end || is_current_session).then(|| {
-                    let session_time_left = bounds.end
-                        .checked_sub(elapsed)
-                        .unwrap_or_default();
-                    (session, session_time_left)
-                })
-            })
-            .last()
-            .expect(Self::NO_SESSIONS_MSG);
+        let session = self.session_at(&position);
         let time_format = session.time_format
             .as_deref()
             .unwrap_or(self.default_time_format);
 
-        let percent = {
-            let elapsed = (session.duration - time_left).as_secs_f64();
-            let fraction = elapsed / session.duration.as_secs_f64();
-            if fraction.is_infinite() {
-                0
-            } else {
-                (fraction * 100.0) as u32
-            }
+        // the session only starts counting once its grace period is over
+        let elapsed = position.elapsed.saturating_sub(position.grace);
+        let (duration, time, exact_percent, overtime) = match position.end_at_duration.or(session.duration) {
+            Some(duration) => {
+                let duration = duration + position.extension;
+                let time_left = duration
+                    .checked_sub(elapsed)
+                    .unwrap_or_default();
+                let overtime = elapsed.checked_sub(duration)
+                    .filter(|overtime| !overtime.is_zero());
+                (duration, time_left, exact_percent(duration - time_left, duration), overtime)
+            },
+            // sessions counting up show the time elapsed so far
+            None => (elapsed, elapsed, 0.0, None),
         };
+        let interruptions = self.interruptions_at(&position);
+        let checkpoint = session.checkpoint_every
+            .filter(|every| !every.is_zero())
+            .map(|every| {
+                let index = (elapsed.as_nanos() / every.as_nanos()) as u32;
+                let mut time_left = every * (index + 1) - elapsed;
+                if session.duration.is_some() || position.end_at_duration.is_some() {
+                    time_left = time_left.min(time);
+                }
+                (index, time_left)
+            });
 
         Ok(PomodoroState {
-            is_paused: matches!(self.clock, Clock::Paused { .. }),
+            is_paused: matches!(self.clock, Clock::Paused { .. }) || position.paused_on_start,
+            is_finished: self.is_finished(&position),
             session_name: session.name.clone(),
-            session_duration: duration_fmt(session.duration, time_format),
-            time: duration_fmt(time_left, time_format),
-            percent,
+            session_kind: session.kind,
+            session_duration: duration_fmt(duration, time_format),
+            next_session: self.upcoming_session_names(&position, 1).pop(),
+            upcoming_sessions: self.upcoming_session_names(&position, UPCOMING_SESSIONS),
+            time: duration_fmt(time, time_format),
+            percent: exact_percent as u32,
+            exact_percent: self.precise.then_some(exact_percent),
+            exact_time: self.precise.then_some(time.as_secs_f64()),
+            overtime: overtime.map(|overtime| duration_fmt(overtime, time_format)),
+            checkpoint_index: checkpoint.map(|(index, _)| index),
+            checkpoint_time: checkpoint.map(|(_, time_left)| duration_fmt(time_left, time_format)),
+            internal_interruptions: interruptions.internal,
+            external_interruptions: interruptions.external,
+            pomodoros_done: position.pomodoros_done,
+            goal_done: self.goal_done(position.pomodoros_done),
+            goal_total: self.daily_goal,
+            is_interrupted: false,
+            is_in_transition: position.elapsed < position.grace,
+            is_suspended_by_schedule: self.is_suspended_by_schedule,
+            started_at: self.started_at(),
+            uptime: self.uptime(),
         })
     }
 
-    pub fn toggle(&mut self, now: Instant) -> Result<(), ClockError> {
-        self.clock = self.clock.toggle(now)?;
-        Ok(())
+    /// Names of up to `count` sessions after the one at `position`, skipping the ones taking no time
+    fn upcoming_session_names(&self, position: &Position, count: usize) -> Vec<String> {
+        let mut names = Vec::new();
+        if self.is_cycle_empty() {
+            return names;
+        }
+        let mut next = self.next_position(position);
+        while names.len() < count && !self.is_finished(&next) {
+            let session = self.session_at(&next);
+            if self.effective_duration(session) != Some(Duration::ZERO) {
+                names.push(session.name.clone());
+            }
+            next = self.next_position(&next);
+        }
+        names
     }
 
-    pub fn skip_session(&mut self, now: Instant) -> Result<(), ClockError> {
-        let elapsed = self.elapsed_until(now)?;
-        let session_bounds = self.sessions_bounds()
-            .take_while(|bounds| elapsed >= bounds.end || bounds.contains(&elapsed))
-            .last()
-            .expect(Self::NO_SESSIONS_MSG);
-        let skip_by = session_bounds.end - elapsed;
-        self.clock = self.clock.skip_by(skip_by);
-        Ok(())
+    fn started_at(&self) -> String {
+        self.started_at.0.to_rfc3339_opts(SecondsFormat::Secs, false)
     }
 
-    pub fn reset(&mut self) {
-        self.clock = Clock::Paused { elapsed: Duration::ZERO };
+    /// Real time since the clock was created, regardless of its time source
+    fn uptime(&self) -> String {
+        let uptime = Duration::from_secs(self.started_at.1.elapsed().as_secs());
+        humantime::format_duration(uptime).to_string()
     }
-}
-
-
-#[derive(Debug, Serialize, Deserialize)]
-pub enum Request {
-    Fetch,
-    Toggle,
-    Skip,
-    Reset,
-    Stop,
-}
 
-impl From<&cli::Request> for Request {
-    fn from(value: &cli::Request) -> Self {
-        match value {
-            cli::Request::Fetch { .. } => Self::Fetch,
-            cli::Request::Toggle => Self::Toggle,
-            cli::Request::Skip => Self::Skip,
-            cli::Request::Reset => Self::Reset,
-            cli::Request::Stop => Self::Stop,
+    fn info(&self) -> ServerInfo {
+        ServerInfo {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            pid: std::process::id(),
+            config_path: self.config_path.clone(),
+            socket: self.socket.clone(),
+            sessions_hash: sessions_hash(self.sessions.iter().map(|session| session.as_ref())),
+            started_at: self.started_at(),
+            uptime: self.uptime(),
         }
     }
-}
 
-#[derive(Debug, Serialize, Deserialize)]
-pub enum Response {
-    State(PomodoroState),
-    Confirmation(Result<(), String>),
-}
+    /// Sessions of the cycle as they are now, edits included
+    fn session_infos(&self, now: Instant) -> Result<Vec<SessionInfo>, ClockError> {
+        let position = self.position_at(now)?;
+        let is_in_cycle = self.interrupt.is_none() && !position.in_queue;
+        Ok(self.sessions
+            .iter()
+            .enumerate()
+            .map(|(index, session)| SessionInfo {
+                name: session.name.clone(),
+                duration: session.duration.map(|duration| humantime::format_duration(duration).to_string()),
+                end_at: session.end_at.map(config::format_time_of_day),
+                kind: session.kind,
+                time_format: session.time_format.as_deref().unwrap_or(self.default_time_format).to_string(),
+                is_current: is_in_cycle && index == position.index,
+            })
+            .collect())
+    }
 
+    fn goal_done(&self, pomodoros_done: u32) -> u32 {
+        pomodoros_done.saturating_sub(self.pomodoros_before_goal_day)
+    }
 
-impl ServerState for PomodoroClock<'_> {
-    type Request<'de> = Request;
-    type Response = Response;
+    /// Starts tracking the daily goal from scratch once the local date changes
+    fn roll_over_goal_day(&mut self, now: Instant) -> Result<(), ClockError> {
+        let today = Local::now().date_naive();
+        if today != self.goal_day {
+            self.advance(now)?;
+            self.goal_day = today;
+            self.pomodoros_before_goal_day = self.pomodoros_done;
+        }
+        Ok(())
+    }
 
-    fn update<'de>(&mut self, request: &Self::Request<'de>) -> ServerAction<Self::Response> {
-        let sys_clock_err_msg = "your system clock is prbly doomed, idk 💀";
-        let now = Instant::now();
+    /// Time until the session as of the last `advance` ends, zero if it already
+    /// has, `None` if nothing is going to end on its own
+    pub fn time_until_transition(&self, now: Instant) -> Result<Option<Duration>, ClockError> {
+        if matches!(self.clock, Clock::Paused { .. }) || self.interrupt.is_some() {
+            return Ok(None);
+        }
+        let position = Position {
+            index: self.session_index,
+            elapsed: self.clock.duration_until(now)?,
+            extension: self.extension,
+            grace: self.grace,
+            cycles_done: self.cycles_done,
+            pomodoros_done: self.pomodoros_done,
+            in_queue: self.in_queue,
+            queue_consumed: 0,
+            end_at_duration: self.end_at_duration,
+            paused_on_start: false,
+        };
+        if self.is_cycle_empty() || self.is_finished(&position) {
+            return Ok(None);
+        }
+        let time_left = self.position_duration(&position)
+            // in overtime a session only ends when skipped, unless it takes no time at all
+            .filter(|duration| !self.overtime || duration.is_zero())
+            .map(|duration| duration.saturating_sub(position.elapsed));
+        Ok(time_left)
+    }
 
-        match request {
-            Request::Toggle => {
-                self.toggle(now).expect(sys_clock_err_msg);
-                ServerAction::Respond(Response::Confirmation(Ok(())))
-            },
-            Request::Skip => {
-                self.skip_session(now).expect(sys_clock_err_msg);
-                ServerAction::Respond(Response::Confirmation(Ok(())))
+    /// Applies the suspend policy if the system has been suspended since the last check
+    fn compensate_suspend(&mut self) -> Result<(), ClockError> {
+        let Some(suspended) = self.time_source.detect_suspend() else {
+            return Ok(());
+        };
+        let suspended = self.time_source.clock_duration(suspended);
+        let now = self.time_source.now();
+        let is_running = matches!(self.clock, Clock::Running { .. });
+        let counts_suspend = matches!(self.time_source.clock_source(), ClockSource::Wall);
+
+        match self.suspend_policy {
+            SuspendPolicy::Continue if is_running && !counts_suspend => {
+                let mut position = self.advance(now)?;
+                position.elapsed += suspended;
+                self.set_position(now, &position);
+                self.advance(now)?;
             },
-            Request::Reset => {
-                self.reset();
-                ServerAction::Respond(Response::Confirmation(Ok(())))
-            }
-            Request::Fetch { .. } => {
-                let state = self.state_at(now).expect(sys_clock_err_msg);
-                ServerAction::Respond(Response::State(state))
+            SuspendPolicy::Pause if is_running => {
+                let mut position = self.advance(now)?;
+                if counts_suspend {
+                    position.elapsed = position.elapsed.saturating_sub(suspended);
+                    self.set_position(now, &position);
+                }
+                self.clock = self.clock.toggle(now)?;
             },
-            Request::Stop => ServerAction::StopRespond(Response::Confirmation(Ok(()))),
+            _ => (),
         }
+        Ok(())
     }
-}
 
+    /// Pauses the running clock when leaving the active hours, and resumes
+    /// it when entering them again, unless it's been toggled in the meantime
+    fn apply_active_hours(&mut self, now: Instant) -> Result<(), ClockError> {
+        let Some(active_hours) = self.active_hours else {
+            return Ok(());
+        };
+        let is_active = active_hours.contains(Local::now().time());
+        if is_active == self.was_active {
+            return Ok(());
+        }
+        self.was_active = is_active;
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+        let is_running = matches!(self.clock, Clock::Running { .. });
+        if !is_active && is_running && self.interrupt.is_none() {
+            self.advance(now)?;
+            self.clock = self.clock.toggle(now)?;
+            self.is_suspended_by_schedule = true;
+        } else if is_active && self.is_suspended_by_schedule {
+            self.clock = self.clock.toggle(now)?;
+            self.is_suspended_by_schedule = false;
+        }
+        Ok(())
+    }
 
-    #[test]
-    fn pomodoro_state_at() {
-        let sessions = vec![
-            Session {
-                name: "work1".into(),
-                duration: Duration::from_secs(200),
-                time_format: None,
-            },
-            Session {
-                name: "rest".into(),
-                duration: Duration::from_secs(100),
-                time_format: None,
-            },
-            Session {
-                name: "work2".into(),
-                duration: Duration::from_secs(200),
-                time_format: None,
-            },
-            Session {
-                name: "long rest".into(),
-                duration: Duration::from_secs(150),
-                time_format: None,
+    /// Real time until the local time enters or leaves the active hours
+    fn time_until_active_hours_edge(&self) -> Option<Duration> {
+        let ActiveHours { start, end } = self.active_hours?;
+        let now = Instant::now();
+        Some(duration_until_time_of_day(start, now).min(duration_until_time_of_day(end, now)))
+    }
+
+    /// Toggles the interrupt session if there's one, the cycle otherwise
+    pub fn toggle(&mut self, now: Instant) -> Result<(), ClockError> {
+        if let Some(interrupt) = &mut self.interrupt {
+            interrupt.clock = interrupt.clock.toggle(now)?;
+        } else {
+            self.advance(now)?;
+            self.clock = self.clock.toggle(now)?;
+            self.is_suspended_by_schedule = false;
+        }
+        Ok(())
+    }
+
+    /// Pauses or resumes the clock unless it already is, returns whether it was toggled
+    pub fn set_paused(&mut self, now: Instant, paused: bool) -> Result<bool, ClockError> {
+        let is_paused = match &self.interrupt {
+            Some(interrupt) => matches!(interrupt.clock, Clock::Paused { .. }),
+            // e.g. a session starting paused
+            None => {
+                self.advance(now)?;
+                matches!(self.clock, Clock::Paused { .. })
             },
-        ];
-        let pomodoro_clock = PomodoroClock {
-            clock: Clock::Paused { elapsed: Duration::from_secs(950) },
-            default_time_format: "%M:%S",
-            sessions: sessions.iter().collect(),
         };
+        if is_paused == paused {
+            return Ok(false);
+        }
+        self.toggle(now)?;
+        Ok(true)
+    }
 
-        assert_eq!(
-            pomodoro_clock.state_at(Instant::now()).unwrap(),
-            PomodoroState {
-                is_paused: true,
-                session_name: "work2".into(),
-                session_duration: "03:20".into(),
-                time: "03:20".into(),
-                percent: 0,
-            },
-        );
+    /// Suspends the cycle with a session lasting `duration`, or counting up if `None`,
+    /// returns `false` if it's already interrupted
+    pub fn interrupt(&mut self, now: Instant, name: String, duration: Option<Duration>) -> Result<bool, ClockError> {
+        if self.interrupt.is_some() {
+            return Ok(false);
+        }
+        self.advance(now)?;
+        let was_running = matches!(self.clock, Clock::Running { .. });
+        if was_running {
+            self.clock = self.clock.toggle(now)?;
+        }
+        self.interrupt = Some(Interrupt {
+            name,
+            duration,
+            clock: Clock::Running { resumed: now, offset: Duration::ZERO },
+            was_running,
+        });
+        Ok(true)
     }
 
-    #[test]
-    fn pomodoro_skip() {
-        let sessions = vec![
-            Session {
-                name: "work1".into(),
-                duration: Duration::from_secs(8),
-                time_format: None,
+    /// Resumes the cycle exactly where it was interrupted,
+    /// returns `false` if it isn't interrupted
+    pub fn end_interrupt(&mut self, now: Instant) -> Result<bool, ClockError> {
+        let Some(interrupt) = self.interrupt.take() else {
+            return Ok(false);
+        };
+        if interrupt.was_running {
+            self.clock = self.clock.toggle(now)?;
+        }
+        Ok(true)
+    }
+
+    /// Interruptions recorded against the session `position` is in
+    fn interruptions_at(&self, position: &Position) -> Interruptions {
+        let session = (position.cycles_done, position.index);
+        if position.in_queue || self.interruptions.session != session {
+            Interruptions { session, ..Interruptions::default() }
+        } else {
+            self.interruptions
+        }
+    }
+
+    /// Records an interruption against the current work session,
+    /// returns `false` if the current session isn't a work session
+    pub fn record_interruption(&mut self, now: Instant, kind: InterruptionKind) -> Result<bool, ClockError> {
+        let position = self.advance(now)?;
+        if self.interrupt.is_some() || self.session_at(&position).kind != SessionKind::Work {
+            return Ok(false);
+        }
+        let mut interruptions = self.interruptions_at(&position);
+        match kind {
+            InterruptionKind::Internal => interruptions.internal += 1,
+            InterruptionKind::External => interruptions.external += 1,
+        }
+        self.interruptions = interruptions;
+        Ok(true)
+    }
+
+    /// In strict mode a running work session can't be paused
+    pub fn is_pause_refused(&self, now: Instant) -> Result<bool, ClockError> {
+        let is_paused = matches!(self.clock, Clock::Paused { .. });
+        if !self.strict || is_paused || self.interrupt.is_some() {
+            return Ok(false);
+        }
+        let position = self.position_at(now)?;
+        Ok(!self.session_at(&position).kind.is_break())
+    }
+
+    /// Moves to the start of the `count`-th following session
+    pub fn skip_sessions(&mut self, now: Instant, count: u32) -> Result<(), ClockError> {
+        for _ in 0..count {
+            let position = self.advance(now)?;
+            if self.is_finished(&position) {
+                break;
+            }
+            self.set_position(now, &self.next_position(&position));
+        }
+        self.advance(now)?;
+        Ok(())
+    }
+
+    /// Extends or shortens the current session, shortening it past
+    /// its end moves on to the following sessions
+    pub fn adjust(&mut self, now: Instant, adjustment: Adjustment) -> Result<(), ClockError> {
+        let mut position = self.advance(now)?;
+        if self.is_finished(&position) {
+            return Ok(());
+        }
+        match adjustment {
+            Adjustment::Extend(duration) => position.extension += duration,
+            Adjustment::Shorten(duration) => {
+                // take back previous extensions first
+                let from_extension = duration.min(position.extension);
+                position.extension -= from_extension;
+                position.elapsed += duration - from_extension;
             },
-        ];
-        let mut pomodoro_clock = PomodoroClock {
-            clock: Clock::Paused { elapsed: Duration::from_secs_f32(5.07) },
-            default_time_format: "%M:%S",
-            sessions: sessions.iter().collect(),
+        }
+        self.set_position(now, &position);
+        self.advance(now)?;
+        Ok(())
+    }
+
+    /// Postpones the end of the current session by `duration`, counting
+    /// from now if the session is already in overtime
+    pub fn snooze(&mut self, now: Instant, duration: Duration) -> Result<(), ClockError> {
+        let mut position = self.advance(now)?;
+        let (Some(end), Some(session_duration)) = (
+            self.position_duration(&position),
+            self.position_duration(&Position { extension: Duration::ZERO, ..position }),
+        ) else {
+            // sessions counting up have no end to postpone
+            return Ok(());
         };
-        let _ = pomodoro_clock.skip_session(Instant::now());
-        assert_eq!(
-            pomodoro_clock.elapsed_until(Instant::now()).unwrap(),
-            Duration::from_secs(0),
-        );
+        if self.is_finished(&position) {
+            return Ok(());
+        }
+        position.extension = end.max(position.elapsed) + duration - session_duration;
+        self.set_position(now, &position);
+        Ok(())
+    }
+
+    /// Sets the time elapsed within the current session, or within
+    /// the whole current cycle if `within_cycle`
+    pub fn seek(&mut self, now: Instant, elapsed: Duration, within_cycle: bool) -> Result<(), ClockError> {
+        let position = self.advance(now)?;
+        let start = self.session_start(&position);
+        let target = if within_cycle {
+            Position { index: 0, elapsed, in_queue: false, ..start }
+        } else {
+            Position { elapsed, extension: position.extension, ..start }
+        };
+        self.set_position(now, &target);
+        self.advance(now)?;
+        Ok(())
+    }
+
+    /// Start of the session `position` is in
+    fn session_start(&self, position: &Position) -> Position {
+        Position {
+            elapsed: Duration::ZERO,
+            extension: Duration::ZERO,
+            grace: Duration::ZERO,
+            end_at_duration: None,
+            // a finished clock stays in the last session of the last cycle
+            cycles_done: position.cycles_done - self.is_finished(position) as u32,
+            ..*position
+        }
+    }
+
+    /// Sets the time elapsed within the current session back to zero
+    pub fn restart_session(&mut self, now: Instant) -> Result<(), ClockError> {
+        let position = self.advance(now)?;
+        self.set_position(now, &self.session_start(&position));
+        Ok(())
+    }
+
+    /// Rewinds to the start of the previous session, or to the start
+    /// of the current one if it's been running for a while
+    pub fn back(&mut self, now: Instant) -> Result<(), ClockError> {
+        let position = self.advance(now)?;
+        let mut target = self.session_start(&position);
+
+        if position.in_queue && position.elapsed <= Self::BACK_RESTART_THRESHOLD {
+            // queued sessions follow the cycle session at `index`
+            target.in_queue = false;
+        } else if !self.is_finished(&position) && position.elapsed <= Self::BACK_RESTART_THRESHOLD {
+            let mut previous = target;
+            for _ in 0..self.sessions.len() {
+                if previous.index == 0 {
+                    if previous.cycles_done == 0 {
+                        break;
+                    }
+                    previous.cycles_done -= 1;
+                    previous.index = self.sessions.len();
+                }
+                previous.index -= 1;
+                // don't land on sessions that would be crossed right away
+                if self.effective_duration(&self.sessions[previous.index]) != Some(Duration::ZERO) {
+                    target = previous;
+                    break;
+                }
+            }
+        }
+
+        self.set_position(now, &target);
+        Ok(())
+    }
+
+    /// Fast-forwards to the start of the next session named `name`,
+    /// returns `false` if there's no such session ahead
+    pub fn skip_to(&mut self, now: Instant, name: &str) -> Result<bool, ClockError> {
+        let mut position = self.advance(now)?;
+        for _ in 0..=(self.sessions.len() + self.queue.len()) {
+            if self.is_finished(&position) {
+                break;
+            }
+            position = self.next_position(&position);
+            if self.session_at(&position).name == name {
+                self.set_position(now, &position);
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Runs a one-off session after the current one (and any previously queued ones)
+    pub fn enqueue(&mut self, now: Instant, session: Session) -> Result<(), ClockError> {
+        self.advance(now)?;
+        self.queue.push(session);
+        Ok(())
+    }
+
+    /// Changes the sessions of the cycle while staying in the current one, refusing
+    /// with a message for the user what can't be done
+    pub fn edit_sessions(&mut self, now: Instant, edit: &SessionEdit) -> Result<Result<(), String>, ClockError> {
+        self.advance(now)?;
+        let no_session = |name: &str| Err(format!("There's no session named '{name}'"));
+        match edit {
+            SessionEdit::Add { name, duration, kind, time_format, after } => {
+                let indices: Vec<usize> = match after {
+                    Some(after) => self.sessions
+                        .iter()
+                        .enumerate()
+                        .filter(|(_, session)| session.name == *after)
+                        .map(|(index, _)| index + 1)
+                        .collect(),
+                    None => vec![self.sessions.len()],
+                };
+                if let (Some(after), true) = (after, indices.is_empty()) {
+                    return Ok(no_session(after));
+                }
+                let session = Session {
+                    name: name.clone(),
+                    duration: Some(*duration),
+                    end_at: None,
+                    time_format: time_format.clone(),
+                    repeat: 1,
+                    kind: *kind,
+                    pause_on_start: false,
+                    checkpoint_every: None,
+                };
+                // from the back, so that the indices still to come stay valid
+                for &index in indices.iter().rev() {
+                    self.sessions.insert(index, Cow::Owned(session.clone()));
+                }
+                self.session_index += indices.iter().filter(|&&index| index <= self.session_index).count();
+            },
+            SessionEdit::Remove { name } => {
+                if self.sessions[self.session_index].name == *name {
+                    return Ok(Err(format!("The cycle is at '{name}', skip it before removing it")));
+                }
+                let removed_before = self.sessions[..self.session_index]
+                    .iter()
+                    .filter(|session| session.name == *name)
+                    .count();
+                let len = self.sessions.len();
+                self.sessions.retain(|session| session.name != *name);
+                if self.sessions.len() == len {
+                    return Ok(no_session(name));
+                }
+                self.session_index -= removed_before;
+            },
+            SessionEdit::Set { name, duration, kind } => {
+                let mut sessions = self.sessions
+                    .iter_mut()
+                    .filter(|session| session.name == *name)
+                    .peekable();
+                if sessions.peek().is_none() {
+                    return Ok(no_session(name));
+                }
+                for session in sessions {
+                    let session = session.to_mut();
+                    if let Some(duration) = duration {
+                        session.duration = Some(*duration);
+                        session.end_at = None;
+                    }
+                    session.kind = kind.unwrap_or(session.kind);
+                }
+                if duration.is_some() && !self.in_queue && self.sessions[self.session_index].name == *name {
+                    self.end_at_duration = None;
+                }
+            },
+        }
+        Ok(Ok(()))
+    }
+
+    pub fn set_auto_skip_breaks(&mut self, now: Instant, auto_skip_breaks: bool) -> Result<(), ClockError> {
+        let position = self.advance(now)?;
+        self.auto_skip_breaks = auto_skip_breaks;
+        if auto_skip_breaks && self.session_at(&position).kind.is_break() {
+            // the current break now lasts for no time at all, so don't carry
+            // the time spent in it over to the next session
+            self.clock = self.clock.with_elapsed(now, Duration::ZERO);
+            self.advance(now)?;
+        }
+        Ok(())
+    }
+
+    /// Session and clock state which subscribers are notified about when it changes
+    fn transition_key(&self) -> (usize, u32, bool, bool, bool) {
+        (
+            self.session_index,
+            self.cycles_done,
+            self.in_queue,
+            matches!(self.clock, Clock::Paused { .. }),
+            self.interrupt.is_some(),
+        )
+    }
+
+    fn snapshot(&self) -> Snapshot<'a> {
+        Snapshot {
+            clock: self.clock,
+            sessions: self.sessions.clone(),
+            session_index: self.session_index,
+            extension: self.extension,
+            grace: self.grace,
+            end_at_duration: self.end_at_duration,
+            cycles_done: self.cycles_done,
+            pomodoros_done: self.pomodoros_done,
+            in_queue: self.in_queue,
+            queue: self.queue.clone(),
+            interrupt: self.interrupt.clone(),
+            interruptions: self.interruptions,
+            auto_skip_breaks: self.auto_skip_breaks,
+            is_suspended_by_schedule: self.is_suspended_by_schedule,
+        }
+    }
+
+    fn push_history(&mut self, snapshot: Snapshot<'a>) {
+        if self.history.len() == Self::HISTORY_LEN {
+            self.history.remove(0);
+        }
+        self.history.push(snapshot);
+    }
+
+    /// Reverts the most recent undoable request, as if it never happened,
+    /// returns `false` if there's nothing to undo
+    pub fn undo(&mut self) -> bool {
+        let Some(snapshot) = self.history.pop() else {
+            return false;
+        };
+        let Snapshot {
+            clock,
+            sessions,
+            session_index,
+            extension,
+            grace,
+            end_at_duration,
+            cycles_done,
+            pomodoros_done,
+            in_queue,
+            queue,
+            interrupt,
+            interruptions,
+            auto_skip_breaks,
+            is_suspended_by_schedule,
+        } = snapshot;
+        self.clock = clock;
+        self.sessions = sessions;
+        self.session_index = session_index;
+        self.extension = extension;
+        self.grace = grace;
+        self.end_at_duration = end_at_duration;
+        self.cycles_done = cycles_done;
+        self.pomodoros_done = pomodoros_done;
+        self.in_queue = in_queue;
+        self.queue = queue;
+        self.interrupt = interrupt;
+        self.interruptions = interruptions;
+        self.auto_skip_breaks = auto_skip_breaks;
+        self.is_suspended_by_schedule = is_suspended_by_schedule;
+        true
+    }
+
+    /// Progress to carry over to another server
+    pub fn handover(&mut self, now: Instant) -> Result<Handover, ClockError> {
+        let position = self.advance(now)?;
+        Ok(Handover {
+            session_name: self.session_at(&position).name.clone(),
+            elapsed: position.elapsed.saturating_sub(position.grace),
+            extension: position.extension,
+            cycles_done: position.cycles_done,
+            pomodoros_done: position.pomodoros_done,
+            goal_done: self.goal_done(position.pomodoros_done),
+            is_paused: matches!(self.clock, Clock::Paused { .. }),
+        })
+    }
+
+    /// Continues from where another server was, in the first session named the same
+    /// as its current one, returns `false` if there's no such session
+    pub fn take_over(&mut self, now: Instant, handover: &Handover) -> bool {
+        let Some(index) = self.sessions
+            .iter()
+            .position(|session| session.name == handover.session_name)
+        else {
+            return false;
+        };
+        self.clock = if handover.is_paused {
+            Clock::Paused { elapsed: Duration::ZERO }
+        } else {
+            Clock::Running { resumed: now, offset: Duration::ZERO }
+        };
+        self.pomodoros_before_goal_day = handover.pomodoros_done.saturating_sub(handover.goal_done);
+        self.set_position(now, &Position {
+            index,
+            elapsed: handover.elapsed,
+            extension: handover.extension,
+            grace: Duration::ZERO,
+            cycles_done: handover.cycles_done,
+            pomodoros_done: handover.pomodoros_done,
+            in_queue: false,
+            queue_consumed: 0,
+            end_at_duration: None,
+            paused_on_start: false,
+        });
+        true
+    }
+
+    pub fn reset(&mut self) {
+        self.clock = Clock::Paused { elapsed: Duration::ZERO };
+        self.session_index = 0;
+        self.extension = Duration::ZERO;
+        self.grace = Duration::ZERO;
+        self.end_at_duration = None;
+        self.cycles_done = 0;
+        self.in_queue = false;
+        self.queue.clear();
+        self.interrupt = None;
+        self.interruptions = Interruptions::default();
+        self.is_suspended_by_schedule = false;
+    }
+}
+
+
+/// Change to the sessions of the cycle, for as long as the server runs
+#[derive(Debug, Serialize, Deserialize)]
+pub enum SessionEdit {
+    /// Inserted after every session named `after`, or at the end of the cycle
+    Add {
+        name: String,
+        duration: Duration,
+        kind: SessionKind,
+        time_format: Option<String>,
+        after: Option<String>,
+    },
+    /// Every session with the name is removed
+    Remove {
+        name: String,
+    },
+    /// Every session with the name is changed
+    Set {
+        name: String,
+        duration: Option<Duration>,
+        kind: Option<SessionKind>,
+    },
+}
+
+/// Signed change of the current session's duration, e.g. `+5m` or `-2m`
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum Adjustment {
+    Extend(Duration),
+    Shorten(Duration),
+}
+
+impl FromStr for Adjustment {
+    type Err = humantime::DurationError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.strip_prefix('-') {
+            Some(duration) => humantime::parse_duration(duration).map(Self::Shorten),
+            None => {
+                let duration = s.strip_prefix('+').unwrap_or(s);
+                humantime::parse_duration(duration).map(Self::Extend)
+            },
+        }
+    }
+}
+
+
+#[derive(Debug, Serialize, Deserialize)]
+pub enum Request {
+    Fetch,
+    /// Keep receiving the state whenever the session or the clock changes
+    Subscribe,
+    Toggle,
+    /// Pauses the clock unless it's paused already
+    Pause,
+    /// Resumes the clock unless it's running already
+    Resume,
+    Skip {
+        to: Option<String>,
+        count: u32,
+    },
+    Back,
+    RestartSession,
+    Adjust(Adjustment),
+    Snooze(Duration),
+    Queue {
+        name: String,
+        duration: Duration,
+    },
+    Seek {
+        elapsed: Duration,
+        within_cycle: bool,
+    },
+    Interrupt {
+        name: String,
+        duration: Option<Duration>,
+    },
+    EndInterrupt,
+    Interruption(InterruptionKind),
+    Reset,
+    Undo,
+    Stop,
+    /// Stops the server, responding with its progress for a new one to take over
+    Handover,
+    Ping,
+    /// Asks for the server's version, process and origin, see `ServerInfo`
+    Info,
+    /// Asks for the sessions of the cycle, which edits may have changed since the server started
+    Sessions,
+    AutoSkip(bool),
+    EditSessions(SessionEdit),
+}
+
+impl Request {
+    /// Changes the clock in a way `Undo` can revert
+    fn is_undoable(&self) -> bool {
+        !matches!(self, Self::Fetch | Self::Subscribe | Self::Undo | Self::Stop | Self::Handover | Self::Ping | Self::Info | Self::Sessions)
+    }
+
+    /// Has the same effect when sent again, e.g. after its response got lost
+    pub fn is_repeatable(&self) -> bool {
+        matches!(self, Self::Fetch | Self::Subscribe | Self::Pause | Self::Resume | Self::AutoSkip(_) | Self::Ping | Self::Info | Self::Sessions)
+    }
+
+    /// Sets a clock going or changes it, worth starting a named timer for
+    pub fn starts_timer(&self) -> bool {
+        matches!(
+            self,
+            Self::Toggle | Self::Resume | Self::Skip { .. } | Self::RestartSession | Self::Adjust(_) | Self::Snooze(_)
+                | Self::Queue { .. } | Self::Seek { .. } | Self::Interrupt { .. } | Self::AutoSkip(_) | Self::EditSessions(_)
+        )
+    }
+}
+
+impl From<&cli::Request> for Request {
+    fn from(value: &cli::Request) -> Self {
+        match value {
+            cli::Request::Fetch(cli::FetchArgs { follow: true, interval: None, .. }) => Self::Subscribe,
+            cli::Request::Fetch(_) => Self::Fetch,
+            cli::Request::Subscribe { .. } => Self::Subscribe,
+            cli::Request::Toggle => Self::Toggle,
+            cli::Request::Pause => Self::Pause,
+            cli::Request::Resume => Self::Resume,
+            cli::Request::Skip { to, count } => Self::Skip {
+                to: to.clone(),
+                count: *count,
+            },
+            cli::Request::Interrupt { end: true, .. } => Self::EndInterrupt,
+            cli::Request::Interrupt { name, duration, .. } => Self::Interrupt {
+                name: name.clone().unwrap_or_default(),
+                duration: *duration,
+            },
+            cli::Request::Interruption { external, .. } => Self::Interruption(if *external {
+                InterruptionKind::External
+            } else {
+                InterruptionKind::Internal
+            }),
+            cli::Request::Back => Self::Back,
+            cli::Request::RestartSession => Self::RestartSession,
+            cli::Request::Adjust { adjustment } => Self::Adjust(*adjustment),
+            cli::Request::Snooze { duration } => Self::Snooze(*duration),
+            cli::Request::Queue { name, duration } => Self::Queue {
+                name: name.clone(),
+                duration: *duration,
+            },
+            cli::Request::Seek { elapsed, within_cycle } => Self::Seek {
+                elapsed: *elapsed,
+                within_cycle: *within_cycle,
+            },
+            cli::Request::Reset { .. } => Self::Reset,
+            cli::Request::Undo => Self::Undo,
+            cli::Request::Stop => Self::Stop,
+            cli::Request::Ping => Self::Ping,
+            cli::Request::AutoSkip { switch } => Self::AutoSkip((*switch).into()),
+            cli::Request::Sessions { edit } => Self::EditSessions(match edit {
+                cli::SessionsEdit::Add { session, after } => SessionEdit::Add {
+                    name: session.name.clone(),
+                    duration: session.duration.unwrap_or_default(),
+                    kind: session.kind,
+                    time_format: session.time_format.clone(),
+                    after: after.clone(),
+                },
+                cli::SessionsEdit::Rm { name } => SessionEdit::Remove { name: name.clone() },
+                cli::SessionsEdit::Set { name, duration, kind } => SessionEdit::Set {
+                    name: name.clone(),
+                    duration: *duration,
+                    kind: *kind,
+                },
+            }),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+// responses are made one at a time to be sent right away
+#[allow(clippy::large_enum_variant)]
+pub enum Response {
+    State(PomodoroState),
+    Confirmation(Result<(), String>),
+    Handover(Handover),
+    Pong,
+    /// Confirms a reset, with the progress it threw away
+    Reset(Discarded),
+    Info(ServerInfo),
+    Sessions(Vec<SessionInfo>),
+}
+
+/// Session of a server's cycle, with its repeats listed one by one
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SessionInfo {
+    pub name: String,
+    /// Human readable, `None` for sessions counting up until skipped
+    pub duration: Option<String>,
+    /// Local time of day the session ends at instead, `hh:mm[:ss]`
+    pub end_at: Option<String>,
+    pub kind: SessionKind,
+    /// Format of the time left, the default one unless the session has its own
+    pub time_format: String,
+    /// The cycle is at this session, rather than at a queued or interrupt one
+    pub is_current: bool,
+}
+
+/// What a server runs as and where it came from
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ServerInfo {
+    pub version: String,
+    pub pid: u32,
+    /// Config file the server read, `None` if it runs on the defaults
+    pub config_path: Option<PathBuf>,
+    /// Path of the socket, or its name prefixed with `@` when it's abstract
+    pub socket: String,
+    /// `sessions_hash` of the server's cycle, edits included
+    pub sessions_hash: String,
+    /// Local time the server was started at, in RFC 3339
+    pub started_at: String,
+    pub uptime: String,
+}
+
+/// Progress of the cycle thrown away by a reset
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Discarded {
+    /// Cycles completed since the server started or was last reset
+    pub cycles: u32,
+    /// Sessions completed within the cycle under way
+    pub sessions: u32,
+    /// Time counted in the current session
+    pub elapsed: Duration,
+}
+
+impl fmt::Display for Discarded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let count = |count: u32, noun: &str| match count {
+            1 => format!("1 {noun}"),
+            count => format!("{count} {noun}s"),
+        };
+        let mut parts = Vec::new();
+        if self.cycles > 0 {
+            parts.push(count(self.cycles, "cycle"));
+        }
+        if self.sessions > 0 {
+            parts.push(count(self.sessions, "session"));
+        }
+        if self.elapsed.as_secs() > 0 {
+            let elapsed = humantime::format_duration(Duration::from_secs(self.elapsed.as_secs()));
+            parts.push(format!("{elapsed} of the current session"));
+        }
+        match parts.split_last() {
+            None => f.write_str("There was no progress to discard"),
+            Some((last, [])) => write!(f, "Discarded {last}"),
+            Some((last, rest)) => write!(f, "Discarded {} and {last}", rest.join(", ")),
+        }
+    }
+}
+
+
+const SYS_CLOCK_ERR_MSG: &str = "your system clock is prbly doomed, idk 💀";
+
+impl ServerState for PomodoroClock<'_> {
+    type Request<'de> = Request;
+    type Response = Response;
+
+    fn update<'de>(&mut self, request: &Self::Request<'de>) -> ServerAction<Self::Response> {
+        self.compensate_suspend().expect(SYS_CLOCK_ERR_MSG);
+        let now = self.time_source.now();
+        let transition_key = self.transition_key();
+        self.apply_active_hours(now).expect(SYS_CLOCK_ERR_MSG);
+        self.roll_over_goal_day(now).expect(SYS_CLOCK_ERR_MSG);
+        let snapshot = request.is_undoable().then(|| self.snapshot());
+        log::debug!("Handling {request:?}");
+
+        let action = match request {
+            Request::Toggle => {
+                let result = if self.is_pause_refused(now).expect(SYS_CLOCK_ERR_MSG) {
+                    Err("Work sessions can't be paused in strict mode".into())
+                } else {
+                    self.toggle(now).expect(SYS_CLOCK_ERR_MSG);
+                    Ok(())
+                };
+                ServerAction::Respond(Response::Confirmation(result))
+            },
+            Request::Pause => {
+                let result = if self.is_pause_refused(now).expect(SYS_CLOCK_ERR_MSG) {
+                    Err("Work sessions can't be paused in strict mode".into())
+                } else {
+                    self.set_paused(now, true).expect(SYS_CLOCK_ERR_MSG);
+                    Ok(())
+                };
+                ServerAction::Respond(Response::Confirmation(result))
+            },
+            Request::Resume => {
+                self.set_paused(now, false).expect(SYS_CLOCK_ERR_MSG);
+                ServerAction::Respond(Response::Confirmation(Ok(())))
+            },
+            Request::Skip { to: None, count } => {
+                self.skip_sessions(now, *count).expect(SYS_CLOCK_ERR_MSG);
+                ServerAction::Respond(Response::Confirmation(Ok(())))
+            },
+            Request::Skip { to: Some(name), .. } => {
+                let result = if self.skip_to(now, name).expect(SYS_CLOCK_ERR_MSG) {
+                    Ok(())
+                } else {
+                    Err(format!("There's no session named '{name}'"))
+                };
+                ServerAction::Respond(Response::Confirmation(result))
+            },
+            Request::Back => {
+                self.back(now).expect(SYS_CLOCK_ERR_MSG);
+                ServerAction::Respond(Response::Confirmation(Ok(())))
+            },
+            Request::RestartSession => {
+                self.restart_session(now).expect(SYS_CLOCK_ERR_MSG);
+                ServerAction::Respond(Response::Confirmation(Ok(())))
+            },
+            Request::Adjust(adjustment) => {
+                self.adjust(now, *adjustment).expect(SYS_CLOCK_ERR_MSG);
+                ServerAction::Respond(Response::Confirmation(Ok(())))
+            },
+            Request::Snooze(duration) => {
+                self.snooze(now, *duration).expect(SYS_CLOCK_ERR_MSG);
+                ServerAction::Respond(Response::Confirmation(Ok(())))
+            },
+            Request::Queue { name, duration } => {
+                let session = Session {
+                    name: name.clone(),
+                    duration: Some(*duration),
+                    end_at: None,
+                    time_format: None,
+                    repeat: 1,
+                    kind: SessionKind::Work,
+                    pause_on_start: false,
+                    checkpoint_every: None,
+                };
+                self.enqueue(now, session).expect(SYS_CLOCK_ERR_MSG);
+                ServerAction::Respond(Response::Confirmation(Ok(())))
+            },
+            Request::Seek { elapsed, within_cycle } => {
+                self.seek(now, *elapsed, *within_cycle).expect(SYS_CLOCK_ERR_MSG);
+                ServerAction::Respond(Response::Confirmation(Ok(())))
+            },
+            Request::Interrupt { name, duration } => {
+                let result = if self.interrupt(now, name.clone(), *duration).expect(SYS_CLOCK_ERR_MSG) {
+                    Ok(())
+                } else {
+                    Err("The cycle is already interrupted".into())
+                };
+                ServerAction::Respond(Response::Confirmation(result))
+            },
+            Request::EndInterrupt => {
+                let result = if self.end_interrupt(now).expect(SYS_CLOCK_ERR_MSG) {
+                    Ok(())
+                } else {
+                    Err("The cycle isn't interrupted".into())
+                };
+                ServerAction::Respond(Response::Confirmation(result))
+            },
+            Request::Interruption(kind) => {
+                let result = if self.record_interruption(now, *kind).expect(SYS_CLOCK_ERR_MSG) {
+                    Ok(())
+                } else {
+                    Err("Interruptions can only be recorded during work sessions".into())
+                };
+                ServerAction::Respond(Response::Confirmation(result))
+            },
+            Request::Reset => {
+                let position = self.position_at(now).expect(SYS_CLOCK_ERR_MSG);
+                // the cycle's session a queued one runs after is done already,
+                // and a finished clock's are counted as cycles
+                let sessions = match self.is_finished(&position) {
+                    true => 0,
+                    false => position.index as u32 + position.in_queue as u32,
+                };
+                let discarded = Discarded {
+                    cycles: position.cycles_done,
+                    sessions,
+                    elapsed: position.elapsed.saturating_sub(position.grace),
+                };
+                self.reset();
+                ServerAction::Respond(Response::Reset(discarded))
+            },
+            Request::Undo => {
+                let result = if self.undo() {
+                    Ok(())
+                } else {
+                    Err("There's nothing to undo".into())
+                };
+                ServerAction::Respond(Response::Confirmation(result))
+            },
+            Request::EditSessions(edit) => {
+                let result = self.edit_sessions(now, edit).expect(SYS_CLOCK_ERR_MSG);
+                ServerAction::Respond(Response::Confirmation(result))
+            },
+            Request::AutoSkip(auto_skip_breaks) => {
+                self.set_auto_skip_breaks(now, *auto_skip_breaks).expect(SYS_CLOCK_ERR_MSG);
+                ServerAction::Respond(Response::Confirmation(Ok(())))
+            },
+            Request::Fetch => {
+                let state = self.state_at(now).expect(SYS_CLOCK_ERR_MSG);
+                ServerAction::Respond(Response::State(state))
+            },
+            Request::Subscribe => {
+                let state = self.state_at(now).expect(SYS_CLOCK_ERR_MSG);
+                ServerAction::Subscribe(Response::State(state))
+            },
+            Request::Stop => ServerAction::StopRespond(Response::Confirmation(Ok(()))),
+            Request::Ping => ServerAction::Respond(Response::Pong),
+            Request::Info => ServerAction::Respond(Response::Info(self.info())),
+            Request::Sessions => {
+                let sessions = self.session_infos(now).expect(SYS_CLOCK_ERR_MSG);
+                ServerAction::Respond(Response::Sessions(sessions))
+            },
+            Request::Handover => {
+                let handover = self.handover(now).expect(SYS_CLOCK_ERR_MSG);
+                ServerAction::StopRespond(Response::Handover(handover))
+            },
+        };
+
+        let is_confirmed = matches!(action, ServerAction::Respond(Response::Confirmation(Ok(())) | Response::Reset(_)));
+        // refused requests didn't change anything worth undoing
+        if let (Some(snapshot), true) = (snapshot, is_confirmed) {
+            self.push_history(snapshot);
+        }
+        self.has_changed |= is_confirmed || transition_key != self.transition_key();
+        action
+    }
+
+    fn next_tick(&self) -> Option<Duration> {
+        let until_transition = self.time_until_transition(self.time_source.now())
+            .expect(SYS_CLOCK_ERR_MSG)
+            .map(|timeout| self.time_source.real_duration(timeout));
+        // wake up to pause or resume the clock right as the active hours change
+        until_transition.into_iter()
+            .chain(self.time_until_active_hours_edge())
+            .min()
+    }
+
+    /// Commits session transitions right as they happen
+    fn tick(&mut self) -> ServerAction<Self::Response> {
+        self.compensate_suspend().expect(SYS_CLOCK_ERR_MSG);
+        let now = self.time_source.now();
+        let transition_key = self.transition_key();
+        self.apply_active_hours(now).expect(SYS_CLOCK_ERR_MSG);
+        let position = self.advance(now).expect(SYS_CLOCK_ERR_MSG);
+        if transition_key != self.transition_key() {
+            log::info!("Moved on to '{}'", self.session_at(&position).name);
+            self.has_changed = true;
+        }
+        if self.stop_when_finished && self.is_finished(&position) {
+            log::info!("'{}' finished", self.session_at(&position).name);
+            ServerAction::Stop
+        } else {
+            ServerAction::None
+        }
+    }
+
+    fn heartbeat(&mut self) -> Vec<Self::Response> {
+        let state = self.state_at(self.time_source.now()).expect(SYS_CLOCK_ERR_MSG);
+        vec![Response::State(state)]
+    }
+
+    /// Toggles the clock on SIGUSR1 and skips the session on SIGUSR2
+    fn signal(&mut self, signal: UserSignal) -> ServerAction<Self::Response> {
+        let request = match signal {
+            UserSignal::Usr1 => Request::Toggle,
+            UserSignal::Usr2 => Request::Skip { to: None, count: 1 },
+        };
+        self.update(&request)
+    }
+
+    fn publish(&mut self) -> Vec<Self::Response> {
+        if !std::mem::take(&mut self.has_changed) {
+            return Vec::new();
+        }
+        let state = self.state_at(self.time_source.now()).expect(SYS_CLOCK_ERR_MSG);
+        vec![Response::State(state)]
+    }
+}
+
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Session of `secs` seconds, run once with the default time format
+    pub(crate) fn session(name: &str, secs: u64, kind: SessionKind) -> Session {
+        Session {
+            name: name.into(),
+            duration: Some(Duration::from_secs(secs)),
+            end_at: None,
+            time_format: None,
+            repeat: 1,
+            kind,
+            pause_on_start: false,
+            checkpoint_every: None,
+        }
+    }
+
+    #[test]
+    fn pomodoro_state_at() {
+        let sessions = [
+            session("work1", 200, SessionKind::Work),
+            session("rest", 100, SessionKind::Break),
+            session("work2", 200, SessionKind::Work),
+            session("long rest", 150, SessionKind::LongBreak),
+        ];
+        let mut pomodoro_clock = PomodoroClock::paused(sessions.iter(), "%M:%S");
+        pomodoro_clock.clock = Clock::Paused { elapsed: Duration::from_secs(950) };
+
+        assert_eq!(
+            pomodoro_clock.state_at(Instant::now()).unwrap(),
+            PomodoroState {
+                is_paused: true,
+                is_finished: false,
+                session_name: "work2".into(),
+                session_kind: SessionKind::Work,
+                session_duration: "03:20".into(),
+                next_session: Some("long rest".into()),
+                upcoming_sessions: ["long rest", "work1", "rest", "work2", "long rest"].map(String::from).into(),
+                time: "03:20".into(),
+                percent: 0,
+                exact_percent: None,
+                exact_time: None,
+                overtime: None,
+                checkpoint_index: None,
+                checkpoint_time: None,
+                internal_interruptions: 0,
+                external_interruptions: 0,
+                pomodoros_done: 3,
+                goal_done: 3,
+                goal_total: None,
+                is_interrupted: false,
+                is_in_transition: false,
+                is_suspended_by_schedule: false,
+                started_at: pomodoro_clock.started_at(),
+                uptime: "0s".into(),
+            },
+        );
+    }
+
+    #[test]
+    fn pomodoro_skip() {
+        let sessions = [
+            session("work1", 8, SessionKind::Work),
+        ];
+        let mut pomodoro_clock = PomodoroClock::paused(sessions.iter(), "%M:%S");
+        pomodoro_clock.clock = Clock::Paused { elapsed: Duration::from_secs_f32(5.07) };
+        let _ = pomodoro_clock.skip_sessions(Instant::now(), 1);
+        assert_eq!(
+            pomodoro_clock.position_at(Instant::now()).unwrap().elapsed,
+            Duration::from_secs(0),
+        );
+    }
+
+    #[test]
+    fn pomodoro_finished() {
+        let sessions = [
+            session("work", 200, SessionKind::Work),
+            session("rest", 100, SessionKind::Work),
+        ];
+        let mut pomodoro_clock = PomodoroClock::paused(sessions.iter(), "%M:%S").with_cycles(Some(2));
+        pomodoro_clock.clock = Clock::Paused { elapsed: Duration::from_secs(650) };
+
+        assert_eq!(
+            pomodoro_clock.state_at(Instant::now()).unwrap(),
+            PomodoroState {
+                is_paused: true,
+                is_finished: true,
+                session_name: "rest".into(),
+                session_kind: SessionKind::Work,
+                session_duration: "01:40".into(),
+                next_session: None,
+                upcoming_sessions: Vec::new(),
+                time: "00:00".into(),
+                percent: 100,
+                exact_percent: None,
+                exact_time: None,
+                overtime: None,
+                checkpoint_index: None,
+                checkpoint_time: None,
+                internal_interruptions: 0,
+                external_interruptions: 0,
+                pomodoros_done: 4,
+                goal_done: 4,
+                goal_total: None,
+                is_interrupted: false,
+                is_in_transition: false,
+                is_suspended_by_schedule: false,
+                started_at: pomodoro_clock.started_at(),
+                uptime: "0s".into(),
+            },
+        );
+
+        pomodoro_clock.skip_sessions(Instant::now(), 1).unwrap();
+        assert!(pomodoro_clock.state_at(Instant::now()).unwrap().is_finished);
+    }
+
+    #[test]
+    fn pomodoro_auto_skip_breaks() {
+        let sessions = [
+            session("work1", 200, SessionKind::Work),
+            session("rest", 100, SessionKind::Break),
+            session("work2", 200, SessionKind::Work),
+        ];
+        let mut pomodoro_clock = PomodoroClock::paused(sessions.iter(), "%M:%S")
+            .with_auto_skip_breaks(true);
+        // the break isn't coming up either
+        assert_eq!(pomodoro_clock.state_at(Instant::now()).unwrap().next_session.as_deref(), Some("work2"));
+        pomodoro_clock.clock = Clock::Paused { elapsed: Duration::from_secs(250) };
+        assert_eq!(
+            pomodoro_clock.state_at(Instant::now()).unwrap().session_name,
+            "work2",
+        );
+
+        pomodoro_clock.reset();
+        pomodoro_clock.set_auto_skip_breaks(Instant::now(), false).unwrap();
+        pomodoro_clock.skip_sessions(Instant::now(), 1).unwrap();
+        assert_eq!(
+            pomodoro_clock.state_at(Instant::now()).unwrap().session_name,
+            "rest",
+        );
+
+        pomodoro_clock.set_auto_skip_breaks(Instant::now(), true).unwrap();
+        let state = pomodoro_clock.state_at(Instant::now()).unwrap();
+        assert_eq!(state.session_name, "work2");
+        assert_eq!(state.time, "03:20");
+    }
+
+    #[test]
+    fn pomodoro_overtime() {
+        let sessions = [
+            session("work", 200, SessionKind::Work),
+            session("rest", 100, SessionKind::Break),
+        ];
+        let mut pomodoro_clock = PomodoroClock::paused(sessions.iter(), "%M:%S")
+            .with_overtime(true);
+        pomodoro_clock.clock = Clock::Paused { elapsed: Duration::from_secs(250) };
+
+        let state = pomodoro_clock.state_at(Instant::now()).unwrap();
+        assert_eq!(state.session_name, "work");
+        assert_eq!(state.time, "00:00");
+        assert_eq!(state.overtime.as_deref(), Some("00:50"));
+
+        pomodoro_clock.skip_sessions(Instant::now(), 1).unwrap();
+        let state = pomodoro_clock.state_at(Instant::now()).unwrap();
+        assert_eq!(state.session_name, "rest");
+        assert_eq!(state.time, "01:40");
+        assert_eq!(state.overtime, None);
+    }
+
+    #[test]
+    fn pomodoro_skip_to() {
+        let sessions = ["work1", "rest", "work2", "long rest"].map(|name| session(name, 100, SessionKind::Work));
+        let mut pomodoro_clock = PomodoroClock::paused(sessions.iter(), "%M:%S");
+        pomodoro_clock.clock = Clock::Paused { elapsed: Duration::from_secs(150) };
+
+        assert!(pomodoro_clock.skip_to(Instant::now(), "long rest").unwrap());
+        let state = pomodoro_clock.state_at(Instant::now()).unwrap();
+        assert_eq!(state.session_name, "long rest");
+        assert_eq!(state.time, "01:40");
+
+        assert!(pomodoro_clock.skip_to(Instant::now(), "rest").unwrap());
+        assert_eq!(pomodoro_clock.cycles_done, 1);
+        assert!(!pomodoro_clock.skip_to(Instant::now(), "nap").unwrap());
+    }
+
+    #[test]
+    fn pomodoro_skip_multiple() {
+        let sessions = ["work1", "rest", "work2", "long rest"].map(|name| session(name, 100, SessionKind::Work));
+        let mut pomodoro_clock = PomodoroClock::paused(sessions.iter(), "%M:%S")
+            .with_cycles(Some(2));
+        pomodoro_clock.clock = Clock::Paused { elapsed: Duration::from_secs(150) };
+
+        pomodoro_clock.skip_sessions(Instant::now(), 3).unwrap();
+        let position = pomodoro_clock.position_at(Instant::now()).unwrap();
+        assert_eq!(position, Position {
+            index: 0,
+            elapsed: Duration::ZERO,
+            extension: Duration::ZERO,
+            grace: Duration::ZERO,
+            cycles_done: 1,
+            pomodoros_done: 1,
+            in_queue: false,
+            queue_consumed: 0,
+            end_at_duration: None,
+            paused_on_start: false,
+        });
+
+        pomodoro_clock.skip_sessions(Instant::now(), 10).unwrap();
+        assert!(pomodoro_clock.state_at(Instant::now()).unwrap().is_finished);
+    }
+
+    #[test]
+    fn pomodoro_back() {
+        let sessions = ["work", "rest"].map(|name| session(name, 100, SessionKind::Work));
+        let mut pomodoro_clock = PomodoroClock::paused(sessions.iter(), "%M:%S");
+        pomodoro_clock.clock = Clock::Paused { elapsed: Duration::from_secs(250) };
+
+        pomodoro_clock.back(Instant::now()).unwrap();
+        assert_eq!(pomodoro_clock.position_at(Instant::now()).unwrap(), Position {
+            index: 0,
+            elapsed: Duration::ZERO,
+            extension: Duration::ZERO,
+            grace: Duration::ZERO,
+            cycles_done: 1,
+            pomodoros_done: 2,
+            in_queue: false,
+            queue_consumed: 0,
+            end_at_duration: None,
+            paused_on_start: false,
+        });
+
+        pomodoro_clock.back(Instant::now()).unwrap();
+        assert_eq!(pomodoro_clock.position_at(Instant::now()).unwrap(), Position {
+            index: 1,
+            elapsed: Duration::ZERO,
+            extension: Duration::ZERO,
+            grace: Duration::ZERO,
+            cycles_done: 0,
+            pomodoros_done: 2,
+            in_queue: false,
+            queue_consumed: 0,
+            end_at_duration: None,
+            paused_on_start: false,
+        });
+    }
+
+    #[test]
+    fn pomodoro_adjust() {
+        let sessions = ["work", "rest"].map(|name| session(name, 100, SessionKind::Work));
+        let mut pomodoro_clock = PomodoroClock::paused(sessions.iter(), "%M:%S");
+        pomodoro_clock.clock = Clock::Paused { elapsed: Duration::from_secs(50) };
+
+        pomodoro_clock.adjust(Instant::now(), "+1m".parse().unwrap()).unwrap();
+        let state = pomodoro_clock.state_at(Instant::now()).unwrap();
+        assert_eq!(state.session_duration, "02:40");
+        assert_eq!(state.time, "01:50");
+
+        pomodoro_clock.adjust(Instant::now(), "-90s".parse().unwrap()).unwrap();
+        let state = pomodoro_clock.state_at(Instant::now()).unwrap();
+        assert_eq!(state.session_duration, "01:40");
+        assert_eq!(state.time, "00:20");
+
+        pomodoro_clock.adjust(Instant::now(), "-1m".parse().unwrap()).unwrap();
+        let state = pomodoro_clock.state_at(Instant::now()).unwrap();
+        assert_eq!(state.session_name, "rest");
+        assert_eq!(state.time, "01:00");
+    }
+
+    #[test]
+    fn pomodoro_seek() {
+        let sessions = ["work", "rest"].map(|name| session(name, 100, SessionKind::Work));
+        let mut pomodoro_clock = PomodoroClock::paused(sessions.iter(), "%M:%S");
+        pomodoro_clock.clock = Clock::Paused { elapsed: Duration::from_secs(120) };
+
+        pomodoro_clock.seek(Instant::now(), Duration::from_secs(70), false).unwrap();
+        let state = pomodoro_clock.state_at(Instant::now()).unwrap();
+        assert_eq!(state.session_name, "rest");
+        assert_eq!(state.time, "00:30");
+
+        pomodoro_clock.seek(Instant::now(), Duration::from_secs(70), true).unwrap();
+        let state = pomodoro_clock.state_at(Instant::now()).unwrap();
+        assert_eq!(state.session_name, "work");
+        assert_eq!(state.time, "00:30");
+    }
+
+    #[test]
+    fn pomodoro_snooze() {
+        let sessions = ["work", "rest"].map(|name| session(name, 100, SessionKind::Work));
+        let mut pomodoro_clock = PomodoroClock::paused(sessions.iter(), "%M:%S")
+            .with_overtime(true);
+        pomodoro_clock.clock = Clock::Paused { elapsed: Duration::from_secs(90) };
+
+        pomodoro_clock.snooze(Instant::now(), Duration::from_secs(60)).unwrap();
+        assert_eq!(pomodoro_clock.state_at(Instant::now()).unwrap().time, "01:10");
+
+        pomodoro_clock.seek(Instant::now(), Duration::from_secs(200), false).unwrap();
+        pomodoro_clock.snooze(Instant::now(), Duration::from_secs(60)).unwrap();
+        let state = pomodoro_clock.state_at(Instant::now()).unwrap();
+        assert_eq!(state.time, "01:00");
+        assert_eq!(state.overtime, None);
+    }
+
+    #[test]
+    fn pomodoro_queue() {
+        let sessions = ["work", "rest"].map(|name| session(name, 100, SessionKind::Work));
+        let mut pomodoro_clock = PomodoroClock::paused(sessions.iter(), "%M:%S");
+        pomodoro_clock.clock = Clock::Paused { elapsed: Duration::from_secs(50) };
+
+        let review = session("review", 30, SessionKind::Work);
+        pomodoro_clock.enqueue(Instant::now(), review).unwrap();
+        pomodoro_clock.seek(Instant::now(), Duration::from_secs(110), false).unwrap();
+        let state = pomodoro_clock.state_at(Instant::now()).unwrap();
+        assert_eq!(state.session_name, "review");
+        assert_eq!(state.time, "00:20");
+
+        pomodoro_clock.seek(Instant::now(), Duration::from_secs(40), false).unwrap();
+        let state = pomodoro_clock.state_at(Instant::now()).unwrap();
+        assert_eq!(state.session_name, "rest");
+        assert_eq!(state.time, "01:30");
+        assert!(pomodoro_clock.queue.is_empty());
+
+        pomodoro_clock.enqueue(Instant::now(), session("review", 30, SessionKind::Work)).unwrap();
+        pomodoro_clock.seek(Instant::now(), Duration::from_secs(110), false).unwrap();
+        let ServerAction::Respond(Response::Reset(discarded)) = pomodoro_clock.update(&Request::Reset) else {
+            panic!("Reset should be confirmed");
+        };
+        assert_eq!((discarded.cycles, discarded.sessions), (0, 2));
+    }
+
+    #[test]
+    fn pomodoro_edit_sessions() {
+        let sessions = ["work", "rest"].map(|name| session(name, 100, SessionKind::Work));
+        let mut pomodoro_clock = PomodoroClock::paused(sessions.iter(), "%M:%S");
+        pomodoro_clock.skip_sessions(Instant::now(), 1).unwrap();
+        let mut edit = |edit| pomodoro_clock.edit_sessions(Instant::now(), &edit).unwrap();
+
+        let add = |after: Option<&str>| SessionEdit::Add {
+            name: "stretch".into(),
+            duration: Duration::from_secs(30),
+            kind: SessionKind::Break,
+            time_format: None,
+            after: after.map(String::from),
+        };
+        assert!(edit(add(Some("work"))).is_ok());
+        assert!(edit(add(None)).is_ok());
+        assert!(edit(add(Some("nap"))).is_err());
+        assert!(edit(SessionEdit::Remove { name: "rest".into() }).is_err());
+        assert!(edit(SessionEdit::Set { name: "rest".into(), duration: Some(Duration::from_secs(60)), kind: None }).is_ok());
+        let names: Vec<&str> = pomodoro_clock.sessions.iter().map(|session| session.name.as_str()).collect();
+        assert_eq!(names, ["work", "stretch", "rest", "stretch"]);
+        // still in the same session, now shorter
+        let state = pomodoro_clock.state_at(Instant::now()).unwrap();
+        assert_eq!(state.session_name, "rest");
+        assert_eq!(state.time, "01:00");
+
+        let mut edit = |edit| pomodoro_clock.edit_sessions(Instant::now(), &edit).unwrap();
+        assert!(edit(SessionEdit::Remove { name: "stretch".into() }).is_ok());
+        assert_eq!(pomodoro_clock.session_index, 1);
+        assert_eq!(pomodoro_clock.state_at(Instant::now()).unwrap().session_name, "rest");
+        // the configured sessions are left alone
+        assert_eq!(sessions[1].duration, Some(Duration::from_secs(100)));
+    }
+
+    #[test]
+    fn pomodoro_interrupt() {
+        let sessions = ["work", "rest"].map(|name| session(name, 100, SessionKind::Work));
+        let start = Instant::now();
+        let mut pomodoro_clock = PomodoroClock::paused(sessions.iter(), "%M:%S");
+        pomodoro_clock.toggle(start).unwrap();
+
+        let interrupted = start + Duration::from_secs(30);
+        assert!(pomodoro_clock.interrupt(interrupted, "meeting".into(), None).unwrap());
+        assert!(!pomodoro_clock.interrupt(interrupted, "call".into(), None).unwrap());
+        let state = pomodoro_clock.state_at(interrupted + Duration::from_secs(600)).unwrap();
+        assert!(state.is_interrupted);
+        assert_eq!(state.session_name, "meeting");
+        assert_eq!(state.time, "10:00");
+
+        let resumed = interrupted + Duration::from_secs(600);
+        assert!(pomodoro_clock.end_interrupt(resumed).unwrap());
+        let state = pomodoro_clock.state_at(resumed + Duration::from_secs(10)).unwrap();
+        assert!(!state.is_interrupted);
+        assert_eq!(state.session_name, "work");
+        assert_eq!(state.time, "01:00");
+    }
+
+    #[test]
+    fn pomodoro_pause_resume() {
+        let sessions = ["work", "rest"].map(|name| Session {
+            pause_on_start: name == "rest",
+            ..session(name, 100, SessionKind::Work)
+        });
+        let start = Instant::now();
+        let mut pomodoro_clock = PomodoroClock::paused(sessions.iter(), "%M:%S");
+        assert!(!pomodoro_clock.set_paused(start, true).unwrap());
+        assert!(pomodoro_clock.set_paused(start, false).unwrap());
+        assert!(!pomodoro_clock.set_paused(start + Duration::from_secs(10), false).unwrap());
+        assert!(!pomodoro_clock.state_at(start + Duration::from_secs(20)).unwrap().is_paused);
+
+        // the session starting paused is paused already
+        let rested = start + Duration::from_secs(150);
+        assert!(!pomodoro_clock.set_paused(rested, true).unwrap());
+        assert!(pomodoro_clock.state_at(rested).unwrap().is_paused);
+
+        // interrupts run from their start, whatever the cycle's clock does
+        assert!(pomodoro_clock.interrupt(rested, "call".into(), None).unwrap());
+        assert!(!pomodoro_clock.set_paused(rested, false).unwrap());
+        assert!(pomodoro_clock.set_paused(rested, true).unwrap());
+        assert!(pomodoro_clock.state_at(rested).unwrap().is_paused);
+    }
+
+    #[test]
+    fn pomodoro_info() {
+        let sessions = ["work", "rest"].map(|name| session(name, 100, SessionKind::Work));
+        let mut pomodoro_clock = PomodoroClock::paused(sessions.iter(), "%M:%S")
+            .with_config_path(Some("/etc/pomidoro.toml".into()))
+            .with_socket("@pomidoro/1".into());
+        let ServerAction::Respond(Response::Info(info)) = pomodoro_clock.update(&Request::Info) else {
+            panic!("Info should be answered");
+        };
+        assert_eq!(info.pid, std::process::id());
+        assert_eq!(info.config_path, Some("/etc/pomidoro.toml".into()));
+        assert_eq!(info.socket, "@pomidoro/1");
+        assert_eq!(info.sessions_hash, sessions_hash(&sessions));
+
+        // edits show in the hash, which no longer matches the config
+        let edit = SessionEdit::Set { name: "rest".into(), duration: Some(Duration::from_secs(50)), kind: None };
+        pomodoro_clock.update(&Request::EditSessions(edit));
+        let ServerAction::Respond(Response::Info(edited)) = pomodoro_clock.update(&Request::Info) else {
+            panic!("Info should be answered");
+        };
+        assert_ne!(edited.sessions_hash, info.sessions_hash);
+    }
+
+    #[test]
+    fn pomodoro_sessions() {
+        let sessions = ["work", "rest"].map(|name| Session {
+            time_format: (name == "rest").then(|| "%S".into()),
+            ..session(name, 100, SessionKind::Work)
+        });
+        let start = Instant::now();
+        let mut pomodoro_clock = PomodoroClock::running(sessions.iter(), "%M:%S", start);
+        let listed = pomodoro_clock.session_infos(start + Duration::from_secs(150)).unwrap();
+        let names: Vec<_> = listed.iter().map(|session| (session.name.as_str(), session.is_current)).collect();
+        assert_eq!(names, [("work", false), ("rest", true)]);
+        assert_eq!(listed[0].duration.as_deref(), Some("1m 40s"));
+        assert_eq!(listed[0].time_format, "%M:%S");
+        assert_eq!(listed[1].time_format, "%S");
+
+        // the cycle isn't at any of its sessions during an interrupt
+        let interrupted = start + Duration::from_secs(160);
+        assert!(pomodoro_clock.interrupt(interrupted, "call".into(), None).unwrap());
+        let listed = pomodoro_clock.session_infos(interrupted).unwrap();
+        assert!(listed.iter().all(|session| !session.is_current));
+    }
+
+    #[test]
+    fn pomodoro_undo() {
+        let sessions = ["work", "rest"].map(|name| session(name, 100, SessionKind::Work));
+        let mut pomodoro_clock = PomodoroClock::paused(sessions.iter(), "%M:%S");
+        let session_name = |pomodoro_clock: &PomodoroClock| {
+            pomodoro_clock.state_at(Instant::now()).unwrap().session_name
+        };
+
+        pomodoro_clock.update(&Request::Skip { to: None, count: 1 });
+        let ServerAction::Respond(Response::Reset(discarded)) = pomodoro_clock.update(&Request::Reset) else {
+            panic!("Reset wasn't confirmed");
+        };
+        assert_eq!(discarded.to_string(), "Discarded 1 session");
+        assert_eq!(session_name(&pomodoro_clock), "work");
+        // refused requests aren't undone
+        pomodoro_clock.update(&Request::EndInterrupt);
+
+        pomodoro_clock.update(&Request::Undo);
+        assert_eq!(session_name(&pomodoro_clock), "rest");
+        pomodoro_clock.update(&Request::Undo);
+        assert_eq!(session_name(&pomodoro_clock), "work");
+        assert!(matches!(
+            pomodoro_clock.update(&Request::Undo),
+            ServerAction::Respond(Response::Confirmation(Err(_))),
+        ));
+    }
+
+    #[test]
+    fn pomodoro_handover() {
+        let sessions = ["work", "rest"].map(|name| session(name, 100, SessionKind::Work));
+        let now = Instant::now();
+        let mut old_clock = PomodoroClock::running(sessions.iter(), "%M:%S", now);
+        let handover = old_clock.handover(now + Duration::from_secs(330)).unwrap();
+
+        // the new cycle is missing "work", and has sessions of different durations
+        let new_sessions = ["break", "rest"].map(|name| session(name, 200, SessionKind::Work));
+        let later = now + Duration::from_secs(400);
+        let mut new_clock = PomodoroClock::paused(new_sessions.iter(), "%M:%S");
+        assert!(new_clock.take_over(later, &handover));
+        let state = new_clock.state_at(later + Duration::from_secs(10)).unwrap();
+        assert_eq!(state.session_name, "rest");
+        assert_eq!(state.time, "02:40");
+        assert_eq!(state.pomodoros_done, 3);
+        assert!(!state.is_paused);
+
+        let handover = new_clock.handover(later + Duration::from_secs(200)).unwrap();
+        let mut new_clock = PomodoroClock::paused(sessions.iter(), "%M:%S");
+        assert!(!new_clock.take_over(later, &handover));
+        assert_eq!(new_clock.state_at(later).unwrap().time, "01:40");
+    }
+
+    #[test]
+    fn pomodoro_daily_goal() {
+        let sessions = ["work", "rest"].map(|name| session(name, 100, SessionKind::Work));
+        let mut pomodoro_clock = PomodoroClock::paused(sessions.iter(), "%M:%S")
+            .with_daily_goal(Some(8));
+        pomodoro_clock.clock = Clock::Paused { elapsed: Duration::from_secs(350) };
+        let state = pomodoro_clock.state_at(Instant::now()).unwrap();
+        assert_eq!((state.goal_done, state.goal_total), (3, Some(8)));
+
+        // the goal is tracked from scratch on the following day
+        pomodoro_clock.goal_day = pomodoro_clock.goal_day.pred_opt().unwrap();
+        let ServerAction::Respond(Response::State(state)) = pomodoro_clock.update(&Request::Fetch) else {
+            panic!("Expected the clock's state");
+        };
+        assert_eq!((state.pomodoros_done, state.goal_done), (3, 0));
+    }
+
+    #[test]
+    fn pomodoro_transition_grace() {
+        let sessions = ["work", "rest", "work2"].map(|name| session(name, 100, SessionKind::Work));
+        let mut pomodoro_clock = PomodoroClock::paused(sessions.iter(), "%M:%S")
+            .with_transition_grace(Duration::from_secs(30));
+        pomodoro_clock.clock = Clock::Paused { elapsed: Duration::from_secs(120) };
+        let state = pomodoro_clock.state_at(Instant::now()).unwrap();
+        assert!(state.is_in_transition);
+        assert_eq!((state.session_name.as_str(), state.time.as_str()), ("rest", "01:40"));
+
+        pomodoro_clock.clock = Clock::Paused { elapsed: Duration::from_secs(140) };
+        let state = pomodoro_clock.state_at(Instant::now()).unwrap();
+        assert!(!state.is_in_transition);
+        assert_eq!((state.session_name.as_str(), state.time.as_str()), ("rest", "01:30"));
+
+        // skipping during the grace period cancels the session right away
+        pomodoro_clock.clock = Clock::Paused { elapsed: Duration::from_secs(120) };
+        pomodoro_clock.skip_sessions(Instant::now(), 1).unwrap();
+        let state = pomodoro_clock.state_at(Instant::now()).unwrap();
+        assert!(!state.is_in_transition);
+        assert_eq!((state.session_name.as_str(), state.time.as_str()), ("work2", "01:40"));
+    }
+
+    #[test]
+    fn pomodoro_pause_on_start() {
+        let sessions = ["work", "rest"].map(|name| Session {
+            pause_on_start: name == "rest",
+            ..session(name, 100, SessionKind::Work)
+        });
+        let start = Instant::now();
+        let mut pomodoro_clock = PomodoroClock::running(sessions.iter(), "%M:%S", start);
+
+        let state = pomodoro_clock.state_at(start + Duration::from_secs(150)).unwrap();
+        assert!(state.is_paused);
+        assert_eq!((state.session_name.as_str(), state.time.as_str()), ("rest", "01:40"));
+
+        let resumed = start + Duration::from_secs(200);
+        pomodoro_clock.toggle(resumed).unwrap();
+        let state = pomodoro_clock.state_at(resumed + Duration::from_secs(10)).unwrap();
+        assert!(!state.is_paused);
+        assert_eq!((state.session_name.as_str(), state.time.as_str()), ("rest", "01:30"));
+
+        // sessions without the flag start on their own
+        let state = pomodoro_clock.state_at(resumed + Duration::from_secs(110)).unwrap();
+        assert!(!state.is_paused);
+        assert_eq!((state.session_name.as_str(), state.time.as_str()), ("work", "01:30"));
+    }
+
+    #[test]
+    fn pomodoro_checkpoints() {
+        let sessions = [
+            Session {
+                checkpoint_every: Some(Duration::from_secs(15 * 60)),
+                ..session("work", 50 * 60, SessionKind::Work)
+            },
+        ];
+        let mut pomodoro_clock = PomodoroClock::paused(sessions.iter(), "%M:%S");
+        pomodoro_clock.clock = Clock::Paused { elapsed: Duration::from_secs(20 * 60) };
+        let state = pomodoro_clock.state_at(Instant::now()).unwrap();
+        assert_eq!(state.checkpoint_index, Some(1));
+        assert_eq!(state.checkpoint_time.as_deref(), Some("10:00"));
+
+        // the last checkpoint interval is cut short by the end of the session
+        pomodoro_clock.clock = Clock::Paused { elapsed: Duration::from_secs(46 * 60) };
+        let state = pomodoro_clock.state_at(Instant::now()).unwrap();
+        assert_eq!(state.checkpoint_index, Some(3));
+        assert_eq!(state.checkpoint_time.as_deref(), Some("04:00"));
+    }
+
+    #[test]
+    fn pomodoro_interruptions() {
+        let sessions = [("work", SessionKind::Work), ("rest", SessionKind::Break)].map(|(name, kind)| session(name, 100, kind));
+        let mut pomodoro_clock = PomodoroClock::paused(sessions.iter(), "%M:%S");
+        let now = Instant::now();
+        assert!(pomodoro_clock.record_interruption(now, InterruptionKind::Internal).unwrap());
+        assert!(pomodoro_clock.record_interruption(now, InterruptionKind::Internal).unwrap());
+        assert!(pomodoro_clock.record_interruption(now, InterruptionKind::External).unwrap());
+        let state = pomodoro_clock.state_at(now).unwrap();
+        assert_eq!((state.internal_interruptions, state.external_interruptions), (2, 1));
+
+        pomodoro_clock.skip_sessions(now, 1).unwrap();
+        assert!(!pomodoro_clock.record_interruption(now, InterruptionKind::External).unwrap());
+
+        // every work session starts without interruptions
+        pomodoro_clock.skip_sessions(now, 1).unwrap();
+        let state = pomodoro_clock.state_at(now).unwrap();
+        assert_eq!((state.internal_interruptions, state.external_interruptions), (0, 0));
+    }
+
+    #[test]
+    fn pomodoro_count_up() {
+        let sessions = [
+            Session {
+                duration: None,
+                ..session("work", 0, SessionKind::Work)
+            },
+            session("rest", 100, SessionKind::Break),
+        ];
+        let mut pomodoro_clock = PomodoroClock::paused(sessions.iter(), "%M:%S");
+        pomodoro_clock.clock = Clock::Paused { elapsed: Duration::from_secs(3000) };
+
+        let state = pomodoro_clock.state_at(Instant::now()).unwrap();
+        assert_eq!(state.session_name, "work");
+        assert_eq!(state.time, "50:00");
+        assert_eq!(state.percent, 0);
+
+        pomodoro_clock.skip_sessions(Instant::now(), 1).unwrap();
+        let state = pomodoro_clock.state_at(Instant::now()).unwrap();
+        assert_eq!(state.session_name, "rest");
+        assert_eq!(state.time, "01:40");
+    }
+
+    #[test]
+    fn time_of_day_duration() {
+        let end_at = (Local::now() + TimeDelta::minutes(10)).time();
+        let duration = duration_until_time_of_day(end_at, Instant::now());
+        assert!(duration <= Duration::from_secs(600));
+        assert!(duration > Duration::from_secs(595));
+    }
+
+    #[test]
+    fn duration_format() {
+        let duration = Duration::from_secs(26 * 3600 + 5 * 60 + 9);
+        assert_eq!(duration_fmt(duration, "%H:%M:%S"), "26:05:09");
+        assert_eq!(duration_fmt(duration, "%T"), "26:05:09");
+        assert_eq!(duration_fmt(duration, "%hh %mm %ss"), "26h 1565m 93909s");
+        assert_eq!(duration_fmt(Duration::from_secs(65), "%R|%M:%S|%%|%q"), "00:01|01:05|%|%q");
+        assert_eq!(duration_fmt(Duration::from_millis(7250), "%S.%f"), "07.250");
+
+        assert!(check_duration_fmt("%T (%%)").is_ok());
+        assert!(check_duration_fmt("%M:%q").is_err());
+        assert!(check_duration_fmt("100%").is_err());
+    }
+
+    #[test]
+    fn request_wire_indices() {
+        // bincode tags variants with their index, appending is the only change
+        // that keeps older clients understood without bumping `PROTOCOL_VERSION`
+        let requests = [
+            Request::Fetch,
+            Request::Subscribe,
+            Request::Toggle,
+            Request::Pause,
+            Request::Resume,
+            Request::Skip { to: None, count: 1 },
+            Request::Back,
+            Request::RestartSession,
+            Request::Adjust(Adjustment::Extend(Duration::ZERO)),
+            Request::Snooze(Duration::ZERO),
+            Request::Queue { name: "tea".into(), duration: Duration::ZERO },
+            Request::Seek { elapsed: Duration::ZERO, within_cycle: false },
+            Request::Interrupt { name: "call".into(), duration: None },
+            Request::EndInterrupt,
+            Request::Interruption(InterruptionKind::Internal),
+            Request::Reset,
+            Request::Undo,
+            Request::Stop,
+            Request::Handover,
+            Request::Ping,
+            Request::Info,
+            Request::Sessions,
+            Request::AutoSkip(true),
+            Request::EditSessions(SessionEdit::Remove { name: "rest".into() }),
+        ];
+        for (index, request) in requests.iter().enumerate() {
+            let data = bincode::serialize(request).unwrap();
+            assert_eq!(data[..4], (index as u32).to_le_bytes(), "{request:?}");
+        }
     }
 }
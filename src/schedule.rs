@@ -0,0 +1,239 @@
+use serde::{Serialize, Serializer, Deserialize, Deserializer};
+
+use std::fmt;
+use std::error::Error;
+use std::str::FromStr;
+
+
+/// Most sessions a schedule may expand to, far more than any day holds
+const MAX_SESSIONS: u64 = 10_000;
+
+/// Declarative cycle made of session names, groups and repeats,
+/// e.g. `(work rest){3} work long_rest`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Schedule(Vec<Item>);
+
+#[derive(Debug, Clone, PartialEq)]
+enum Item {
+    Session { name: String, repeat: u32 },
+    Group { items: Vec<Item>, repeat: u32 },
+}
+
+impl Schedule {
+    /// Session names of one full cycle, with repeats expanded
+    pub fn names(&self) -> Vec<&str> {
+        fn expand<'a>(items: &'a [Item], names: &mut Vec<&'a str>) {
+            for item in items {
+                match item {
+                    Item::Session { name, repeat } => {
+                        names.extend(std::iter::repeat_n(name.as_str(), *repeat as usize));
+                    },
+                    Item::Group { items, repeat } => {
+                        for _ in 0..*repeat {
+                            expand(items, names);
+                        }
+                    },
+                }
+            }
+        }
+
+        let mut names = Vec::new();
+        expand(&self.0, &mut names);
+        names
+    }
+
+    /// Number of sessions `names` expands to, saturating instead of overflowing
+    fn len(items: &[Item]) -> u64 {
+        items.iter().fold(0u64, |len, item| {
+            let item_len = match item {
+                Item::Session { repeat, .. } => u64::from(*repeat),
+                Item::Group { items, repeat } => Self::len(items).saturating_mul(u64::from(*repeat)),
+            };
+            len.saturating_add(item_len)
+        })
+    }
+
+    /// Whether `name` in a schedule refers to the session named `session_name`,
+    /// spaces may be written as underscores, e.g. `long_rest` for "long rest"
+    pub fn refers_to(name: &str, session_name: &str) -> bool {
+        name == session_name || name == session_name.replace(' ', "_")
+    }
+}
+
+
+#[derive(Debug)]
+pub struct ScheduleError {
+    /// Byte offset in the schedule
+    position: usize,
+    message: String,
+}
+
+impl Error for ScheduleError {}
+
+impl fmt::Display for ScheduleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid schedule at {}: {}", self.position, self.message)
+    }
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    position: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, message: impl Into<String>) -> ScheduleError {
+        ScheduleError { position: self.position, message: message.into() }
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        let rest = &self.text[self.position..];
+        self.position += rest.len() - rest.trim_start().len();
+        self.text[self.position..].chars().next()
+    }
+
+    fn is_name_char(c: char) -> bool {
+        c.is_alphanumeric() || c == '_' || c == '-'
+    }
+
+    /// Items up to the end of the text, or of the current group
+    fn items(&mut self, depth: usize) -> Result<Vec<Item>, ScheduleError> {
+        let mut items = Vec::new();
+        loop {
+            let item = match self.peek() {
+                None if depth > 0 => return Err(self.error("unclosed '('")),
+                None => return Ok(items),
+                Some(')') if depth > 0 => {
+                    self.position += 1;
+                    return Ok(items);
+                },
+                Some('(') => {
+                    self.position += 1;
+                    let group = self.items(depth + 1)?;
+                    if group.is_empty() {
+                        return Err(self.error("empty group"));
+                    }
+                    Item::Group { items: group, repeat: self.repeat()? }
+                },
+                Some(c) if Self::is_name_char(c) => {
+                    let rest = &self.text[self.position..];
+                    let len = rest.find(|c| !Self::is_name_char(c)).unwrap_or(rest.len());
+                    self.position += len;
+                    Item::Session { name: rest[..len].into(), repeat: self.repeat()? }
+                },
+                Some(c) => return Err(self.error(format!("unexpected '{c}'"))),
+            };
+            items.push(item);
+        }
+    }
+
+    /// Optional `{n}` following an item
+    fn repeat(&mut self) -> Result<u32, ScheduleError> {
+        if self.peek() != Some('{') {
+            return Ok(1);
+        }
+        self.position += 1;
+        let rest = &self.text[self.position..];
+        let Some(len) = rest.find('}') else {
+            return Err(self.error("unclosed '{'"));
+        };
+        let repeat = rest[..len].trim()
+            .parse()
+            .map_err(|err| self.error(format!("invalid repeat count: {err}")))?;
+        if repeat == 0 {
+            return Err(self.error("an item repeated 0 times never runs"));
+        }
+        self.position += len + 1;
+        Ok(repeat)
+    }
+}
+
+impl FromStr for Schedule {
+    type Err = ScheduleError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser { text: s, position: 0 };
+        let items = parser.items(0)?;
+        if items.is_empty() {
+            return Err(parser.error("no sessions"));
+        }
+        if Schedule::len(&items) > MAX_SESSIONS {
+            return Err(ScheduleError {
+                position: 0,
+                message: format!("expands to more than {MAX_SESSIONS} sessions"),
+            });
+        }
+        Ok(Self(items))
+    }
+}
+
+impl fmt::Display for Schedule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fn write_items(f: &mut fmt::Formatter, items: &[Item]) -> fmt::Result {
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    write!(f, " ")?;
+                }
+                let repeat = match item {
+                    Item::Session { name, repeat } => {
+                        write!(f, "{name}")?;
+                        repeat
+                    },
+                    Item::Group { items, repeat } => {
+                        write!(f, "(")?;
+                        write_items(f, items)?;
+                        write!(f, ")")?;
+                        repeat
+                    },
+                };
+                if *repeat != 1 {
+                    write!(f, "{{{repeat}}}")?;
+                }
+            }
+            Ok(())
+        }
+
+        write_items(f, &self.0)
+    }
+}
+
+impl Serialize for Schedule {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Schedule {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn schedule_names() {
+        let schedule: Schedule = "(work rest){2} (work{2} nap) long_rest".parse().unwrap();
+        assert_eq!(
+            schedule.names(),
+            ["work", "rest", "work", "rest", "work", "work", "nap", "long_rest"],
+        );
+        assert!(Schedule::refers_to("long_rest", "long rest"));
+        assert_eq!(schedule.to_string(), "(work rest){2} (work{2} nap) long_rest");
+
+        assert!("(work rest".parse::<Schedule>().is_err());
+        assert!("work rest)".parse::<Schedule>().is_err());
+        assert!("work{x}".parse::<Schedule>().is_err());
+        assert!("()".parse::<Schedule>().is_err());
+        assert!("work{0}".parse::<Schedule>().is_err());
+        assert!("(work rest){0} nap".parse::<Schedule>().is_err());
+        assert!("(work rest){4000000000}".parse::<Schedule>().is_err());
+        assert!("((work rest){100}){100}".parse::<Schedule>().is_err());
+        assert_eq!("(work rest){5000}".parse::<Schedule>().unwrap().names().len(), 10_000);
+    }
+}
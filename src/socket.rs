@@ -1,78 +1,1363 @@
+use super::dbus::{self, Bus};
+use super::http::{self, HttpRequest};
+
 use serde::{Serialize, Deserialize};
 use serde::de::DeserializeOwned;
+use signal_hook::consts::{SIGINT, SIGTERM, SIGUSR1, SIGUSR2};
+use signal_hook::iterator::Signals;
 
+use std::fmt;
+use std::error::Error;
+use std::cell::Cell;
+use std::io::{self, BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::{Shutdown, SocketAddr as TcpAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::fs::{self, Permissions};
 use std::path::Path;
-use std::os::unix::net::UnixDatagram;
+use std::os::fd::{FromRawFd, OwnedFd, RawFd};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::{SocketAddr, UnixDatagram, UnixListener, UnixStream};
+#[cfg(target_os = "linux")]
+use std::os::linux::net::SocketAddrExt;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::time::{Duration, Instant};
 
 
+/// Bumped whenever requests or responses change shape
+pub const PROTOCOL_VERSION: u32 = 5;
 const MAX_UDP_PACKET_SIZE: usize = 65_535;
+/// Leads every chunk of a message too large for one datagram, followed by the id
+/// of the message, the index of the chunk and the number of chunks
+const CHUNK_MARKER: u8 = 0xc2;
+const CHUNK_HEADER_SIZE: usize = 1 + 4 + 2 + 2;
+/// Time a datagram client gets to make room for every chunk of a message
+/// after the first one
+const CHUNK_SEND_TIMEOUT: Duration = Duration::from_millis(500);
+/// Wait before sending a chunk again when the client's socket is full
+const CHUNK_RETRY_INTERVAL: Duration = Duration::from_millis(1);
+/// Largest response accepted over a stream
+const MAX_FRAME_SIZE: usize = 16 * 1024 * 1024;
+/// Largest request a server reads over a stream, before it can tell whether the
+/// client may send any, requests being far smaller than some responses
+const MAX_REQUEST_SIZE: usize = 64 * 1024;
+/// Leads every MessagePack message, a byte MessagePack never uses
+/// and bincode messages can't start with
+#[cfg(feature = "msgpack")]
+const MSGPACK_MARKER: u8 = 0xc1;
+/// First file descriptor passed by systemd socket activation
+const SD_LISTEN_FDS_START: RawFd = 3;
+/// Time a stream client gets to take in a response before it's given up on
+const STREAM_WRITE_TIMEOUT: Duration = Duration::from_millis(500);
+/// Time a stream client gets to send its first request, and the rest of
+/// any request once it started sending it
+const STREAM_READ_TIMEOUT: Duration = Duration::from_secs(5);
+/// Stream connections handled at once by a listener, the next ones wait to be accepted
+const MAX_STREAM_CONNECTIONS: usize = 64;
+/// Time between heartbeats sent to WebSocket subscribers
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
+/// Time an HTTP client gets to send its whole request
+const HTTP_READ_TIMEOUT: Duration = Duration::from_secs(5);
+/// HTTP connections handled at once, the next ones wait to be accepted
+const MAX_HTTP_CONNECTIONS: usize = 64;
+/// Wait before accepting again after failing to, e.g. when out of file descriptors
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+
+/// Encoding of requests and responses, the server answers every client
+/// in the encoding of its requests
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Protocol {
+    #[default]
+    Bincode,
+    /// Newline-delimited, e.g. `{"version":3,"id":1,"body":{"timer":null,"request":{"Skip":{"to":null,"count":1}}}}`
+    Json,
+    /// Prefixed with `MSGPACK_MARKER`
+    #[cfg(feature = "msgpack")]
+    #[value(name = "msgpack")]
+    #[serde(rename = "msgpack")]
+    MsgPack,
+    /// JSON-RPC 2.0 in place of the message envelope, framed like JSON,
+    /// only understood by servers
+    #[value(skip)]
+    #[serde(skip)]
+    JsonRpc,
+}
+
+impl Protocol {
+    /// Tells the protocol of a message from its first byte: JSON messages start
+    /// with `{` or `"`, JSON-RPC ones being the objects with a `jsonrpc` member,
+    /// MessagePack ones with `MSGPACK_MARKER`, and bincode ones with a small enum
+    /// variant index
+    fn detect(data: &[u8]) -> Self {
+        match data.iter().find(|byte| !byte.is_ascii_whitespace()) {
+            Some(b'{') if serde_json::from_slice::<RpcHeader>(data).is_ok() => Self::JsonRpc,
+            Some(b'{' | b'"') => Self::Json,
+            #[cfg(feature = "msgpack")]
+            Some(&MSGPACK_MARKER) => Self::MsgPack,
+            _ => Self::Bincode,
+        }
+    }
+
+    /// Tells the protocol of a stream from its first message, length prefixes
+    /// of framed messages end with zeros, unlike any JSON
+    fn detect_stream(stream: &mut impl Read) -> io::Result<(Self, [u8; 5])> {
+        let mut head = [0u8; 5];
+        stream.read_exact(&mut head)?;
+        let protocol = match head[2..4] {
+            [0, 0] => Self::detect(&head[4..]),
+            _ => Self::Json,
+        };
+        Ok((protocol, head))
+    }
+
+    fn encode(self, value: &impl Serialize) -> Vec<u8> {
+        match self {
+            Self::Bincode => bincode::serialize(value).unwrap(),
+            Self::Json | Self::JsonRpc => {
+                let mut data = serde_json::to_vec(value).unwrap();
+                data.push(b'\n');
+                data
+            },
+            #[cfg(feature = "msgpack")]
+            Self::MsgPack => {
+                let mut data = vec![MSGPACK_MARKER];
+                rmp_serde::encode::write_named(&mut data, value).unwrap();
+                data
+            },
+        }
+    }
+
+    fn decode<'de, T: Deserialize<'de>>(self, data: &'de [u8]) -> io::Result<T> {
+        let invalid = |err: String| io::Error::new(ErrorKind::InvalidData, err);
+        match self {
+            Self::Bincode => bincode::deserialize(data).map_err(|err| invalid(err.to_string())),
+            Self::Json | Self::JsonRpc => serde_json::from_slice(data).map_err(|err| invalid(err.to_string())),
+            #[cfg(feature = "msgpack")]
+            Self::MsgPack => rmp_serde::from_slice(data.strip_prefix(&[MSGPACK_MARKER]).unwrap_or(data))
+                .map_err(|err| invalid(err.to_string())),
+        }
+    }
+
+    /// Sends an encoded message over a stream, length-prefixed so that binary messages
+    /// can be told apart, JSON messages already end with a newline
+    fn write_frame(self, stream: &mut impl Write, data: &[u8]) -> io::Result<()> {
+        if !matches!(self, Self::Json | Self::JsonRpc) {
+            stream.write_all(&(data.len() as u32).to_le_bytes())?;
+        }
+        stream.write_all(data)
+    }
+
+    /// Message of up to `max_len` bytes
+    fn read_frame(self, stream: &mut impl BufRead, max_len: usize) -> io::Result<Vec<u8>> {
+        let mut data = Vec::new();
+        match self {
+            Self::Json | Self::JsonRpc => {
+                stream.take(max_len as u64).read_until(b'\n', &mut data)?;
+                if !data.ends_with(b"\n") {
+                    return Err(io::Error::new(ErrorKind::UnexpectedEof, "Incomplete message"));
+                }
+            },
+            _ => {
+                let mut len = [0u8; 4];
+                stream.read_exact(&mut len)?;
+                let len = u32::from_le_bytes(len) as usize;
+                if len > max_len {
+                    return Err(io::Error::new(ErrorKind::InvalidData, "Frame too large"));
+                }
+                data.resize(len, 0);
+                stream.read_exact(&mut data)?;
+            },
+        }
+        Ok(data)
+    }
+}
+
+
+/// Every request and response, versioned so that clients and servers
+/// of different versions can tell they don't understand each other
+#[derive(Serialize, Deserialize)]
+struct Message<T> {
+    version: u32,
+    /// Chosen by the client for every request, responses and updates carry
+    /// the id of the request they follow
+    id: u32,
+    /// Shared secret of the server, only checked for requests over TCP
+    #[serde(default)]
+    token: Option<String>,
+    body: T,
+}
+
+/// Leading part of a `Message` of any version
+#[derive(Deserialize)]
+struct Version {
+    version: u32,
+}
+
+/// Leading part of a `Message` of the current version
+#[derive(Deserialize)]
+struct Header {
+    #[allow(unused)]
+    version: u32,
+    id: u32,
+}
+
+impl<T> Message<T> {
+    fn new(id: u32, body: T) -> Self {
+        Self { version: PROTOCOL_VERSION, id, token: None, body }
+    }
+
+    /// Message of the current version
+    fn decode<'de>(protocol: Protocol, data: &'de [u8]) -> Result<Self, DecodeError>
+    where
+        T: Deserialize<'de>,
+    {
+        let version = protocol.decode::<Version>(data)
+            .ok()
+            .map(|Version { version }| version);
+        if version != Some(PROTOCOL_VERSION) {
+            return Err(DecodeError::Version(version));
+        }
+        protocol.decode(data).map_err(|err| {
+            let id = protocol.decode::<Header>(data).map_or(0, |header| header.id);
+            DecodeError::Malformed(id, err.to_string())
+        })
+    }
+}
+
+/// Leading part of a JSON-RPC message of any version
+#[derive(Deserialize)]
+struct RpcHeader {
+    #[allow(unused)]
+    jsonrpc: serde::de::IgnoredAny,
+}
+
+/// JSON-RPC 2.0 request, which is a notification without an `id`
+#[derive(Deserialize)]
+struct RpcRequest {
+    #[allow(unused)]
+    jsonrpc: RpcVersion,
+    id: Option<serde_json::Value>,
+    method: String,
+    #[serde(default)]
+    params: serde_json::Value,
+    /// Not part of JSON-RPC, required over TCP as in other messages
+    #[serde(default)]
+    token: Option<String>,
+}
+
+/// Only the 2.0 version is accepted
+#[derive(Deserialize)]
+#[serde(try_from = "String")]
+struct RpcVersion;
+
+impl TryFrom<String> for RpcVersion {
+    type Error = String;
+
+    fn try_from(version: String) -> Result<Self, Self::Error> {
+        match version.as_str() {
+            "2.0" => Ok(Self),
+            _ => Err(format!("Unsupported JSON-RPC version {version}")),
+        }
+    }
+}
+
+/// Request of a JSON-RPC message in the server's own terms, along with its id and token
+fn decode_rpc<'de, S: ServerState>(
+    data: &[u8],
+) -> (Option<serde_json::Value>, Option<String>, Result<S::Request<'de>, ProtocolError>) {
+    let RpcRequest { id, method, params, token, .. } = match serde_json::from_slice(data) {
+        Ok(request) => request,
+        Err(err) => return (None, None, Err(ProtocolError::Malformed(err.to_string()))),
+    };
+    let request = S::rpc_request(&method, params)
+        .ok_or(ProtocolError::UnknownMethod(method))
+        .and_then(|body| S::Request::deserialize(body).map_err(|err| ProtocolError::Malformed(err.to_string())));
+    (id, token, request)
+}
+
+/// Compares every byte whatever the first difference, so that the time
+/// taken doesn't tell how much of a token is right
+fn is_token_valid(expected: &str, token: Option<&str>) -> bool {
+    let Some(token) = token else { return false; };
+    token.len() == expected.len()
+        && token.bytes().zip(expected.bytes()).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+/// Response to a JSON-RPC request, or a notification of an update without an `id`
+fn rpc_message<T: Serialize>(id: Option<&serde_json::Value>, response: Result<&T, ProtocolError>) -> serde_json::Value {
+    match (id, response) {
+        (Some(id), Ok(result)) => serde_json::json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        (None, Ok(params)) => serde_json::json!({ "jsonrpc": "2.0", "method": "update", "params": params }),
+        (id, Err(err)) => {
+            let code = match err {
+                ProtocolError::UnknownMethod(_) => -32601,
+                _ => -32600,
+            };
+            let error = serde_json::json!({ "code": code, "message": err.to_string() });
+            serde_json::json!({ "jsonrpc": "2.0", "id": id, "error": error })
+        },
+    }
+}
+
+enum DecodeError {
+    /// Version of a message of another version, `None` for messages sent before versioning
+    Version(Option<u32>),
+    /// Id of the message if it could be read, 0 otherwise
+    Malformed(u32, String),
+}
+
+/// Reason the server couldn't handle a request, sent in place of a response
+#[derive(Debug, Serialize, Deserialize)]
+pub enum ProtocolError {
+    /// Versions are `None` for programs older than versioning
+    IncompatibleVersion {
+        server: Option<u32>,
+        client: Option<u32>,
+    },
+    /// Request that couldn't be deserialized
+    Malformed(String),
+    /// JSON-RPC method the server doesn't have
+    UnknownMethod(String),
+    /// Request over TCP without the server's token
+    Unauthorized,
+}
+
+impl Error for ProtocolError {}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let version = |version: Option<u32>| version
+            .map_or("unversioned".into(), |version| version.to_string());
+        match self {
+            Self::IncompatibleVersion { server, client } => write!(
+                f,
+                "Incompatible protocol versions, server: {}, client: {}",
+                version(*server),
+                version(*client),
+            ),
+            Self::Malformed(err) => write!(f, "Malformed request: {err}"),
+            Self::UnknownMethod(method) => write!(f, "Unknown method '{method}'"),
+            Self::Unauthorized => write!(f, "Missing or wrong auth token"),
+        }
+    }
+}
+
+
+/// Where the Unix sockets of servers and clients live
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SocketKind {
+    /// Socket files in the socket directory
+    #[default]
+    File,
+    /// Linux abstract namespace, nothing is left behind on the filesystem
+    Abstract,
+}
+
+impl SocketKind {
+    /// Address of the socket called `name`, `dir` only matters for `SocketKind::File`
+    pub fn addr(self, dir: &Path, name: &str) -> io::Result<SocketAddr> {
+        match self {
+            Self::File => SocketAddr::from_pathname(dir.join(name)),
+            #[cfg(target_os = "linux")]
+            Self::Abstract => SocketAddr::from_abstract_name(format!("pomidoro/{name}")),
+            #[cfg(not(target_os = "linux"))]
+            Self::Abstract => Err(io::Error::new(
+                ErrorKind::Unsupported,
+                "Abstract sockets are only available on Linux",
+            )),
+        }
+    }
+}
+
+
+/// How messages travel over the Unix sockets of servers and clients
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SocketType {
+    /// One message per datagram, up to `MAX_UDP_PACKET_SIZE` bytes
+    #[default]
+    Datagram,
+    /// Length-prefixed frames over a connection, up to `MAX_REQUEST_SIZE` bytes
+    /// for requests and `MAX_FRAME_SIZE` bytes for responses
+    Stream,
+}
 
 
 #[derive(Debug, Clone, Copy)]
 pub enum ServerAction<T: Serialize> {
     Respond(T),
-    #[allow(unused)]
+    /// Responds, then keeps sending the requester whatever `ServerState::publish` returns
+    Subscribe(T),
+    /// Responds, then stops the server
     StopRespond(T),
-    #[allow(unused)]
     Stop,
-    #[allow(unused)]
     None,
 }
 
+/// Signals taken as requests by a server, on top of the ones stopping it
+#[derive(Debug, Clone, Copy)]
+pub enum UserSignal {
+    Usr1,
+    Usr2,
+}
+
 pub trait ServerState {
     type Request<'de>: Deserialize<'de>;
     type Response: Serialize;
 
     fn update<'de>(&mut self, request: &Self::Request<'de>) -> ServerAction<Self::Response>;
+
+    /// Time until `tick` gets called, whether requests arrive in the meantime or not,
+    /// checked again after every request and tick
+    fn next_tick(&self) -> Option<Duration> {
+        None
+    }
+
+    /// There's nobody to respond to, so responses are dropped
+    fn tick(&mut self) -> ServerAction<Self::Response> {
+        ServerAction::None
+    }
+
+    /// Request made by an HTTP `method` on `target`, in its JSON form,
+    /// `None` if the target isn't found
+    fn http_request(_method: &str, _target: &str) -> Option<serde_json::Value> {
+        None
+    }
+
+    /// Sent every second to WebSocket subscribers, so that they can keep
+    /// the time up to date without polling
+    fn heartbeat(&mut self) -> Vec<Self::Response> {
+        Vec::new()
+    }
+
+    /// Request named by the JSON-RPC `method`, in its JSON form,
+    /// `None` if there's no such method
+    fn rpc_request(_method: &str, _params: serde_json::Value) -> Option<serde_json::Value> {
+        None
+    }
+
+    /// Request made by calling a D-Bus `method` of `dbus::INTERFACE`, in its JSON form,
+    /// `None` if there's no such method
+    fn dbus_request(_method: &str) -> Option<serde_json::Value> {
+        None
+    }
+
+    /// Called on SIGUSR1 and SIGUSR2, responses are dropped as with `tick`
+    fn signal(&mut self, _signal: UserSignal) -> ServerAction<Self::Response> {
+        ServerAction::None
+    }
+
+    /// Checked after every request and tick, each update sent to all of the subscribers
+    fn publish(&mut self) -> Vec<Self::Response> {
+        Vec::new()
+    }
+}
+
+/// Whether a server is bound to the socket at `addr`, as opposed to
+/// a socket file left behind, whatever the type of the socket
+pub fn is_server_alive(addr: &SocketAddr) -> bool {
+    UnixDatagram::unbound()
+        .and_then(|socket| socket.connect_addr(addr))
+        .is_ok()
+        || UnixStream::connect_addr(addr).is_ok()
+}
+
+
+/// Number of sockets passed to this process by systemd socket activation
+fn listen_fds() -> Option<RawFd> {
+    let pid = std::env::var("LISTEN_PID").ok()?;
+    if pid.parse() != Ok(std::process::id()) {
+        return None;
+    }
+    std::env::var("LISTEN_FDS").ok()?
+        .parse()
+        .ok()
+        .filter(|&fds| fds > 0)
+}
+
+/// Whether the server was started by systemd with its sockets already bound,
+/// in which case systemd owns them
+pub fn is_socket_activated() -> bool {
+    listen_fds().is_some()
+}
+
+/// Sockets passed by systemd, a Unix socket optionally followed by a TCP listener,
+/// the environment is cleared so that they aren't taken twice
+fn take_activated_sockets() -> Option<(OwnedFd, Option<TcpListener>)> {
+    let fds = listen_fds()?;
+    std::env::remove_var("LISTEN_PID");
+    std::env::remove_var("LISTEN_FDS");
+    std::env::remove_var("LISTEN_FDNAMES");
+    // SAFETY: systemd hands these over to this process alone, in this order
+    unsafe {
+        let socket = OwnedFd::from_raw_fd(SD_LISTEN_FDS_START);
+        let listener = (fds > 1).then(|| TcpListener::from_raw_fd(SD_LISTEN_FDS_START + 1));
+        Some((socket, listener))
+    }
+}
+
+
+/// Connection over TCP or a Unix stream socket
+enum Stream {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+impl Stream {
+    fn try_clone(&self) -> io::Result<Self> {
+        match self {
+            Self::Tcp(stream) => stream.try_clone().map(Self::Tcp),
+            Self::Unix(stream) => stream.try_clone().map(Self::Unix),
+        }
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            Self::Tcp(stream) => stream.set_read_timeout(timeout),
+            Self::Unix(stream) => stream.set_read_timeout(timeout),
+        }
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            Self::Tcp(stream) => stream.set_write_timeout(timeout),
+            Self::Unix(stream) => stream.set_write_timeout(timeout),
+        }
+    }
+
+    fn shutdown(&self) -> io::Result<()> {
+        match self {
+            Self::Tcp(stream) => stream.shutdown(Shutdown::Both),
+            Self::Unix(stream) => stream.shutdown(Shutdown::Both),
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Self::Tcp(stream) => stream.read(buf),
+            Self::Unix(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for &Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => (&*stream).write(buf),
+            Stream::Unix(stream) => (&*stream).write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => (&*stream).flush(),
+            Stream::Unix(stream) => (&*stream).flush(),
+        }
+    }
 }
 
-pub fn start_server<S: ServerState>(path: &Path, mut state: S) -> std::io::Result<()> {
-    let socket = UnixDatagram::bind(path)?;
-    let mut buffer = vec![0u8; MAX_UDP_PACKET_SIZE];
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        (&*self).write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        (&*self).flush()
+    }
+}
+
+/// Requester a response is sent back to, along with how
+enum Client {
+    Datagram(SocketAddr),
+    Stream {
+        stream: Stream,
+        /// Tells connections apart, Unix stream clients don't have addresses
+        connection: u64,
+    },
+    /// Gets a single HTTP response, then the connection is closed
+    Http(Stream),
+    /// Gets every response in its own text frame, along with heartbeats
+    WebSocket(Stream),
+    /// Gets the reply to its method call, or updates as signals without one
+    DBus {
+        bus: Arc<Bus>,
+        call: Option<dbus::Message>,
+    },
+}
+
+impl Client {
+    /// What the client is connected over, for logs
+    fn kind(&self) -> &'static str {
+        match self {
+            Self::Datagram(_) => "datagram",
+            Self::Stream { stream: Stream::Unix(_), .. } => "stream",
+            Self::Stream { stream: Stream::Tcp(_), .. } => "TCP",
+            Self::Http(_) => "HTTP",
+            Self::WebSocket(_) => "WebSocket",
+            Self::DBus { .. } => "D-Bus",
+        }
+    }
+
+    /// Whether the client has to send the token, HTTP clients are checked
+    /// before their requests get this far
+    fn needs_token(&self) -> bool {
+        matches!(self, Self::Stream { stream: Stream::Tcp(_), .. })
+    }
+
+    fn is_same(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Datagram(addr), Self::Datagram(other)) => {
+                #[cfg(target_os = "linux")]
+                if addr.as_abstract_name() != other.as_abstract_name() {
+                    return false;
+                }
+                addr.as_pathname() == other.as_pathname()
+            },
+            (Self::Stream { connection, .. }, Self::Stream { connection: other, .. }) => connection == other,
+            _ => false,
+        }
+    }
+}
+
+/// Client along with the request it made
+struct Requester {
+    client: Client,
+    protocol: Protocol,
+    request_id: u32,
+    /// Id of a JSON-RPC request, updates are sent as notifications without one
+    rpc_id: Option<serde_json::Value>,
+}
+
+enum Event {
+    Request(Vec<u8>, Client),
+    Signal(UserSignal),
+    Stop,
+}
+
+/// Sends a message in as many datagrams as it takes, each one through `send`,
+/// which may fail with `ErrorKind::WouldBlock`: nothing is sent if the first
+/// chunk would block, the next ones are retried so as not to cut a message short
+fn send_chunked(data: &[u8], message_id: u32, mut send: impl FnMut(&[u8]) -> io::Result<()>) -> io::Result<()> {
+    if data.len() <= MAX_UDP_PACKET_SIZE {
+        return send(data);
+    }
+    let chunks = data.chunks(MAX_UDP_PACKET_SIZE - CHUNK_HEADER_SIZE);
+    let count = u16::try_from(chunks.len())
+        .map_err(|_| io::Error::new(ErrorKind::InvalidInput, "Message too large"))?;
+    for (index, chunk) in (0u16..).zip(chunks) {
+        let mut datagram = Vec::with_capacity(CHUNK_HEADER_SIZE + chunk.len());
+        datagram.push(CHUNK_MARKER);
+        datagram.extend(message_id.to_le_bytes());
+        datagram.extend(index.to_le_bytes());
+        datagram.extend(count.to_le_bytes());
+        datagram.extend(chunk);
+        let deadline = Instant::now() + CHUNK_SEND_TIMEOUT;
+        loop {
+            match send(&datagram) {
+                Err(err) if err.kind() == ErrorKind::WouldBlock && index > 0 => {
+                    if Instant::now() >= deadline {
+                        return Err(io::Error::new(ErrorKind::TimedOut, "Client didn't take in the whole message"));
+                    }
+                    std::thread::sleep(CHUNK_RETRY_INTERVAL);
+                },
+                result => break result?,
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Puts chunked messages back together, relying on datagrams arriving in order
+#[derive(Default)]
+struct Reassembly {
+    message_id: u32,
+    next_index: u16,
+    data: Vec<u8>,
+}
+
+impl Reassembly {
+    /// Whole message once its last chunk arrives, datagrams that aren't chunks
+    /// are whole already, chunks of an incomplete message are dropped
+    fn push(&mut self, datagram: Vec<u8>) -> Option<Vec<u8>> {
+        let Some((header, chunk)) = datagram
+            .split_first_chunk::<CHUNK_HEADER_SIZE>()
+            .filter(|(header, _)| header[0] == CHUNK_MARKER)
+        else {
+            return Some(datagram);
+        };
+        let message_id = u32::from_le_bytes([header[1], header[2], header[3], header[4]]);
+        let index = u16::from_le_bytes([header[5], header[6]]);
+        let count = u16::from_le_bytes([header[7], header[8]]);
+
+        if index == 0 {
+            *self = Self { message_id, next_index: 0, data: Vec::new() };
+        }
+        if message_id != self.message_id || index != self.next_index {
+            return None;
+        }
+        self.data.extend(chunk);
+        self.next_index += 1;
+        (self.next_index == count).then(|| std::mem::take(&mut self.data))
+    }
+}
+
+/// Sends `Event::Stop` on SIGINT and SIGTERM, and `Event::Signal` on SIGUSR1 and SIGUSR2
+fn forward_signals(events: Sender<Event>) -> io::Result<()> {
+    let mut signals = Signals::new([SIGINT, SIGTERM, SIGUSR1, SIGUSR2])?;
+    std::thread::spawn(move || {
+        for signal in signals.forever() {
+            let event = match signal {
+                SIGUSR1 => Event::Signal(UserSignal::Usr1),
+                SIGUSR2 => Event::Signal(UserSignal::Usr2),
+                _ => Event::Stop,
+            };
+            let is_stop = matches!(event, Event::Stop);
+            if events.send(event).is_err() || is_stop {
+                break;
+            }
+        }
+    });
+    Ok(())
+}
+
+fn receive_datagrams(socket: UnixDatagram, events: Sender<Event>) {
+    std::thread::spawn(move || {
+        let mut buffer = vec![0u8; MAX_UDP_PACKET_SIZE];
+        while let Ok((size, sock_addr)) = socket.recv_from(&mut buffer) {
+            let request = Event::Request(buffer[..size].to_vec(), Client::Datagram(sock_addr));
+            if events.send(request).is_err() {
+                break;
+            }
+        }
+    });
+}
+
+/// Receives requests over every connection `accept` returns
+fn receive_streams(mut accept: impl FnMut() -> io::Result<Stream> + Send + 'static, events: Sender<Event>) {
+    static NEXT_CONNECTION: AtomicU64 = AtomicU64::new(0);
+
+    let open = Arc::new(AtomicUsize::new(0));
+    std::thread::spawn(move || loop {
+        // idle clients can't make the server run out of threads
+        if open.load(Ordering::Acquire) >= MAX_STREAM_CONNECTIONS {
+            std::thread::sleep(ACCEPT_BACKOFF);
+            continue;
+        }
+        let stream = match accept() {
+            Ok(stream) => stream,
+            Err(err) => {
+                log::warn!("Couldn't accept a connection: {err}");
+                std::thread::sleep(ACCEPT_BACKOFF);
+                continue;
+            },
+        };
+        open.fetch_add(1, Ordering::AcqRel);
+        let open = open.clone();
+        let connection = NEXT_CONNECTION.fetch_add(1, Ordering::Relaxed);
+        let events = events.clone();
+        // every connection gets its own thread, so that one can't hold up the others
+        std::thread::spawn(move || {
+            let result = serve_stream(stream, connection, &events);
+            open.fetch_sub(1, Ordering::AcqRel);
+            result
+        });
+    });
+}
+
+/// Reads the requests of a stream connection until it's closed
+fn serve_stream(stream: Stream, connection: u64, events: &Sender<Event>) -> io::Result<()> {
+    stream.set_read_timeout(Some(STREAM_READ_TIMEOUT))?;
+    stream.set_write_timeout(Some(STREAM_WRITE_TIMEOUT))?;
+    let mut reader = stream.try_clone()?;
+    // a connection sticks to the protocol of its first message
+    let (protocol, head) = Protocol::detect_stream(&mut reader)?;
+    let mut reader = BufReader::new(io::Cursor::new(head).chain(reader));
     loop {
-        let (size, sock_addr) = socket.recv_from(&mut buffer)?;
-        let received_data = &buffer[..size];
-        let request = bincode::deserialize(received_data).unwrap(); // TODO
-
-        let action = state.update(&request);
-        match action {
-            ServerAction::Respond(ref response) | ServerAction::StopRespond(ref response) => {
-                let response_data = bincode::serialize(&response).unwrap();
-                socket.send_to_addr(&response_data, &sock_addr)?;
-
-                if matches!(action, ServerAction::StopRespond(_)) {
-                    break Ok(());
+        let data = protocol.read_frame(&mut reader, MAX_REQUEST_SIZE)?;
+        let client = Client::Stream { stream: stream.try_clone()?, connection };
+        if events.send(Event::Request(data, client)).is_err() {
+            return Ok(());
+        }
+        // clients may take their time between requests, e.g. polling or
+        // subscribed ones, but not within one
+        stream.set_read_timeout(None)?;
+        if reader.fill_buf()?.is_empty() {
+            return Ok(());
+        }
+        stream.set_read_timeout(Some(STREAM_READ_TIMEOUT))?;
+    }
+}
+
+/// There's no TLS yet, so anyone on the network path can read the auth token
+fn warn_unencrypted(addr: TcpAddr) {
+    if !addr.ip().is_loopback() {
+        log::warn!("Requests to {addr} aren't encrypted, the auth token included, tunnel them over SSH or a TLS proxy");
+    }
+}
+
+/// Turns every HTTP request into a JSON message, unless it's not found,
+/// completing WebSocket upgrades first
+fn receive_http<S: ServerState>(listener: TcpListener, token: String, events: Sender<Event>) {
+    let open = Arc::new(AtomicUsize::new(0));
+    std::thread::spawn(move || loop {
+        // slow clients can't make the server run out of threads
+        if open.load(Ordering::Acquire) >= MAX_HTTP_CONNECTIONS {
+            std::thread::sleep(ACCEPT_BACKOFF);
+            continue;
+        }
+        let stream = match listener.accept() {
+            Ok((stream, _)) => stream,
+            Err(err) => {
+                log::warn!("Couldn't accept an HTTP connection: {err}");
+                std::thread::sleep(ACCEPT_BACKOFF);
+                continue;
+            },
+        };
+        open.fetch_add(1, Ordering::AcqRel);
+        let open = open.clone();
+        let events = events.clone();
+        let token = token.clone();
+        std::thread::spawn(move || {
+            let result = serve_http::<S>(stream, &token, &events);
+            open.fetch_sub(1, Ordering::AcqRel);
+            result
+        });
+    });
+}
+
+/// Reads the one request of an HTTP connection
+fn serve_http<S: ServerState>(stream: TcpStream, token: &str, events: &Sender<Event>) -> io::Result<()> {
+    stream.set_read_timeout(Some(HTTP_READ_TIMEOUT))?;
+    stream.set_write_timeout(Some(STREAM_WRITE_TIMEOUT))?;
+    let request = HttpRequest::read(&mut BufReader::new(&stream));
+    let stream = Stream::Tcp(stream);
+    let request = match request {
+        Ok(request) => request,
+        Err(err) => return http::write_response(&mut &stream, 400, &json_error(&err.to_string())),
+    };
+    let bearer = request.header("authorization").and_then(|value| value.strip_prefix("Bearer "));
+    if !is_token_valid(token, bearer) {
+        return http::write_response(&mut &stream, 401, &json_error(&ProtocolError::Unauthorized.to_string()));
+    }
+    match S::http_request(&request.method, &request.target) {
+        Some(body) => {
+            let client = if request.is_websocket_upgrade() {
+                http::accept_websocket(&mut &stream, &request)?;
+                Client::WebSocket(stream)
+            } else {
+                Client::Http(stream)
+            };
+            let data = Protocol::Json.encode(&Message::new(0, body));
+            let _ = events.send(Event::Request(data, client));
+            Ok(())
+        },
+        None => http::write_response(&mut &stream, 404, &json_error("Not found")),
+    }
+}
+
+/// Turns every call of a method of `dbus::INTERFACE` into a JSON message,
+/// answering calls of the standard interfaces on its own
+fn receive_dbus<S: ServerState>(bus: Arc<Bus>, mut reader: UnixStream, events: Sender<Event>) {
+    std::thread::spawn(move || -> io::Result<()> {
+        loop {
+            let call = dbus::Message::read(&mut reader)?;
+            if !call.is_method_call() || bus.handle_standard_call(&call)? {
+                continue;
+            }
+            let is_ours = call.path.as_deref() == Some(dbus::PATH)
+                && call.interface.as_deref().is_none_or(|interface| interface == dbus::INTERFACE);
+            match call.member.as_deref().filter(|_| is_ours).and_then(S::dbus_request) {
+                Some(body) => {
+                    let data = Protocol::Json.encode(&Message::new(0, body));
+                    let client = Client::DBus { bus: bus.clone(), call: Some(call) };
+                    let _ = events.send(Event::Request(data, client));
+                },
+                None => {
+                    let error = "org.freedesktop.DBus.Error.UnknownMethod";
+                    bus.reply_error(&call, error, "No such method")?;
+                },
+            }
+        }
+    });
+}
+
+fn json_error(msg: &str) -> String {
+    serde_json::json!({ "error": msg }).to_string()
+}
+
+/// Access to a socket file, set right after binding it
+#[derive(Debug, Clone, Copy)]
+pub struct SocketPermissions {
+    pub mode: u32,
+    pub group: Option<u32>,
+}
+
+impl SocketPermissions {
+    fn apply(&self, path: &Path) -> io::Result<()> {
+        if self.group.is_some() {
+            std::os::unix::fs::chown(path, None, self.group)?;
+        }
+        fs::set_permissions(path, Permissions::from_mode(self.mode))
+    }
+}
+
+/// Ways of reaching a server besides its Unix socket
+#[derive(Debug, Default)]
+pub struct Transports {
+    /// Accepts the same requests as the Unix socket over TCP
+    pub listen: Option<TcpAddr>,
+    pub http: Option<TcpAddr>,
+    /// Exports the state on the session bus
+    pub dbus: bool,
+    /// Shared secret every request over TCP has to carry, required to listen on TCP
+    pub token: Option<String>,
+}
+
+/// Serves requests over the Unix socket at `addr`, and over the other `transports`,
+/// returns once stopped by the state or by a signal, leaving the socket file behind,
+/// uses the sockets passed by systemd instead of binding any when socket activated
+pub fn start_server<S: ServerState>(
+    addr: &SocketAddr,
+    socket_type: SocketType,
+    permissions: SocketPermissions,
+    transports: Transports,
+    mut state: S,
+) -> io::Result<()> {
+    let Transports { listen, http, dbus, token } = transports;
+    let (events, received) = mpsc::channel();
+    let bus = dbus.then(Bus::connect).transpose()?.map(|(bus, reader)| {
+        let bus = Arc::new(bus);
+        receive_dbus::<S>(bus.clone(), reader, events.clone());
+        bus
+    });
+    let (socket, listener) = match take_activated_sockets() {
+        Some(sockets) => sockets,
+        None => {
+            let socket = match socket_type {
+                SocketType::Datagram => UnixDatagram::bind_addr(addr)?.into(),
+                SocketType::Stream => UnixListener::bind_addr(addr)?.into(),
+            };
+            // abstract sockets have no file to restrict
+            if let Some(path) = addr.as_pathname() {
+                permissions.apply(path)?;
+            }
+            (socket, listen.map(TcpListener::bind).transpose()?)
+        },
+    };
+    match socket_type {
+        SocketType::Datagram => receive_datagrams(UnixDatagram::from(socket), events.clone()),
+        SocketType::Stream => {
+            let listener = UnixListener::from(socket);
+            let accept = move || listener.accept().map(|(stream, _)| Stream::Unix(stream));
+            receive_streams(accept, events.clone());
+        },
+    }
+    let token = match token {
+        Some(token) => token,
+        None if listener.is_some() || http.is_some() => {
+            let msg = "Accepting requests over TCP requires an `auth_token` in the config";
+            return Err(io::Error::new(ErrorKind::InvalidInput, msg));
+        },
+        None => String::new(),
+    };
+    if let Some(listener) = listener {
+        log::info!("Listening over TCP at {}", listener.local_addr()?);
+        warn_unencrypted(listener.local_addr()?);
+        let accept = move || listener.accept().map(|(stream, _)| Stream::Tcp(stream));
+        receive_streams(accept, events.clone());
+    }
+    if let Some(http) = http {
+        let listener = TcpListener::bind(http)?;
+        log::info!("Listening over HTTP at {}", listener.local_addr()?);
+        warn_unencrypted(listener.local_addr()?);
+        receive_http::<S>(listener, token.clone(), events.clone());
+    }
+    forward_signals(events)?;
+    log::info!("Listening on {addr:?} with {socket_type:?} sockets");
+
+    // responses are sent from a separate non-blocking socket, so that a client
+    // not reading them can't stall the server
+    let sender = UnixDatagram::unbound()?;
+    sender.set_nonblocking(true)?;
+    let next_message_id = Cell::new(0u32);
+    let send = |response: Result<&S::Response, ProtocolError>, requester: &Requester| {
+        let Requester { client, protocol, request_id, rpc_id } = requester;
+        let encode = |response| match protocol {
+            Protocol::JsonRpc => protocol.encode(&rpc_message(rpc_id.as_ref(), response)),
+            _ => protocol.encode(&Message::new(*request_id, response)),
+        };
+        match client {
+            Client::Datagram(sock_addr) => {
+                let message_id = next_message_id.replace(next_message_id.get().wrapping_add(1));
+                send_chunked(&encode(response), message_id, |datagram| {
+                    sender.send_to_addr(datagram, sock_addr).map(|_| ())
+                })
+            },
+            Client::Stream { stream, .. } => protocol.write_frame(&mut &*stream, &encode(response)),
+            // without the envelope, the status tells errors apart
+            Client::Http(stream) => match response {
+                Ok(response) => http::write_response(&mut &*stream, 200, &serde_json::to_string(response).unwrap()),
+                Err(err) => http::write_response(&mut &*stream, 400, &json_error(&err.to_string())),
+            },
+            Client::WebSocket(stream) => {
+                let text = match response {
+                    Ok(response) => serde_json::to_string(response).unwrap(),
+                    Err(err) => json_error(&err.to_string()),
+                };
+                http::write_websocket_text(&mut &*stream, &text)
+            },
+            Client::DBus { bus, call } => match (call, response) {
+                (Some(call), Ok(response)) => bus.reply(call, &serde_json::to_string(response).unwrap()),
+                (Some(call), Err(err)) => bus.reply_error(call, &format!("{}.Error", dbus::INTERFACE), &err.to_string()),
+                (None, Ok(update)) => bus.emit_state(&serde_json::to_string(update).unwrap()),
+                // only updates are sent without a call
+                (None, Err(_)) => Ok(()),
+            },
+        }
+    };
+    let mut subscribers: Vec<Requester> = Vec::new();
+    // the bus takes every update as a signal, starting with the current state
+    if let Some(bus) = bus {
+        let client = Client::DBus { bus, call: None };
+        let requester = Requester { client, protocol: Protocol::Json, request_id: 0, rpc_id: None };
+        for state in state.heartbeat() {
+            send(Ok(&state), &requester)?;
+        }
+        subscribers.push(requester);
+    }
+    let mut next_heartbeat = Instant::now();
+
+    loop {
+        let has_websockets = subscribers
+            .iter()
+            .any(|subscriber| matches!(subscriber.client, Client::WebSocket(_)));
+        let until_heartbeat = has_websockets.then(|| next_heartbeat.saturating_duration_since(Instant::now()));
+        let timeout = state.next_tick().into_iter().chain(until_heartbeat).min();
+        let event = match timeout {
+            Some(timeout) => received.recv_timeout(timeout),
+            None => received.recv().map_err(RecvTimeoutError::from),
+        };
+
+        let action = match event {
+            Ok(Event::Stop) | Err(RecvTimeoutError::Disconnected) => {
+                log::info!("Stopping on a signal");
+                break Ok(());
+            },
+            Ok(Event::Request(data, client)) => {
+                let protocol = Protocol::detect(&data);
+                log::debug!("Received {} bytes of {protocol:?} from a {} client", data.len(), client.kind());
+                let (request_id, rpc_id, request_token, request) = if protocol == Protocol::JsonRpc {
+                    let (rpc_id, request_token, request) = decode_rpc::<S>(&data);
+                    (0, rpc_id, request_token, request)
+                } else {
+                    match Message::decode(protocol, &data) {
+                        Ok(Message { id, token, body, .. }) => (id, None, token, Ok(body)),
+                        Err(DecodeError::Version(client)) => {
+                            let server = Some(PROTOCOL_VERSION);
+                            (0, None, None, Err(ProtocolError::IncompatibleVersion { server, client }))
+                        },
+                        Err(DecodeError::Malformed(id, err)) => (id, None, None, Err(ProtocolError::Malformed(err))),
+                    }
+                };
+                let request = match request {
+                    Ok(_) if client.needs_token() && !is_token_valid(&token, request_token.as_deref()) => {
+                        Err(ProtocolError::Unauthorized)
+                    },
+                    request => request,
+                };
+                // JSON-RPC notifications don't get responses, unless they're wrong
+                let is_notification = protocol == Protocol::JsonRpc && rpc_id.is_none();
+                let mut requester = Requester { client, protocol, request_id, rpc_id };
+                let respond = |response: Result<_, _>| {
+                    if is_notification && response.is_ok() {
+                        return;
+                    }
+                    if let Err(err) = send(response, &requester) {
+                        log::warn!("Couldn't respond to a {} client: {err}", requester.client.kind());
+                    }
+                };
+
+                let action = match request {
+                    Ok(request) => state.update(&request),
+                    Err(err) => {
+                        log::warn!("Couldn't handle a request: {err}");
+                        let is_unauthorized = matches!(err, ProtocolError::Unauthorized);
+                        respond(Err(err));
+                        // a client without the token gets no more of the server's time
+                        match &requester.client {
+                            Client::Stream { stream, .. } if is_unauthorized => {
+                                let _ = stream.shutdown();
+                            },
+                            _ => (),
+                        }
+                        ServerAction::None
+                    },
+                };
+                match action {
+                    ServerAction::Respond(ref response)
+                    | ServerAction::Subscribe(ref response)
+                    | ServerAction::StopRespond(ref response) => respond(Ok(response)),
+                    ServerAction::Stop | ServerAction::None => (),
                 }
+                let is_subscribed = subscribers
+                    .iter()
+                    .any(|subscriber| subscriber.client.is_same(&requester.client));
+                // an HTTP connection is done with after its response
+                let is_http = matches!(requester.client, Client::Http(_));
+                if matches!(action, ServerAction::Subscribe(_)) && !is_subscribed && !is_http {
+                    log::debug!("Subscribed a {} client, {} subscribers", requester.client.kind(), subscribers.len() + 1);
+                    requester.rpc_id = None;
+                    subscribers.push(requester);
+                }
+                action
+            },
+            Ok(Event::Signal(signal)) => {
+                log::debug!("Received {signal:?}");
+                state.signal(signal)
             },
-            ServerAction::Stop => break Ok(()),
-            ServerAction::None => (),
+            Err(RecvTimeoutError::Timeout) => state.tick(),
+        };
+
+        for update in state.publish() {
+            log::trace!("Publishing an update to {} subscribers", subscribers.len());
+            // subscribers that went away are dropped, the ones lagging behind
+            // on a datagram socket only miss this update
+            subscribers.retain(|subscriber| match send(Ok(&update), subscriber) {
+                Ok(()) => true,
+                Err(err) if matches!(subscriber.client, Client::Datagram(_)) && err.kind() == ErrorKind::WouldBlock => true,
+                Err(err) => {
+                    log::debug!("Dropped a {} subscriber: {err}", subscriber.client.kind());
+                    false
+                },
+            });
+        }
+        if has_websockets && Instant::now() >= next_heartbeat {
+            for heartbeat in state.heartbeat() {
+                subscribers.retain(|subscriber| {
+                    !matches!(subscriber.client, Client::WebSocket(_)) || send(Ok(&heartbeat), subscriber).is_ok()
+                });
+            }
+            next_heartbeat = Instant::now() + HEARTBEAT_INTERVAL;
+        }
+        if matches!(action, ServerAction::StopRespond(_) | ServerAction::Stop) {
+            log::info!("Stopping");
+            break Ok(());
         }
     }
 }
 
 
-// pub fn send(
-//     client_sock_path: &Path,
-//     server_sock_path: &Path,
-//     request: &impl Serialize,
-// ) -> std::io::Result<()> {
-//     let msg = bincode::serialize(request).unwrap(); // TODO
-//     let socket = UnixDatagram::bind(client_sock_path)?;
-//     socket.send_to(&msg, server_sock_path)?;
-//     Ok(())
-// }
+/// Client's end of the connection to a server
+pub struct Connection {
+    transport: Transport,
+    protocol: Protocol,
+    /// Id of the latest request, responses with any other one are stale
+    request_id: u32,
+    /// Sent along with every request
+    token: Option<String>,
+}
+
+enum Transport {
+    Datagram {
+        socket: UnixDatagram,
+        server_addr: SocketAddr,
+        reassembly: Reassembly,
+    },
+    Stream(BufReader<Stream>),
+}
+
+impl Connection {
+    /// Binds a datagram socket at `client_addr` to talk to the server at `server_addr`
+    pub fn unix(client_addr: &SocketAddr, server_addr: SocketAddr, protocol: Protocol) -> io::Result<Self> {
+        let socket = UnixDatagram::bind_addr(client_addr)?;
+        let transport = Transport::Datagram { socket, server_addr, reassembly: Reassembly::default() };
+        Ok(Self { transport, protocol, request_id: 0, token: None })
+    }
 
-pub fn send_and_receive<Response: DeserializeOwned>(
-    client_sock_path: impl AsRef<Path>,
-    server_sock_path: impl AsRef<Path>,
-    request: &impl Serialize,
-) -> std::io::Result<Response> {
-    let msg = bincode::serialize(request).unwrap();
-    let socket = UnixDatagram::bind(client_sock_path.as_ref())?;
-    socket.send_to(&msg, server_sock_path.as_ref())?;
+    /// Connects to the server listening on a stream socket at `server_addr`
+    pub fn unix_stream(server_addr: &SocketAddr, protocol: Protocol) -> io::Result<Self> {
+        let stream = Stream::Unix(UnixStream::connect_addr(server_addr)?);
+        Ok(Self { transport: Transport::Stream(BufReader::new(stream)), protocol, request_id: 0, token: None })
+    }
 
-    let mut buffer = vec![0u8; MAX_UDP_PACKET_SIZE];
-    let size = socket.recv(&mut buffer)?;
-    let response = bincode::deserialize(&buffer[..size]).unwrap();
-    Ok(response)
+    pub fn tcp(addr: impl ToSocketAddrs, protocol: Protocol) -> io::Result<Self> {
+        let stream = Stream::Tcp(TcpStream::connect(addr)?);
+        Ok(Self { transport: Transport::Stream(BufReader::new(stream)), protocol, request_id: 0, token: None })
+    }
+
+    /// Token the server requires of requests over TCP
+    pub fn with_token(mut self, token: Option<String>) -> Self {
+        self.token = token;
+        self
+    }
+
+    /// Time `receive` waits for before failing with `ErrorKind::WouldBlock` or
+    /// `ErrorKind::TimedOut`, waits indefinitely if `None`
+    pub fn set_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match &self.transport {
+            Transport::Datagram { socket, .. } => socket.set_read_timeout(timeout),
+            Transport::Stream(stream) => stream.get_ref().set_read_timeout(timeout),
+        }
+    }
+
+    /// Sends the request and receives its response, sending it again up to `retries` times
+    /// while the server doesn't respond within the timeout
+    pub fn request<Response: DeserializeOwned>(
+        &mut self,
+        request: &impl Serialize,
+        retries: u32,
+    ) -> io::Result<Response> {
+        self.request_id = self.request_id.wrapping_add(1);
+        for attempt in 0..=retries {
+            log::debug!("Sending request {} over {:?}, attempt {}", self.request_id, self.protocol, attempt + 1);
+            // a late response to an earlier attempt is just as good
+            self.send(request)?;
+            match self.receive() {
+                Err(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                    log::debug!("No response to request {} in time", self.request_id);
+                    continue;
+                },
+                response => return response,
+            }
+        }
+        Err(io::Error::new(ErrorKind::TimedOut, "Server did not respond"))
+    }
+
+    /// Sends a request with the id of the latest one
+    fn send(&mut self, request: &impl Serialize) -> io::Result<()> {
+        let message = Message { token: self.token.clone(), ..Message::new(self.request_id, request) };
+        let msg = self.protocol.encode(&message);
+        match &mut self.transport {
+            Transport::Datagram { socket, server_addr, .. } => socket.send_to_addr(&msg, server_addr).map(|_| ()),
+            Transport::Stream(stream) => self.protocol.write_frame(stream.get_mut(), &msg),
+        }
+    }
+
+    /// Receives the response to the latest request, or an update following it,
+    /// discarding any others
+    pub fn receive<Response: DeserializeOwned>(&mut self) -> io::Result<Response> {
+        let invalid = |err: ProtocolError| io::Error::new(ErrorKind::InvalidData, err);
+        loop {
+            let data = match &mut self.transport {
+                Transport::Datagram { socket, reassembly, .. } => {
+                    let mut buffer = vec![0u8; MAX_UDP_PACKET_SIZE];
+                    let size = socket.recv(&mut buffer)?;
+                    buffer.truncate(size);
+                    match reassembly.push(buffer) {
+                        Some(data) => data,
+                        None => continue,
+                    }
+                },
+                Transport::Stream(stream) => self.protocol.read_frame(stream, MAX_FRAME_SIZE)?,
+            };
+            match Message::<Result<Response, ProtocolError>>::decode(self.protocol, &data) {
+                Ok(Message { id, body, .. }) if id == self.request_id => {
+                    log::trace!("Received {} bytes in response to request {id}", data.len());
+                    break body.map_err(invalid);
+                },
+                Ok(Message { id, .. }) => {
+                    log::debug!("Discarded a stale response to request {id}");
+                    continue;
+                },
+                // a server of another version can't be expected to get the id right
+                Err(DecodeError::Version(server)) => break Err(invalid(ProtocolError::IncompatibleVersion {
+                    server,
+                    client: Some(PROTOCOL_VERSION),
+                })),
+                Err(DecodeError::Malformed(_, err)) => break Err(io::Error::new(ErrorKind::InvalidData, err)),
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chunked_messages() {
+        let message: Vec<u8> = (0..3 * MAX_UDP_PACKET_SIZE).map(|i| i as u8).collect();
+        let mut datagrams = Vec::new();
+        send_chunked(&message, 7, |datagram| {
+            datagrams.push(datagram.to_vec());
+            Ok(())
+        }).unwrap();
+        assert_eq!(datagrams.len(), 4);
+        assert!(datagrams.iter().all(|datagram| datagram.len() <= MAX_UDP_PACKET_SIZE));
+
+        let mut reassembly = Reassembly::default();
+        let last = datagrams.pop().unwrap();
+        for datagram in datagrams.clone() {
+            assert_eq!(reassembly.push(datagram), None);
+        }
+        assert_eq!(reassembly.push(last.clone()), Some(message));
+
+        // a message missing a chunk is dropped, small ones pass through
+        assert_eq!(reassembly.push(datagrams[0].clone()), None);
+        assert_eq!(reassembly.push(last), None);
+        assert_eq!(reassembly.push(vec![2, 0, 0, 0]), Some(vec![2, 0, 0, 0]));
+    }
+
+    #[test]
+    fn stream_requests() {
+        let (mut client, server) = UnixStream::pair().unwrap();
+        let (events, received) = mpsc::channel();
+        let serving = std::thread::spawn(move || serve_stream(Stream::Unix(server), 0, &events));
+
+        let request = Protocol::Bincode.encode(&Message::new(1, "Fetch"));
+        Protocol::Bincode.write_frame(&mut client, &request).unwrap();
+        match received.recv().unwrap() {
+            Event::Request(data, Client::Stream { .. }) => assert_eq!(data, request),
+            _ => panic!("Expected a request"),
+        }
+
+        // a client can't have the server allocate responses' worth of memory
+        client.write_all(&(MAX_FRAME_SIZE as u32).to_le_bytes()).unwrap();
+        let err = serving.join().unwrap().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+        let mut json = io::Cursor::new(vec![b' '; MAX_REQUEST_SIZE + 1]);
+        assert!(Protocol::Json.read_frame(&mut json, MAX_REQUEST_SIZE).is_err());
+    }
+
+    #[test]
+    fn chunks_to_slow_client() {
+        let (sender, receiver) = UnixDatagram::pair().unwrap();
+        sender.set_nonblocking(true).unwrap();
+        let message: Vec<u8> = (0..8 * MAX_UDP_PACKET_SIZE).map(|i| (i / 7) as u8).collect();
+        let receiving = std::thread::spawn(move || {
+            let mut reassembly = Reassembly::default();
+            loop {
+                // takes its time, so the sender's socket fills up
+                std::thread::sleep(Duration::from_millis(10));
+                let mut buffer = vec![0u8; MAX_UDP_PACKET_SIZE];
+                let size = receiver.recv(&mut buffer).unwrap();
+                buffer.truncate(size);
+                if let Some(message) = reassembly.push(buffer) {
+                    break message;
+                }
+            }
+        });
+        send_chunked(&message, 1, |datagram| sender.send(datagram).map(|_| ())).unwrap();
+        assert_eq!(receiving.join().unwrap(), message);
+    }
+
+    #[test]
+    fn auth_tokens() {
+        assert!(is_token_valid("secret", Some("secret")));
+        assert!(!is_token_valid("secret", Some("secreT")));
+        assert!(!is_token_valid("secret", Some("secret2")));
+        assert!(!is_token_valid("secret", None));
+
+        // JSON clients can leave the token out
+        let data = format!(r#"{{"version":{PROTOCOL_VERSION},"id":1,"body":"Fetch"}}"#);
+        let message: Message<String> = Message::decode(Protocol::Json, data.as_bytes()).ok().unwrap();
+        assert!(message.token.is_none());
+    }
 }
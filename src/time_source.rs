@@ -0,0 +1,125 @@
+use serde::{Serialize, Deserialize};
+
+use std::cell::Cell;
+use std::time::{Duration, Instant, SystemTime};
+
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ClockSource {
+    /// Doesn't advance while the system is suspended on some platforms
+    #[default]
+    Monotonic,
+    /// Keeps up with suspends, but follows any changes of the system time
+    Wall,
+}
+
+/// What happens to the clock when a system suspend is detected
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SuspendPolicy {
+    /// Count the suspend as elapsed time
+    Continue,
+    /// Pause the clock at the moment of the suspend
+    Pause,
+    /// Whatever the clock source does
+    #[default]
+    Ignore,
+}
+
+/// Provides the `Instant`s driving a `PomodoroClock`, which may be derived
+/// from the wall clock or run faster than real time
+#[derive(Debug, Clone)]
+pub struct TimeSource {
+    clock_source: ClockSource,
+    origin: Instant,
+    wall_origin: SystemTime,
+    speed: f64,
+    /// Monotonic and wall time of the last suspend check
+    last_sample: (Instant, SystemTime),
+    /// Latest instant returned by `now`, which never goes back past it
+    last_now: Cell<Instant>,
+}
+
+impl TimeSource {
+    /// Wall time passing faster than monotonic time by at least this much
+    /// is taken as a system suspend
+    const SUSPEND_THRESHOLD: Duration = Duration::from_secs(5);
+
+    pub fn new(clock_source: ClockSource, speed: f64) -> Self {
+        let origin = Instant::now();
+        let wall_origin = SystemTime::now();
+        Self {
+            clock_source,
+            origin,
+            wall_origin,
+            speed,
+            last_sample: (origin, wall_origin),
+            last_now: Cell::new(origin),
+        }
+    }
+
+    pub fn clock_source(&self) -> ClockSource {
+        self.clock_source
+    }
+
+    pub fn now(&self) -> Instant {
+        let real_elapsed = match self.clock_source {
+            ClockSource::Monotonic => Instant::now().duration_since(self.origin),
+            // the wall clock going back before the origin is treated as no time passing
+            ClockSource::Wall => SystemTime::now()
+                .duration_since(self.wall_origin)
+                .unwrap_or_default(),
+        };
+        // the wall clock being stepped back, e.g. by NTP, stalls the clock
+        // until it catches up rather than rewinding it
+        let now = self.last_now.get().max(self.origin + real_elapsed.mul_f64(self.speed));
+        self.last_now.set(now);
+        now
+    }
+
+    /// Real time it takes for `duration` to pass according to this source
+    pub fn real_duration(&self, duration: Duration) -> Duration {
+        duration.div_f64(self.speed)
+    }
+
+    /// Time that would pass according to this source during `real_duration`
+    pub fn clock_duration(&self, real_duration: Duration) -> Duration {
+        real_duration.mul_f64(self.speed)
+    }
+
+    /// Real duration of a system suspend that happened since the last check, if any
+    pub fn detect_suspend(&mut self) -> Option<Duration> {
+        let sample = (Instant::now(), SystemTime::now());
+        let (last_monotonic, last_wall) = std::mem::replace(&mut self.last_sample, sample);
+        let monotonic_elapsed = sample.0.duration_since(last_monotonic);
+        let wall_elapsed = sample.1.duration_since(last_wall).ok()?;
+        wall_elapsed
+            .checked_sub(monotonic_elapsed)
+            .filter(|suspended| *suspended >= Self::SUSPEND_THRESHOLD)
+    }
+}
+
+impl Default for TimeSource {
+    fn default() -> Self {
+        Self::new(ClockSource::default(), 1.0)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wall_clock_stepped_back() {
+        let mut time_source = TimeSource::new(ClockSource::Wall, 1.0);
+        time_source.wall_origin -= Duration::from_secs(60);
+        let before = time_source.now();
+        assert!(before >= time_source.origin + Duration::from_secs(60));
+
+        // the system time going back a minute is the same as the origin going forward
+        time_source.wall_origin += Duration::from_secs(120);
+        assert!(time_source.now() >= before);
+    }
+}
@@ -0,0 +1,248 @@
+use super::pomodoro_clock::{PomodoroClock, Request, Response};
+use super::socket::{ServerAction, ServerState, UserSignal};
+
+use serde::{Serialize, Deserialize};
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+
+/// Named timers a server keeps at once, so that clients can't make it run out of memory
+const MAX_NAMED_TIMERS: usize = 64;
+
+/// Request to one of the timers of a server, the main one if `timer` is `None`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TimerRequest {
+    pub timer: Option<String>,
+    pub request: Request,
+}
+
+/// Response from the timer a request went to, or an update of any timer
+/// for subscribers, who only care about theirs
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TimerResponse {
+    pub timer: Option<String>,
+    pub response: Response,
+}
+
+/// Main clock of a server, along with named timers started on demand
+pub struct Timers<'a> {
+    main: PomodoroClock<'a>,
+    named: BTreeMap<String, PomodoroClock<'a>>,
+    /// Makes the clock of a newly named timer
+    new_clock: Box<dyn Fn() -> PomodoroClock<'a> + 'a>,
+}
+
+impl<'a> Timers<'a> {
+    pub fn new(main: PomodoroClock<'a>, new_clock: impl Fn() -> PomodoroClock<'a> + 'a) -> Self {
+        Self {
+            main,
+            named: BTreeMap::new(),
+            new_clock: Box::new(new_clock),
+        }
+    }
+
+    /// Responses of every timer, tagged with its name
+    fn responses_of_each(&mut self, mut responses: impl FnMut(&mut PomodoroClock<'a>) -> Vec<Response>) -> Vec<TimerResponse> {
+        let main = responses(&mut self.main)
+            .into_iter()
+            .map(|response| TimerResponse { timer: None, response });
+        let named = self.named
+            .iter_mut()
+            .flat_map(|(name, clock)| responses(clock)
+                .into_iter()
+                .map(|response| TimerResponse { timer: Some(name.clone()), response }));
+        main.chain(named).collect()
+    }
+}
+
+fn wrap(timer: Option<&str>, action: ServerAction<Response>) -> ServerAction<TimerResponse> {
+    let wrap = |response| TimerResponse { timer: timer.map(String::from), response };
+    match action {
+        ServerAction::Respond(response) => ServerAction::Respond(wrap(response)),
+        ServerAction::Subscribe(response) => ServerAction::Subscribe(wrap(response)),
+        ServerAction::StopRespond(response) => ServerAction::StopRespond(wrap(response)),
+        ServerAction::Stop => ServerAction::Stop,
+        ServerAction::None => ServerAction::None,
+    }
+}
+
+impl ServerState for Timers<'_> {
+    type Request<'de> = TimerRequest;
+    type Response = TimerResponse;
+
+    fn update<'de>(&mut self, request: &Self::Request<'de>) -> ServerAction<Self::Response> {
+        let Some(name) = &request.timer else {
+            return wrap(None, self.main.update(&request.request));
+        };
+        // e.g. a typo fetches nothing rather than a fresh timer
+        if !self.named.contains_key(name) {
+            let error = if !request.request.starts_timer() {
+                Some(format!("There's no timer named '{name}'"))
+            } else if self.named.len() >= MAX_NAMED_TIMERS {
+                Some(format!("There are {MAX_NAMED_TIMERS} timers already, stop one first"))
+            } else {
+                None
+            };
+            if let Some(error) = error {
+                return wrap(Some(name), ServerAction::Respond(Response::Confirmation(Err(error))));
+            }
+        }
+        let clock = self.named
+            .entry(name.clone())
+            .or_insert_with(|| (self.new_clock)());
+        // stopping a named timer only removes it, the server keeps running
+        match clock.update(&request.request) {
+            ServerAction::StopRespond(response) => {
+                self.named.remove(name);
+                wrap(Some(name), ServerAction::Respond(response))
+            },
+            ServerAction::Stop => {
+                self.named.remove(name);
+                ServerAction::None
+            },
+            action => wrap(Some(name), action),
+        }
+    }
+
+    /// `GET /state`, `GET /info`, `POST /toggle`, `POST /pause`, `POST /resume`, `POST /skip` and `POST /reset`, and a WebSocket
+    /// at `GET /events`, with `?timer=<name>` for a named timer
+    fn http_request(method: &str, target: &str) -> Option<serde_json::Value> {
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        let timer = query
+            .split('&')
+            .find_map(|pair| pair.strip_prefix("timer="))
+            .map(String::from);
+        let request = match (method, path) {
+            ("GET", "/state") => Request::Fetch,
+            ("GET", "/events") => Request::Subscribe,
+            ("GET", "/info") => Request::Info,
+            ("POST", "/toggle") => Request::Toggle,
+            ("POST", "/pause") => Request::Pause,
+            ("POST", "/resume") => Request::Resume,
+            ("POST", "/skip") => Request::Skip { to: None, count: 1 },
+            ("POST", "/reset") => Request::Reset,
+            _ => return None,
+        };
+        serde_json::to_value(TimerRequest { timer, request }).ok()
+    }
+
+    /// Methods are requests in snake case, e.g. `restart_session`, taking their fields
+    /// as named parameters, along with an optional `timer`
+    fn rpc_request(method: &str, params: serde_json::Value) -> Option<serde_json::Value> {
+        let variant: String = method
+            .split('_')
+            .flat_map(|word| {
+                let mut chars = word.chars();
+                chars.next().map(|first| first.to_ascii_uppercase()).into_iter().chain(chars)
+            })
+            .collect();
+        let (timer, request) = match params {
+            serde_json::Value::Object(mut params) => {
+                let timer = params.remove("timer");
+                let request = if params.is_empty() {
+                    serde_json::Value::String(variant)
+                } else {
+                    serde_json::json!({ variant: params })
+                };
+                (timer, request)
+            },
+            serde_json::Value::Null => (None, serde_json::Value::String(variant)),
+            params => (None, serde_json::json!({ variant: params })),
+        };
+        Some(serde_json::json!({ "timer": timer, "request": request }))
+    }
+
+    /// `Toggle`, `Pause`, `Resume`, `Skip`, `Reset` and `Fetch`, all of the main timer
+    fn dbus_request(method: &str) -> Option<serde_json::Value> {
+        let request = match method {
+            "Toggle" => Request::Toggle,
+            "Pause" => Request::Pause,
+            "Resume" => Request::Resume,
+            "Skip" => Request::Skip { to: None, count: 1 },
+            "Reset" => Request::Reset,
+            "Fetch" => Request::Fetch,
+            _ => return None,
+        };
+        serde_json::to_value(TimerRequest { timer: None, request }).ok()
+    }
+
+    fn next_tick(&self) -> Option<Duration> {
+        std::iter::once(&self.main)
+            .chain(self.named.values())
+            .filter_map(|clock| clock.next_tick())
+            .min()
+    }
+
+    fn tick(&mut self) -> ServerAction<Self::Response> {
+        self.named.retain(|_, clock| !matches!(clock.tick(), ServerAction::Stop | ServerAction::StopRespond(_)));
+        wrap(None, self.main.tick())
+    }
+
+    fn heartbeat(&mut self) -> Vec<Self::Response> {
+        self.responses_of_each(|clock| clock.heartbeat())
+    }
+
+    fn signal(&mut self, signal: UserSignal) -> ServerAction<Self::Response> {
+        wrap(None, self.main.signal(signal))
+    }
+
+    fn publish(&mut self) -> Vec<Self::Response> {
+        self.responses_of_each(|clock| clock.publish())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::SessionKind;
+    use crate::pomodoro_clock::tests::session;
+
+    #[test]
+    fn named_timers() {
+        let sessions = [session("work", 100, SessionKind::Work)];
+        let new_clock = || PomodoroClock::paused(sessions.iter(), "%M:%S");
+        let mut timers = Timers::new(new_clock(), new_clock);
+        let request = |timer: Option<&str>, request| TimerRequest { timer: timer.map(String::from), request };
+        let is_paused = |timers: &mut Timers, timer| match timers.update(&request(timer, Request::Fetch)) {
+            ServerAction::Respond(TimerResponse { response: Response::State(state), .. }) => state.is_paused,
+            _ => panic!("Expected a state"),
+        };
+
+        timers.update(&request(Some("tea"), Request::Toggle));
+        assert!(!is_paused(&mut timers, Some("tea")));
+        assert!(is_paused(&mut timers, None));
+        assert_eq!(timers.publish().len(), 1);
+
+        // stopping a named timer leaves the server running
+        assert!(matches!(timers.update(&request(Some("tea"), Request::Stop)), ServerAction::Respond(_)));
+        assert!(!timers.named.contains_key("tea"));
+        assert!(matches!(
+            timers.update(&request(Some("tea"), Request::Stop)),
+            ServerAction::Respond(TimerResponse { response: Response::Confirmation(Err(_)), .. }),
+        ));
+        assert!(matches!(timers.update(&request(None, Request::Stop)), ServerAction::StopRespond(_)));
+
+        // only requests setting a clock going start a timer
+        assert!(matches!(
+            timers.update(&request(Some("taa"), Request::Fetch)),
+            ServerAction::Respond(TimerResponse { response: Response::Confirmation(Err(_)), .. }),
+        ));
+        assert!(!timers.named.contains_key("taa"));
+    }
+
+    #[test]
+    fn rpc_requests() {
+        let decode = |method, params| -> TimerRequest {
+            serde_json::from_value(Timers::rpc_request(method, params).unwrap()).unwrap()
+        };
+        let request = decode("restart_session", serde_json::Value::Null);
+        assert!(request.timer.is_none());
+        assert!(matches!(request.request, Request::RestartSession));
+
+        let request = decode("skip", serde_json::json!({ "timer": "tea", "count": 2 }));
+        assert_eq!(request.timer.as_deref(), Some("tea"));
+        assert!(matches!(request.request, Request::Skip { to: None, count: 2 }));
+    }
+}
@@ -0,0 +1,457 @@
+use super::config::Config;
+use super::dbus::{self, Bus, Message, Variant, Writer};
+use super::pomodoro_clock::{PomodoroState, Request, Response};
+use super::{STATUS_TEMPLATE, TemplateSource};
+
+use std::io;
+use std::os::unix::net::UnixStream;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::time::Duration;
+
+
+const ITEM_PATH: &str = "/StatusNotifierItem";
+const ITEM_INTERFACE: &str = "org.kde.StatusNotifierItem";
+const MENU_PATH: &str = "/MenuBar";
+const MENU_INTERFACE: &str = "com.canonical.dbusmenu";
+const WATCHER_NAME: &str = "org.kde.StatusNotifierWatcher";
+const WATCHER_PATH: &str = "/StatusNotifierWatcher";
+/// Time between fetches of the server's state
+const REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+/// Entries of the menu by id from 1, the menu itself being 0, `None` for a separator
+const MENU_ITEMS: [Option<&str>; 5] = [Some("Start/pause"), Some("Skip"), Some("Reset"), None, Some("Quit")];
+
+/// What a click asks for
+enum Action {
+    Request(Request),
+    Quit,
+}
+
+fn menu_action(id: i32) -> Option<Action> {
+    match id {
+        1 => Some(Action::Request(Request::Toggle)),
+        2 => Some(Action::Request(Request::Skip { to: None, count: 1 })),
+        3 => Some(Action::Request(Request::Reset)),
+        5 => Some(Action::Quit),
+        _ => None,
+    }
+}
+
+/// What the icon shows, as of the last fetch
+#[derive(Debug, Default, Clone, PartialEq)]
+struct Appearance {
+    /// Session and time left, the title, the text next to the icon where
+    /// the tray shows one, and the title of the tooltip
+    label: String,
+    icon_name: &'static str,
+    /// Body of the tooltip
+    description: String,
+}
+
+impl Appearance {
+    fn new(server_id: &str, state: io::Result<PomodoroState>, template: &mustache::Template, config: &Config) -> Self {
+        let state = match state {
+            Ok(state) => state,
+            Err(err) => return Self {
+                label: err.to_string(),
+                icon_name: "dialog-error",
+                description: String::new(),
+            },
+        };
+        let icon_name = if state.is_finished {
+            "media-playback-stop"
+        } else if state.is_paused {
+            "media-playback-pause"
+        } else {
+            "media-playback-start"
+        };
+        let label = format!("{} {}", state.session_name, state.time);
+        let description = template.render_to_string(&TemplateSource::new(server_id, state, config))
+            .expect("Couldn't populate mustache template");
+        Self { label, icon_name, description }
+    }
+
+    fn item_properties(&self) -> Vec<(&str, Variant<'_>)> {
+        vec![
+            ("Category", Variant::String("ApplicationStatus")),
+            ("Id", Variant::String("pomidoro")),
+            ("Title", Variant::String(&self.label)),
+            ("Status", Variant::String("Active")),
+            ("WindowId", Variant::I32(0)),
+            ("IconName", Variant::String(self.icon_name)),
+            ("IconThemePath", Variant::String("")),
+            ("ToolTip", Variant::ToolTip { icon_name: self.icon_name, title: &self.label, description: &self.description }),
+            ("ItemIsMenu", Variant::Bool(false)),
+            ("Menu", Variant::Object(MENU_PATH)),
+            ("XAyatanaLabel", Variant::String(&self.label)),
+        ]
+    }
+}
+
+fn menu_properties() -> Vec<(&'static str, Variant<'static>)> {
+    vec![
+        ("Version", Variant::U32(3)),
+        ("TextDirection", Variant::String("ltr")),
+        ("Status", Variant::String("normal")),
+        ("IconThemePath", Variant::EmptyArray("as")),
+    ]
+}
+
+fn menu_item_properties(id: i32) -> Vec<(&'static str, Variant<'static>)> {
+    if id == 0 {
+        return vec![("children-display", Variant::String("submenu"))];
+    }
+    match id.checked_sub(1).and_then(|index| usize::try_from(index).ok()).and_then(|index| MENU_ITEMS.get(index)) {
+        Some(Some(label)) => vec![("label", Variant::String(label))],
+        Some(None) => vec![("type", Variant::String("separator"))],
+        None => Vec::new(),
+    }
+}
+
+/// `(ia{sv}av)` of the entry `id`, with its entries if it's the menu itself
+fn write_layout(writer: &mut Writer, id: i32) {
+    writer.open_struct().i32(id).dict(&menu_item_properties(id)).array(1, |writer| if id == 0 {
+        for child in 1..=MENU_ITEMS.len() as i32 {
+            writer.signature("(ia{sv}av)");
+            write_layout(writer, child);
+        }
+    });
+}
+
+/// Arguments of `RegisterStatusNotifierItem` for the item named `name`
+fn registration(name: &str) -> Writer {
+    let mut args = Writer::default();
+    args.string(name);
+    args
+}
+
+/// Tray icon of a server, exported on the session bus
+struct Item {
+    bus: Arc<Bus>,
+    /// Well-known name of the item on the bus
+    name: String,
+    appearance: Mutex<Appearance>,
+}
+
+impl Item {
+    /// Emits the signals of whatever changed in the appearance
+    fn update(&self, appearance: Appearance) -> io::Result<()> {
+        let old = std::mem::replace(&mut *self.appearance.lock().unwrap(), appearance.clone());
+        if old.label != appearance.label {
+            self.bus.emit(ITEM_PATH, ITEM_INTERFACE, "NewTitle", "", Writer::default())?;
+            let mut label = Writer::default();
+            label.string(&appearance.label).string("");
+            self.bus.emit(ITEM_PATH, ITEM_INTERFACE, "XAyatanaNewLabel", "ss", label)?;
+        }
+        if old.icon_name != appearance.icon_name {
+            self.bus.emit(ITEM_PATH, ITEM_INTERFACE, "NewIcon", "", Writer::default())?;
+        }
+        if old != appearance {
+            self.bus.emit(ITEM_PATH, ITEM_INTERFACE, "NewToolTip", "", Writer::default())?;
+        }
+        Ok(())
+    }
+
+    /// Answers calls of the item and its menu, passing clicks on to `actions`
+    fn receive(&self, mut reader: UnixStream, actions: Sender<Action>) -> io::Result<()> {
+        loop {
+            let message = Message::read(&mut reader)?;
+            if message.is_signal() && message.member.as_deref() == Some("NameOwnerChanged") {
+                let mut args = message.args();
+                let (_, _, new_owner) = (args.string()?, args.string()?, args.string()?);
+                // a watcher starting over, e.g. with the panel, knows of no item
+                if !new_owner.is_empty() {
+                    let args = registration(&self.name);
+                    self.bus.call_later(WATCHER_NAME, WATCHER_PATH, WATCHER_NAME, "RegisterStatusNotifierItem", "s", args)?;
+                }
+            } else if message.is_method_call() {
+                self.answer(&message, &actions)?;
+            }
+        }
+    }
+
+    fn answer(&self, call: &Message, actions: &Sender<Action>) -> io::Result<()> {
+        let mut args = call.args();
+        let mut body = Writer::default();
+        let path = call.path.as_deref().unwrap_or_default();
+        let interface = call.interface.as_deref().unwrap_or_default();
+        let member = call.member.as_deref().unwrap_or_default();
+        match (path, interface, member) {
+            (ITEM_PATH | MENU_PATH, dbus::INTROSPECTABLE_INTERFACE, "Introspect") => {
+                return self.bus.reply(call, &introspection(path));
+            },
+            (ITEM_PATH | MENU_PATH, dbus::PROPERTIES_INTERFACE, "Get" | "GetAll") => {
+                let appearance = self.appearance.lock().unwrap();
+                let properties = match path {
+                    ITEM_PATH => appearance.item_properties(),
+                    _ => menu_properties(),
+                };
+                let _interface = args.string()?;
+                if member == "GetAll" {
+                    body.dict(&properties);
+                    return self.bus.reply_with(call, "a{sv}", body);
+                }
+                let name = args.string()?;
+                return match properties.iter().find(|(property, _)| *property == name) {
+                    Some((_, value)) => {
+                        body.variant(value);
+                        self.bus.reply_with(call, "v", body)
+                    },
+                    None => {
+                        let error = "org.freedesktop.DBus.Error.UnknownProperty";
+                        self.bus.reply_error(call, error, &format!("No property {name}"))
+                    },
+                };
+            },
+            (ITEM_PATH, ITEM_INTERFACE, "Activate") => {
+                let _ = actions.send(Action::Request(Request::Toggle));
+            },
+            (ITEM_PATH, ITEM_INTERFACE, "SecondaryActivate" | "ContextMenu" | "Scroll") => (),
+            (MENU_PATH, MENU_INTERFACE, "GetLayout") => {
+                let parent = args.i32()?;
+                // the menu is a single level, so it's whole at any depth
+                body.u32(1);
+                write_layout(&mut body, parent);
+                return self.bus.reply_with(call, "u(ia{sv}av)", body);
+            },
+            (MENU_PATH, MENU_INTERFACE, "GetGroupProperties") => {
+                let mut ids = args.array(4, |args| args.i32())?;
+                if ids.is_empty() {
+                    ids = (0..=MENU_ITEMS.len() as i32).collect();
+                }
+                body.array(8, |body| for id in ids {
+                    body.open_struct().i32(id).dict(&menu_item_properties(id));
+                });
+                return self.bus.reply_with(call, "a(ia{sv})", body);
+            },
+            (MENU_PATH, MENU_INTERFACE, "GetProperty") => {
+                let (id, name) = (args.i32()?, args.string()?);
+                return match menu_item_properties(id).iter().find(|(property, _)| *property == name) {
+                    Some((_, value)) => {
+                        body.variant(value);
+                        self.bus.reply_with(call, "v", body)
+                    },
+                    None => {
+                        let error = "org.freedesktop.DBus.Error.UnknownProperty";
+                        self.bus.reply_error(call, error, &format!("No property {name}"))
+                    },
+                };
+            },
+            (MENU_PATH, MENU_INTERFACE, "Event") => {
+                let (id, event) = (args.i32()?, args.string()?);
+                if let Some(action) = menu_action(id).filter(|_| event == "clicked") {
+                    let _ = actions.send(action);
+                }
+            },
+            (MENU_PATH, MENU_INTERFACE, "EventGroup") => {
+                let events = args.array(8, |args| {
+                    args.open_struct();
+                    let (id, event) = (args.i32()?, args.string()?);
+                    args.skip_variant()?;
+                    args.u32()?;
+                    Ok((id, event))
+                })?;
+                for (id, event) in events {
+                    if let Some(action) = menu_action(id).filter(|_| event == "clicked") {
+                        let _ = actions.send(action);
+                    }
+                }
+                // no id errors
+                body.array(4, |_| ());
+                return self.bus.reply_with(call, "ai", body);
+            },
+            (MENU_PATH, MENU_INTERFACE, "AboutToShow") => {
+                // the menu never needs updating
+                body.bool(false);
+                return self.bus.reply_with(call, "b", body);
+            },
+            (MENU_PATH, MENU_INTERFACE, "AboutToShowGroup") => {
+                body.array(4, |_| ()).array(4, |_| ());
+                return self.bus.reply_with(call, "aiai", body);
+            },
+            _ => {
+                let error = "org.freedesktop.DBus.Error.UnknownMethod";
+                return self.bus.reply_error(call, error, "No such method");
+            },
+        }
+        self.bus.reply_with(call, "", body)
+    }
+}
+
+fn introspection(path: &str) -> String {
+    let interface = match path {
+        ITEM_PATH => format!(r#"<interface name="{ITEM_INTERFACE}">
+<method name="Activate"><arg type="i" direction="in"/><arg type="i" direction="in"/></method>
+<method name="SecondaryActivate"><arg type="i" direction="in"/><arg type="i" direction="in"/></method>
+<method name="ContextMenu"><arg type="i" direction="in"/><arg type="i" direction="in"/></method>
+<method name="Scroll"><arg type="i" direction="in"/><arg type="s" direction="in"/></method>
+<signal name="NewTitle"/><signal name="NewIcon"/><signal name="NewToolTip"/>
+<signal name="XAyatanaNewLabel"><arg type="s"/><arg type="s"/></signal>
+<property name="Category" type="s" access="read"/><property name="Id" type="s" access="read"/>
+<property name="Title" type="s" access="read"/><property name="Status" type="s" access="read"/>
+<property name="WindowId" type="i" access="read"/><property name="IconName" type="s" access="read"/>
+<property name="IconThemePath" type="s" access="read"/><property name="ToolTip" type="(sa(iiay)ss)" access="read"/>
+<property name="ItemIsMenu" type="b" access="read"/><property name="Menu" type="o" access="read"/>
+<property name="XAyatanaLabel" type="s" access="read"/>
+</interface>"#),
+        _ => format!(r#"<interface name="{MENU_INTERFACE}">
+<method name="GetLayout"><arg type="i" direction="in"/><arg type="i" direction="in"/><arg type="as" direction="in"/><arg type="u" direction="out"/><arg type="(ia{{sv}}av)" direction="out"/></method>
+<method name="GetGroupProperties"><arg type="ai" direction="in"/><arg type="as" direction="in"/><arg type="a(ia{{sv}})" direction="out"/></method>
+<method name="GetProperty"><arg type="i" direction="in"/><arg type="s" direction="in"/><arg type="v" direction="out"/></method>
+<method name="Event"><arg type="i" direction="in"/><arg type="s" direction="in"/><arg type="v" direction="in"/><arg type="u" direction="in"/></method>
+<method name="EventGroup"><arg type="a(isvu)" direction="in"/><arg type="ai" direction="out"/></method>
+<method name="AboutToShow"><arg type="i" direction="in"/><arg type="b" direction="out"/></method>
+<method name="AboutToShowGroup"><arg type="ai" direction="in"/><arg type="ai" direction="out"/><arg type="ai" direction="out"/></method>
+<signal name="LayoutUpdated"><arg type="u"/><arg type="i"/></signal>
+<property name="Version" type="u" access="read"/><property name="TextDirection" type="s" access="read"/>
+<property name="Status" type="s" access="read"/><property name="IconThemePath" type="as" access="read"/>
+</interface>"#),
+    };
+    format!(
+        r#"<!DOCTYPE node PUBLIC "-//freedesktop//DTD D-BUS Object Introspection 1.0//EN"
+"http://www.freedesktop.org/standards/dbus/1.0/introspect.dtd">
+<node>{interface}</node>"#
+    )
+}
+
+/// Shows the server in the system tray until `Quit` is clicked, the tooltip
+/// rendering `template`, or the summary printed by `status`
+pub fn run(server_id: String, template: Option<mustache::Template>, config: &Config) -> io::Result<()> {
+    let template = template
+        .unwrap_or_else(|| mustache::compile_str(STATUS_TEMPLATE).expect("Status template is valid"));
+    let fetch = || match super::request(&server_id, Request::Fetch, config) {
+        Ok(Response::State(state)) => Ok(state),
+        Ok(_) => unreachable!(),
+        Err(err) => Err(super::not_running(&server_id, err)),
+    };
+
+    let (bus, mut reader) = Bus::open()?;
+    let name = format!("org.kde.StatusNotifierItem-{}-1", std::process::id());
+    bus.request_name(&name, &mut reader)?;
+    let rule = format!("type='signal',member='NameOwnerChanged',arg0='{WATCHER_NAME}'");
+    bus.add_match(&rule, &mut reader)?;
+    let appearance = Appearance::new(&server_id, fetch(), &template, config);
+    let item = Arc::new(Item { bus: Arc::new(bus), name, appearance: Mutex::new(appearance) });
+
+    let args = registration(&item.name);
+    let reply = item.bus.call(WATCHER_NAME, WATCHER_PATH, WATCHER_NAME, "RegisterStatusNotifierItem", "s", args, &mut reader)?;
+    if reply.is_error() {
+        log::warn!("There's no system tray on the session bus, waiting for one");
+    }
+
+    let (actions, received) = mpsc::channel();
+    let receiver = std::thread::spawn({
+        let item = item.clone();
+        move || item.receive(reader, actions)
+    });
+    loop {
+        match received.recv_timeout(REFRESH_INTERVAL) {
+            Ok(Action::Quit) => return Ok(()),
+            Ok(Action::Request(request)) => match super::request(&server_id, request, config) {
+                Ok(Response::Confirmation(Err(err))) => log::warn!("Server {server_id} refused: {err}"),
+                Ok(_) => (),
+                Err(err) => log::warn!("{}", super::not_running(&server_id, err)),
+            },
+            Err(RecvTimeoutError::Timeout) => (),
+            Err(RecvTimeoutError::Disconnected) => {
+                return receiver.join().expect("Tray thread panicked");
+            },
+        }
+        item.update(Appearance::new(&server_id, fetch(), &template, config))?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{SessionKind, TomlConfig};
+    use crate::dbus::Reader;
+    use crate::pomodoro_clock::PomodoroClock;
+    use crate::pomodoro_clock::tests::session;
+    use std::time::Instant;
+
+    /// Entry of the menu as read back from `(ia{sv}av)`, all of its properties being strings
+    #[derive(Debug, PartialEq)]
+    struct Layout {
+        id: i32,
+        properties: Vec<(String, String)>,
+        children: Vec<Layout>,
+    }
+
+    fn read_layout(reader: &mut Reader) -> io::Result<Layout> {
+        reader.open_struct();
+        let id = reader.i32()?;
+        let properties = reader.array(8, |reader| {
+            reader.open_struct();
+            let key = reader.string()?;
+            assert_eq!(reader.signature()?, "s");
+            Ok((key, reader.string()?))
+        })?;
+        let children = reader.array(1, |reader| {
+            assert_eq!(reader.signature()?, "(ia{sv}av)");
+            read_layout(reader)
+        })?;
+        Ok(Layout { id, properties, children })
+    }
+
+    fn properties(id: i32) -> Vec<(&'static str, String)> {
+        menu_item_properties(id).into_iter().map(|(name, value)| match value {
+            Variant::String(value) => (name, value.to_string()),
+            _ => panic!("Expected a string for {name}"),
+        }).collect()
+    }
+
+    #[test]
+    fn menu_items() {
+        assert_eq!(properties(0), [("children-display", "submenu".to_string())]);
+        assert_eq!(properties(1), [("label", "Start/pause".to_string())]);
+        assert_eq!(properties(4), [("type", "separator".to_string())]);
+        assert_eq!(properties(5), [("label", "Quit".to_string())]);
+        assert!(properties(6).is_empty());
+        assert!(properties(-1).is_empty());
+        assert!(properties(i32::MIN).is_empty());
+    }
+
+    #[test]
+    fn menu_layout() {
+        let mut writer = Writer::default();
+        write_layout(&mut writer, 0);
+        let menu = read_layout(&mut writer.reader()).unwrap();
+        assert_eq!(menu.id, 0);
+        assert_eq!(menu.properties, [("children-display".to_string(), "submenu".to_string())]);
+        let ids: Vec<i32> = menu.children.iter().map(|child| child.id).collect();
+        assert_eq!(ids, [1, 2, 3, 4, 5]);
+        assert_eq!(menu.children[2].properties, [("label".to_string(), "Reset".to_string())]);
+        assert!(menu.children.iter().all(|child| child.children.is_empty()));
+
+        // an entry on its own comes without its siblings
+        let mut writer = Writer::default();
+        write_layout(&mut writer, 2);
+        let skip = read_layout(&mut writer.reader()).unwrap();
+        assert_eq!(skip, Layout { id: 2, properties: vec![("label".into(), "Skip".into())], children: Vec::new() });
+    }
+
+    #[test]
+    fn appearance() {
+        let config = Config::from(TomlConfig::default());
+        let template = mustache::compile_str("{{id}}: {{session}}").unwrap();
+        let sessions = [session("work", 1500, SessionKind::Work)];
+        let clock = PomodoroClock::paused(sessions.iter(), "%M:%S");
+        let state = clock.state_at(Instant::now()).unwrap();
+
+        let appearance = Appearance::new("a", Ok(state), &template, &config);
+        assert_eq!(appearance, Appearance {
+            label: "work 25:00".into(),
+            icon_name: "media-playback-pause",
+            description: "a: work".into(),
+        });
+
+        let err = io::Error::other("Server a isn't running");
+        let appearance = Appearance::new("a", Err(err), &template, &config);
+        assert_eq!(appearance, Appearance {
+            label: "Server a isn't running".into(),
+            icon_name: "dialog-error",
+            description: String::new(),
+        });
+    }
+}
@@ -0,0 +1,352 @@
+use super::config::Config;
+use super::pomodoro_clock::{PomodoroState, Request, Response};
+
+use signal_hook::consts::SIGWINCH;
+use signal_hook::SigId;
+
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+
+/// Time between fetches of the servers' state when no key is pressed
+const REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+/// Widest the progress gauge gets, brackets included
+const MAX_GAUGE_WIDTH: usize = 62;
+/// Rows of the characters of the big countdown
+const GLYPH_HEIGHT: usize = 5;
+const CTRL_C: u8 = 0x03;
+
+const DIGITS: [[&str; GLYPH_HEIGHT]; 10] = [
+    ["███", "█ █", "█ █", "█ █", "███"],
+    ["  █", "  █", "  █", "  █", "  █"],
+    ["███", "  █", "███", "█  ", "███"],
+    ["███", "  █", "███", "  █", "███"],
+    ["█ █", "█ █", "███", "  █", "  █"],
+    ["███", "█  ", "███", "  █", "███"],
+    ["███", "█  ", "███", "█ █", "███"],
+    ["███", "  █", "  █", "  █", "  █"],
+    ["███", "█ █", "███", "█ █", "███"],
+    ["███", "█ █", "███", "  █", "███"],
+];
+const COLON: [&str; GLYPH_HEIGHT] = [" ", "█", " ", "█", " "];
+
+/// Terminal in raw mode showing the alternate screen, restored once dropped
+struct Terminal {
+    original: libc::termios,
+    /// Set on SIGWINCH, which also interrupts waiting for a key
+    resized: Arc<AtomicBool>,
+    resize_hook: SigId,
+}
+
+impl Terminal {
+    fn enter() -> io::Result<Self> {
+        // SAFETY: isatty only inspects the descriptor
+        if unsafe { libc::isatty(libc::STDIN_FILENO) } != 1 {
+            return Err(io::Error::other("The dashboard needs a terminal"));
+        }
+        // SAFETY: termios is plain data, filled in by tcgetattr before being used
+        let mut original: libc::termios = unsafe { std::mem::zeroed() };
+        if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut original) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut raw = original;
+        // keys arrive one by one without being echoed, ctrl-c included
+        raw.c_lflag &= !(libc::ICANON | libc::ECHO | libc::ISIG);
+        raw.c_cc[libc::VMIN] = 1;
+        raw.c_cc[libc::VTIME] = 0;
+        // SAFETY: raw is a valid termios copied from the current one
+        if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSAFLUSH, &raw) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let resized = Arc::new(AtomicBool::new(false));
+        let resize_hook = signal_hook::flag::register(SIGWINCH, resized.clone())?;
+        let terminal = Self { original, resized, resize_hook };
+        // alternate screen, hidden cursor
+        print!("\x1b[?1049h\x1b[?25l");
+        io::stdout().flush()?;
+        Ok(terminal)
+    }
+
+    /// Columns and rows, 80 by 24 when the terminal doesn't say
+    fn size() -> (usize, usize) {
+        // SAFETY: winsize is plain data, only read when ioctl fills it in
+        let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+        match unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) } {
+            0 if size.ws_col > 0 && size.ws_row > 0 => (size.ws_col.into(), size.ws_row.into()),
+            _ => (80, 24),
+        }
+    }
+
+    /// The terminal changed size since the last call
+    fn was_resized(&self) -> bool {
+        self.resized.swap(false, Ordering::Relaxed)
+    }
+
+    /// Next key pressed within `timeout`, as its first byte
+    fn key(&self, timeout: Duration) -> io::Result<Option<u8>> {
+        let mut fd = libc::pollfd { fd: libc::STDIN_FILENO, events: libc::POLLIN, revents: 0 };
+        let timeout = timeout.as_millis().try_into().unwrap_or(i32::MAX);
+        // SAFETY: fd points to a single pollfd
+        match unsafe { libc::poll(&mut fd, 1, timeout) } {
+            0 => return Ok(None),
+            ready if ready < 0 => {
+                let err = io::Error::last_os_error();
+                // e.g. the terminal being resized
+                return match err.kind() {
+                    io::ErrorKind::Interrupted => Ok(None),
+                    _ => Err(err),
+                };
+            },
+            _ => (),
+        }
+        let mut key = 0u8;
+        // SAFETY: reads a single byte into key
+        match unsafe { libc::read(libc::STDIN_FILENO, (&mut key as *mut u8).cast(), 1) } {
+            1 => Ok(Some(key)),
+            0 => Err(io::Error::new(io::ErrorKind::UnexpectedEof, "The terminal closed")),
+            _ => Err(io::Error::last_os_error()),
+        }
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        signal_hook::low_level::unregister(self.resize_hook);
+        print!("\x1b[?25h\x1b[?1049l");
+        let _ = io::stdout().flush();
+        // SAFETY: original is the termios read when entering
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSAFLUSH, &self.original) };
+    }
+}
+
+/// Countdown drawn with block characters, `None` if the time has characters other
+/// than digits and colons, e.g. from a custom `time_format`
+fn big_text(text: &str) -> Option<[String; GLYPH_HEIGHT]> {
+    let glyphs = text
+        .chars()
+        .map(|char| match char {
+            ':' => Some(COLON),
+            _ => char.to_digit(10).map(|digit| DIGITS[digit as usize]),
+        })
+        .collect::<Option<Vec<_>>>()?;
+    Some(std::array::from_fn(|row| {
+        glyphs.iter().map(|glyph| glyph[row]).collect::<Vec<_>>().join(" ")
+    }))
+}
+
+fn gauge(percent: u32, width: usize) -> String {
+    let inner = width.saturating_sub(2);
+    let filled = inner * percent.min(100) as usize / 100;
+    format!("[{}{}]", "█".repeat(filled), "░".repeat(inner - filled))
+}
+
+/// `line` cut to `width` columns, escape codes taking none
+fn fit(line: &str, width: usize) -> String {
+    let mut fitted = String::with_capacity(line.len());
+    let mut columns = 0;
+    let mut chars = line.chars();
+    let mut has_escapes = false;
+    while let Some(char) = chars.next() {
+        if char == '\x1b' {
+            // CSI sequences end with a letter
+            has_escapes = true;
+            fitted.push(char);
+            for char in chars.by_ref() {
+                fitted.push(char);
+                if char.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+            continue;
+        }
+        if columns == width {
+            // no style spills over onto the rest of the screen
+            if has_escapes {
+                fitted.push_str("\x1b[0m");
+            }
+            break;
+        }
+        fitted.push(char);
+        columns += 1;
+    }
+    fitted
+}
+
+fn centered(text: &str, width: usize) -> String {
+    let padding = width.saturating_sub(text.chars().count()) / 2;
+    format!("{}{text}", " ".repeat(padding))
+}
+
+/// Full-screen view of the servers, the selected one in detail
+struct Dashboard<'a> {
+    server_ids: Vec<String>,
+    states: Vec<io::Result<PomodoroState>>,
+    selected: usize,
+    /// Outcome of the last key's request, shown until the next key
+    message: Option<String>,
+    config: &'a Config,
+}
+
+impl Dashboard<'_> {
+    fn refresh(&mut self) {
+        self.states = self.server_ids
+            .iter()
+            .map(|server_id| match super::request(server_id, Request::Fetch, self.config) {
+                Ok(Response::State(state)) => Ok(state),
+                Ok(_) => unreachable!(),
+                Err(err) => Err(super::not_running(server_id, err)),
+            })
+            .collect();
+    }
+
+    fn clock_state(&self, state: &PomodoroState) -> &str {
+        if state.is_finished {
+            &self.config.finished_state_text
+        } else if state.is_paused {
+            &self.config.paused_state_text
+        } else {
+            &self.config.running_state_text
+        }
+    }
+
+    /// Sends the request bound to `key` to the selected server, returns whether to quit
+    fn press(&mut self, key: u8) -> bool {
+        let request = match key {
+            b'q' | CTRL_C => return true,
+            b'\t' => {
+                self.selected = (self.selected + 1) % self.server_ids.len();
+                return false;
+            },
+            b'1'..=b'9' => {
+                let index = usize::from(key - b'1');
+                if index < self.server_ids.len() {
+                    self.selected = index;
+                }
+                return false;
+            },
+            b't' | b' ' => Request::Toggle,
+            b's' => Request::Skip { to: None, count: 1 },
+            b'r' => Request::Reset,
+            _ => return false,
+        };
+        let server_id = &self.server_ids[self.selected];
+        self.message = match super::request(server_id, request, self.config) {
+            Ok(Response::Confirmation(Err(err))) => Some(err),
+            Ok(_) => None,
+            Err(err) => Some(super::not_running(server_id, err).to_string()),
+        };
+        false
+    }
+
+    fn draw(&self) -> io::Result<()> {
+        let (width, height) = Terminal::size();
+        let mut lines = Vec::new();
+
+        let mut tabs = String::new();
+        for (index, (server_id, state)) in self.server_ids.iter().zip(&self.states).enumerate() {
+            let summary = match state {
+                Ok(state) => format!(" {} {server_id} {} ", index + 1, state.time),
+                Err(_) => format!(" {} {server_id} - ", index + 1),
+            };
+            match index == self.selected {
+                true => write!(tabs, "\x1b[7m{summary}\x1b[0m "),
+                false => write!(tabs, "{summary} "),
+            }.expect("Writing to a string");
+        }
+        lines.push(tabs);
+        lines.push(String::new());
+
+        match &self.states[self.selected] {
+            Ok(state) => {
+                match big_text(&state.time) {
+                    Some(rows) => lines.extend(rows.iter().map(|row| centered(row, width))),
+                    None => lines.push(centered(&state.time, width)),
+                }
+                lines.push(String::new());
+                let mut status = format!("{} · {}", state.session_name, self.clock_state(state));
+                if let Some(overtime) = &state.overtime {
+                    status += &format!(" · +{overtime}");
+                }
+                lines.push(centered(&status, width));
+                let gauge = gauge(state.percent, width.saturating_sub(8).min(MAX_GAUGE_WIDTH));
+                lines.push(centered(&format!("{gauge} {:>3}%", state.percent), width));
+                lines.push(String::new());
+                let mut done = format!("{} done", state.pomodoros_done);
+                if let Some(goal_total) = state.goal_total {
+                    done += &format!(", {}/{goal_total} today", state.goal_done);
+                }
+                lines.push(format!(" {done}"));
+                if !state.upcoming_sessions.is_empty() {
+                    lines.push(" Up next".into());
+                    for (index, session) in state.upcoming_sessions.iter().enumerate() {
+                        lines.push(format!("   {}. {session}", index + 1));
+                    }
+                }
+            },
+            Err(err) => lines.push(centered(&err.to_string(), width)),
+        }
+
+        let footer = " t toggle  s skip  r reset  tab/1-9 server  q quit";
+        let message = self.message.as_deref().map(|message| format!(" {message}"));
+        let bottom: Vec<String> = message.into_iter().chain([footer.to_string()]).collect();
+        lines.truncate(height.saturating_sub(bottom.len()));
+        lines.resize(height.saturating_sub(bottom.len()), String::new());
+        lines.extend(bottom);
+
+        // every line is cleared to its end rather than the whole screen first,
+        // which would flicker
+        let mut screen = String::from("\x1b[H");
+        for (index, line) in lines.iter().enumerate() {
+            let end = if index + 1 < lines.len() { "\r\n" } else { "" };
+            // wrapping would push every line below down
+            write!(screen, "{}\x1b[K{end}", fit(line, width)).expect("Writing to a string");
+        }
+        let mut stdout = io::stdout().lock();
+        stdout.write_all(screen.as_bytes())?;
+        stdout.flush()
+    }
+}
+
+/// Shows the servers full-screen until `q` is pressed, sending the requests
+/// bound to keys to the selected one
+pub fn run(server_ids: Vec<String>, config: &Config) -> io::Result<()> {
+    let terminal = Terminal::enter()?;
+    let mut dashboard = Dashboard { server_ids, states: Vec::new(), selected: 0, message: None, config };
+    loop {
+        dashboard.refresh();
+        dashboard.draw()?;
+        let deadline = Instant::now() + REFRESH_INTERVAL;
+        loop {
+            match terminal.key(deadline.saturating_duration_since(Instant::now()))? {
+                Some(key) => {
+                    if dashboard.press(key) {
+                        return Ok(());
+                    }
+                    dashboard.refresh();
+                    dashboard.draw()?;
+                },
+                None if terminal.was_resized() => dashboard.draw()?,
+                None => break,
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fit_lines() {
+        assert_eq!(fit("work 12:34", 4), "work");
+        assert_eq!(fit("rest", 10), "rest");
+        // escape codes take no room, and styles end where the line is cut
+        assert_eq!(fit("\x1b[7m 1 a \x1b[0m 2 b", 5), "\x1b[7m 1 a \x1b[0m\x1b[0m");
+        assert_eq!(big_text("1:0").unwrap()[0], "  █   ███");
+        assert!(big_text("12m").is_none());
+    }
+}